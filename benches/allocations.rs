@@ -0,0 +1,114 @@
+/// Allocation Benchmark
+///
+/// Counts heap allocations per log call with and without the message buffer pool, for short
+/// lines which are sent to the worker without a heap buffer and for lines too long for that.
+/// Every allocation made by the process is counted, including the ones made by the
+/// worker thread, so the numbers reflect the total allocator pressure of logging.
+///
+/// Usage:
+///   cargo bench --bench allocations > /dev/null
+///
+/// Results are printed to STDERR, STDOUT receives the log lines.
+use log::{Level, LevelFilter, Log, Record};
+use log_nonblock::{NonBlockingLogger, NonBlockingLoggerBuilder};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const MESSAGE_COUNT: usize = 100_000;
+
+// Messages are logged in bursts followed by a flush, which models a worker that keeps up
+// with the producers. Under a sustained overload every buffer is in flight and the pool
+// runs dry, so the pool only helps once the worker catches up.
+const BURST_SIZE: usize = 100;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn build_logger(pool_size: usize) -> NonBlockingLogger {
+    NonBlockingLoggerBuilder::new()
+        .with_level(LevelFilter::Info)
+        .without_timestamps()
+        .with_colors(false)
+        .with_buffer_pool_size(pool_size)
+        .build()
+        .expect("Failed to build log_nonblock")
+}
+
+// Makes the line longer than the inline capacity of 128 bytes
+const LONG_PADDING: &str = "lorem ipsum dolor sit amet consectetur adipiscing elit sed do eiusmod tempor incididunt ut labore et dolore magna aliqua";
+
+fn allocations_per_message(logger: &NonBlockingLogger, padding: &str) -> f64 {
+    // Warm up the pool, so only the steady state is measured
+    for i in 0..1000 {
+        logger.log(
+            &Record::builder()
+                .level(Level::Info)
+                .target("allocations")
+                .args(format_args!("warmup message {}{}", i, padding))
+                .build(),
+        );
+    }
+    logger.flush();
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+
+    for burst in 0..MESSAGE_COUNT / BURST_SIZE {
+        for i in 0..BURST_SIZE {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("allocations")
+                    .args(format_args!("message {} {}{}", burst, i, padding))
+                    .build(),
+            );
+        }
+        logger.flush();
+    }
+
+    let after = ALLOCATIONS.load(Ordering::Relaxed);
+
+    (after - before) as f64 / MESSAGE_COUNT as f64
+}
+
+fn main() {
+    eprintln!("Allocations per message ({} messages):", MESSAGE_COUNT);
+
+    for (name, padding) in [("short", ""), ("long", LONG_PADDING)] {
+        let without_pool = build_logger(0);
+        let without_pool_allocations = allocations_per_message(&without_pool, padding);
+        without_pool.shutdown().expect("Failed to shutdown logger");
+
+        let with_pool = build_logger(log_nonblock::DEFAULT_BUFFER_POOL_SIZE);
+        let with_pool_allocations = allocations_per_message(&with_pool, padding);
+        with_pool.shutdown().expect("Failed to shutdown logger");
+
+        eprintln!(
+            "  {} lines, without buffer pool: {:.2}",
+            name, without_pool_allocations
+        );
+        eprintln!(
+            "  {} lines, with buffer pool:    {:.2}",
+            name, with_pool_allocations
+        );
+    }
+}
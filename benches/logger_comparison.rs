@@ -1,8 +1,9 @@
 use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
-use log::LevelFilter;
+use log::{Level, LevelFilter, Log, Record};
 use log_nonblock::NonBlockingLoggerBuilder;
 use std::sync::Once;
 use std::thread;
+use std::time::{Duration, Instant};
 
 static INIT_LOG_NONBLOCK: Once = Once::new();
 static INIT_SIMPLE_LOGGER: Once = Once::new();
@@ -36,7 +37,7 @@ fn bench_log_nonblock_single_thread(c: &mut Criterion) {
     // Initialize logger once for all benchmarks in this group
     init_log_nonblock();
 
-    let message_sizes = vec![
+    let message_sizes = [
         ("small_100B", "a".repeat(100)),
         ("medium_1KB", "a".repeat(1024)),
         ("large_100KB", "a".repeat(102400)),
@@ -61,7 +62,7 @@ fn bench_simple_logger_single_thread(c: &mut Criterion) {
     // Initialize logger once for all benchmarks in this group
     init_simple_logger();
 
-    let message_sizes = vec![
+    let message_sizes = [
         ("small_100B", "a".repeat(100)),
         ("medium_1KB", "a".repeat(1024)),
         ("large_100KB", "a".repeat(102400)),
@@ -85,7 +86,7 @@ fn bench_simple_logger_single_thread(c: &mut Criterion) {
 fn bench_log_nonblock_multi_thread(c: &mut Criterion) {
     init_log_nonblock();
 
-    let thread_counts = vec![2, 4, 8];
+    let thread_counts = [2, 4, 8];
 
     let mut group = c.benchmark_group("log_nonblock/multi_thread");
 
@@ -122,7 +123,7 @@ fn bench_log_nonblock_multi_thread(c: &mut Criterion) {
 fn bench_simple_logger_multi_thread(c: &mut Criterion) {
     init_simple_logger();
 
-    let thread_counts = vec![2, 4, 8];
+    let thread_counts = [2, 4, 8];
 
     let mut group = c.benchmark_group("simple_logger/multi_thread");
 
@@ -291,13 +292,57 @@ fn bench_simple_logger_overhead(c: &mut Criterion) {
     group.finish();
 }
 
+// Benchmark caller side cost of inline vs deferred formatting - log_nonblock
+fn bench_log_nonblock_deferred_formatting(c: &mut Criterion) {
+    let mut group = c.benchmark_group("log_nonblock/formatting");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, deferred) in [("inline", false), ("deferred", true)] {
+        // Timestamps are kept on, formatting them is a big part of the work that moves
+        let logger = NonBlockingLoggerBuilder::new()
+            .with_level(LevelFilter::Info)
+            .with_deferred_formatting(deferred)
+            .build()
+            .expect("Failed to build log_nonblock");
+
+        group.bench_function(name, |b| {
+            // Only the time spent on the calling thread is measured, the worker is drained
+            // outside of the measurement so the channel never fills up
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+
+                for chunk_start in (0..iters).step_by(1000) {
+                    let start = Instant::now();
+                    for i in chunk_start..iters.min(chunk_start + 1000) {
+                        logger.log(
+                            &Record::builder()
+                                .level(Level::Info)
+                                .target("formatting")
+                                .args(format_args!("Message {}", black_box(i)))
+                                .build(),
+                        );
+                    }
+                    elapsed += start.elapsed();
+
+                    logger.flush();
+                }
+
+                elapsed
+            });
+        });
+    }
+
+    group.finish();
+}
+
 criterion_group!(
     log_nonblock_benches,
     bench_log_nonblock_single_thread,
     bench_log_nonblock_multi_thread,
     bench_log_nonblock_mixed_levels,
     bench_log_nonblock_large_messages,
-    bench_log_nonblock_overhead
+    bench_log_nonblock_overhead,
+    bench_log_nonblock_deferred_formatting
 );
 
 criterion_group!(
@@ -0,0 +1,104 @@
+/// Stage Timing Benchmark
+///
+/// Logs from several threads at once and reports the time spent per stage, measured with the
+/// `bench-hooks` feature: formatting and sending a record on the logging threads (enqueue),
+/// and writing batches on the worker thread (write), including how many lines the worker
+/// batched per write under contention.
+///
+/// Usage:
+///   cargo bench --bench stages --features bench-hooks > /dev/null
+///
+/// Results are printed to STDERR, STDOUT receives the log lines.
+use log::{Level, LevelFilter, Log, Record};
+use log_nonblock::{FullPolicy, NonBlockingLoggerBuilder, Stage};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+const MESSAGE_COUNT: u64 = 200_000;
+
+#[derive(Default)]
+struct Timings {
+    enqueue_nanos: AtomicU64,
+    enqueued: AtomicU64,
+    write_nanos: AtomicU64,
+    batches: AtomicU64,
+    written: AtomicU64,
+}
+
+fn run(threads: u64) -> Arc<Timings> {
+    let timings = Arc::new(Timings::default());
+    let hook_timings = timings.clone();
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_level(LevelFilter::Info)
+        .without_timestamps()
+        .with_colors(false)
+        .with_full_policy(FullPolicy::Block)
+        .with_stage_hook(move |stage, elapsed| {
+            let nanos = elapsed.as_nanos() as u64;
+
+            match stage {
+                Stage::Enqueue => {
+                    hook_timings
+                        .enqueue_nanos
+                        .fetch_add(nanos, Ordering::Relaxed);
+                    hook_timings.enqueued.fetch_add(1, Ordering::Relaxed);
+                }
+                Stage::Write { lines } => {
+                    hook_timings.write_nanos.fetch_add(nanos, Ordering::Relaxed);
+                    hook_timings.batches.fetch_add(1, Ordering::Relaxed);
+                    hook_timings
+                        .written
+                        .fetch_add(lines as u64, Ordering::Relaxed);
+                }
+            }
+        })
+        .build()
+        .expect("Failed to build log_nonblock");
+
+    thread::scope(|scope| {
+        for t in 0..threads {
+            let logger = &logger;
+
+            scope.spawn(move || {
+                for i in 0..MESSAGE_COUNT / threads {
+                    logger.log(
+                        &Record::builder()
+                            .level(Level::Info)
+                            .target("stages")
+                            .args(format_args!("thread {} message {}", t, i))
+                            .build(),
+                    );
+                }
+            });
+        }
+    });
+
+    logger.shutdown().expect("Failed to shutdown logger");
+
+    timings
+}
+
+fn main() {
+    eprintln!("Stage timings ({} messages):", MESSAGE_COUNT);
+
+    for threads in [1, 4, 8] {
+        let timings = run(threads);
+
+        let enqueued = timings.enqueued.load(Ordering::Relaxed);
+        let batches = timings.batches.load(Ordering::Relaxed);
+        let written = timings.written.load(Ordering::Relaxed);
+
+        eprintln!("  {} threads:", threads);
+        eprintln!(
+            "    enqueue: {:.0} ns per message",
+            timings.enqueue_nanos.load(Ordering::Relaxed) as f64 / enqueued as f64
+        );
+        eprintln!(
+            "    write:   {:.0} ns per line, {:.2} lines per batch",
+            timings.write_nanos.load(Ordering::Relaxed) as f64 / written as f64,
+            written as f64 / batches as f64
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use std::io::{IoSlice, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+// Opens a local TCP connection whose other end is drained by a thread, so writes behave
+// like writes to a pipe with a fast consumer.
+fn drained_stream() -> TcpStream {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind listener");
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).expect("Failed to connect");
+    let (mut reader, _) = listener.accept().expect("Failed to accept connection");
+
+    thread::spawn(move || {
+        let mut buf = vec![0u8; 64 * 1024];
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    });
+
+    stream
+}
+
+// Compares the two ways the worker can write a pair of coalesced messages:
+// copying both into one buffer and writing it, or a single vectored write over both.
+// The first message of a pair is always small, the second one can be of any size.
+fn bench_pair_writes(c: &mut Criterion) {
+    let mut out = drained_stream();
+
+    let second_sizes = [
+        ("100B", 100),
+        ("1KB", 1024),
+        ("4KB", 4 * 1024),
+        ("16KB", 16 * 1024),
+        ("64KB", 64 * 1024),
+    ];
+
+    let first = "a".repeat(100);
+    let mut pipe_buffer = Vec::with_capacity(2 * 1024);
+
+    let mut group = c.benchmark_group("log_nonblock/pair_writes");
+
+    for (size_name, size) in second_sizes.iter() {
+        let second = "b".repeat(*size);
+
+        group.throughput(Throughput::Elements(2));
+
+        group.bench_with_input(BenchmarkId::new("copy", size_name), size, |b, _| {
+            b.iter(|| {
+                pipe_buffer.extend_from_slice(black_box(first.as_bytes()));
+                pipe_buffer.extend_from_slice(black_box(second.as_bytes()));
+                out.write_all(&pipe_buffer).unwrap();
+                pipe_buffer.clear();
+            });
+        });
+
+        group.bench_with_input(BenchmarkId::new("vectored", size_name), size, |b, _| {
+            b.iter(|| {
+                let mut bufs = [
+                    IoSlice::new(black_box(first.as_bytes())),
+                    IoSlice::new(black_box(second.as_bytes())),
+                ];
+                let mut bufs = &mut bufs[..];
+
+                while !bufs.is_empty() {
+                    let n = out.write_vectored(bufs).unwrap();
+                    IoSlice::advance_slices(&mut bufs, n);
+                }
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_pair_writes);
+criterion_main!(benches);
@@ -0,0 +1,34 @@
+/// Capture Test
+///
+/// Shows how to assert on logged lines in tests: the logger writes formatted lines into a
+/// `CaptureSink` instead of STDOUT.
+///
+/// Usage:
+///   cargo run --example capture
+use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+
+fn main() {
+    let capture = CaptureSink::new();
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_capture(&capture)
+        .init()
+        .expect("Failed to initialize log_nonblock");
+
+    log::info!("User {} logged in", 42);
+
+    log::logger().flush();
+
+    let lines = capture.lines();
+    assert_eq!(lines.len(), 1, "Unexpected lines: {:?}", lines);
+    assert!(lines[0].contains("INFO"), "Unexpected line: {}", lines[0]);
+    assert!(
+        lines[0].ends_with("[capture] User 42 logged in"),
+        "Unexpected line: {}",
+        lines[0]
+    );
+
+    logger.shutdown().expect("Failed to shutdown logger");
+
+    println!("Captured: {}", lines[0]);
+}
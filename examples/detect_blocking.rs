@@ -0,0 +1,68 @@
+/// Blocking Detection Test
+///
+/// Starts itself as a child process which logs through a slow sink, with a channel of a single
+/// message and `FullPolicy::Block`, so most calls to `log()` wait for the worker. With
+/// `with_detect_blocking` the first slow call is reported once on STDERR, without it nothing is.
+///
+/// Usage:
+///   cargo run --example detect_blocking
+use log::{Log, info};
+use log_nonblock::{FullPolicy, NonBlockingLoggerBuilder, Sink};
+use std::process::Command;
+use std::time::Duration;
+
+struct SlowSink;
+
+impl Sink for SlowSink {
+    fn write_all(&mut self, _buf: &[u8]) -> std::io::Result<()> {
+        std::thread::sleep(Duration::from_millis(10));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn child(detect: bool) {
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_channel_size(1)
+        .with_full_policy(FullPolicy::Block)
+        .add_sink(SlowSink)
+        .with_detect_blocking(detect)
+        .init()
+        .expect("Failed to initialize log_nonblock");
+
+    for i in 0..10 {
+        info!("Line {}", i);
+    }
+
+    logger.flush();
+}
+
+fn run_child(arg: &str) -> String {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .arg(arg)
+        .output()
+        .expect("Failed to run the child process");
+    assert!(output.status.success());
+
+    String::from_utf8(output.stderr).unwrap()
+}
+
+fn main() {
+    match std::env::args().nth(1).as_deref() {
+        Some("detect") => return child(true),
+        Some("ignore") => return child(false),
+        _ => {}
+    }
+
+    let stderr = run_child("detect");
+    assert_eq!(stderr.matches("blocked for").count(), 1, "{}", stderr);
+    assert!(stderr.contains("full policy Block"), "{}", stderr);
+
+    let stderr = run_child("ignore");
+    assert!(!stderr.contains("blocked for"), "{}", stderr);
+
+    println!("The slow call to log() was reported once");
+}
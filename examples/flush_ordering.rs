@@ -0,0 +1,144 @@
+/// Flush Ordering Stress Test
+///
+/// Several threads log a mix of small lines and lines above the large message threshold, and
+/// flush every few lines. When `flush()` returns, every line the thread logged before it must
+/// have been written, in order. A sink counts the lines of each thread as they are written and
+/// each thread checks the count after its flushes, for several worker configurations. The sink
+/// is slowed down, so the worker falls behind and finds flushes in the middle of a batch.
+///
+/// Usage:
+///   cargo run --example flush_ordering
+use log::{Level, Log, Record};
+use log_nonblock::{CaptureSink, FullPolicy, NonBlockingLoggerBuilder, Sink};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const THREADS: usize = 4;
+const LINES: usize = 2000;
+const FLUSH_EVERY: usize = 37;
+
+/// Lines written so far for each thread, and the first line written out of order
+#[derive(Default)]
+struct Written {
+    counts: [usize; THREADS],
+    out_of_order: Option<String>,
+}
+
+#[derive(Clone, Default)]
+struct CountingSink(Arc<Mutex<Written>>);
+
+impl Sink for CountingSink {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        // Slow enough for lines and flushes of other threads to queue up behind the write
+        std::thread::sleep(Duration::from_micros(20));
+
+        let mut written = self.0.lock().unwrap();
+        let text = String::from_utf8_lossy(buf);
+
+        for line in text.split_terminator("\r\n") {
+            // "INFO  thread 1 line 42 xxx..."
+            let mut words = line.split(' ').skip_while(|word| *word != "thread").skip(1);
+            let thread: usize = words.next().unwrap().parse().unwrap();
+            let index: usize = words.nth(1).unwrap().parse().unwrap();
+
+            if index != written.counts[thread] && written.out_of_order.is_none() {
+                written.out_of_order = Some(format!(
+                    "thread {} wrote line {} after {} lines",
+                    thread, index, written.counts[thread]
+                ));
+            }
+
+            written.counts[thread] += 1;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn stress(name: &'static str, builder: NonBlockingLoggerBuilder) {
+    let sink = CountingSink::default();
+    let logger = builder
+        .without_timestamps()
+        .with_colors(false)
+        .with_full_policy(FullPolicy::Block)
+        .with_capture(&CaptureSink::new())
+        .add_sink(sink.clone())
+        .build()
+        .expect("Failed to build log_nonblock");
+
+    let threads: Vec<_> = (0..THREADS)
+        .map(|thread| {
+            let logger = logger.clone();
+            let written = sink.0.clone();
+
+            std::thread::spawn(move || {
+                for i in 0..LINES {
+                    // Every third line is larger than the large message threshold
+                    let padding = if i % 3 == 0 { 2000 } else { i % 50 };
+
+                    logger.log(
+                        &Record::builder()
+                            .args(format_args!(
+                                "thread {} line {} {}",
+                                thread,
+                                i,
+                                "x".repeat(padding)
+                            ))
+                            .level(Level::Info)
+                            .build(),
+                    );
+
+                    if i % FLUSH_EVERY == 0 || i == LINES - 1 {
+                        logger.flush();
+
+                        let count = written.lock().unwrap().counts[thread];
+                        assert_eq!(
+                            count,
+                            i + 1,
+                            "{}: flush returned with {} of {} lines of thread {} written",
+                            name,
+                            count,
+                            i + 1,
+                            thread
+                        );
+                    }
+                }
+            })
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let written = sink.0.lock().unwrap();
+    assert_eq!(written.out_of_order, None, "{}", name);
+    assert_eq!(written.counts, [LINES; THREADS], "{}", name);
+
+    println!("{}: every flush covered the lines logged before it", name);
+}
+
+fn main() {
+    stress("default", NonBlockingLoggerBuilder::new());
+    stress(
+        "small channel",
+        NonBlockingLoggerBuilder::new().with_channel_size(4),
+    );
+    stress(
+        "max batch bytes",
+        NonBlockingLoggerBuilder::new().with_max_batch_bytes(64 * 1024),
+    );
+    stress(
+        "deferred formatting",
+        NonBlockingLoggerBuilder::new().with_deferred_formatting(true),
+    );
+    stress(
+        "thread-local batching",
+        NonBlockingLoggerBuilder::new().with_thread_local_batching(true),
+    );
+}
@@ -0,0 +1,41 @@
+/// Structured Key-Value Test
+///
+/// Logs records with key-value pairs and verifies how they are appended to the message.
+///
+/// Usage:
+///   cargo run --example kv --features kv
+use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+
+fn main() {
+    let capture = CaptureSink::new();
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_capture(&capture)
+        .init()
+        .expect("Failed to initialize log_nonblock");
+
+    log::info!("No fields");
+    log::info!(user_id = 42, active = true; "User logged in");
+    log::info!(name = "John \"JD\" Doe", empty = "", path = "/tmp"; "Quoted values");
+
+    log::logger().flush();
+
+    let lines = capture.lines();
+    let expected = [
+        "[kv] No fields",
+        "[kv] User logged in user_id=42 active=true",
+        r#"[kv] Quoted values name="John \"JD\" Doe" empty="" path=/tmp"#,
+    ];
+
+    assert_eq!(lines.len(), expected.len(), "Unexpected lines: {:?}", lines);
+
+    for (line, expected) in lines.iter().zip(expected) {
+        assert!(line.ends_with(expected), "Unexpected line: {}", line);
+    }
+
+    logger.shutdown().expect("Failed to shutdown logger");
+
+    for line in lines {
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,82 @@
+/// Log File Reopen Test
+///
+/// Logs to a file, renames it the way logrotate does and sends SIGHUP to the process. A thread
+/// waiting for SIGHUP calls `reopen`, so the lines logged afterwards go to a fresh file at the
+/// original path, and none are lost or split between the two files.
+///
+/// Usage:
+///   cargo run --example logrotate --features unix-signals
+use log::info;
+use log_nonblock::NonBlockingLoggerBuilder;
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use signal_hook::low_level;
+use std::time::{Duration, Instant};
+
+const LINES: usize = 1000;
+
+fn main() {
+    let path =
+        std::env::temp_dir().join(format!("log_nonblock_logrotate_{}.log", std::process::id()));
+    let rotated = path.with_extension("log.1");
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .without_timestamps()
+        .with_colors(false)
+        .with_file(&path)
+        .init()
+        .expect("Failed to initialize log_nonblock");
+
+    // The handler only wakes up this thread, which can wait for the worker
+    let mut signals = Signals::new([SIGHUP]).expect("Failed to register the SIGHUP handler");
+    let reopener = logger.clone();
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Err(err) = reopener.reopen() {
+                eprintln!("Failed to reopen the log file: {}", err);
+            }
+        }
+    });
+
+    for i in 0..LINES {
+        info!("Line {}", i);
+    }
+
+    // What logrotate does, while lines may still be waiting in the channel
+    std::fs::rename(&path, &rotated).unwrap();
+    low_level::raise(SIGHUP).unwrap();
+
+    let started = Instant::now();
+    while !path.exists() {
+        assert!(
+            started.elapsed() < Duration::from_secs(5),
+            "The log file wasn't reopened"
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    for i in LINES..LINES * 2 {
+        info!("Line {}", i);
+    }
+    logger.stop().unwrap();
+
+    let old = std::fs::read_to_string(&rotated).unwrap();
+    let new = std::fs::read_to_string(&path).unwrap();
+    let numbers: Vec<usize> = old
+        .lines()
+        .chain(new.lines())
+        .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+        .collect();
+
+    assert_eq!(numbers, (0..LINES * 2).collect::<Vec<_>>());
+    assert!(new.lines().count() >= LINES);
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&rotated).unwrap();
+
+    println!(
+        "{} lines before and {} after the reopen, none lost",
+        old.lines().count(),
+        new.lines().count()
+    );
+}
@@ -0,0 +1,66 @@
+/// Ordered Synchronous Lines Test
+///
+/// Starts itself as a child process which logs info lines through the worker, with an error
+/// written synchronously every few lines. With `with_ordered_sync` every error comes after the
+/// info lines logged before it, so the child's output is in the order the lines were logged.
+///
+/// Usage:
+///   cargo run --example ordered_sync
+use log::{Level, Log, error, info};
+use log_nonblock::{NonBlockingLoggerBuilder, Output};
+use std::process::Command;
+
+const LINES: usize = 2000;
+
+fn child() {
+    let logger = NonBlockingLoggerBuilder::new()
+        .without_timestamps()
+        .with_colors(false)
+        // Read back from the child's STDOUT, whatever the features
+        .with_output(Output::Stdout)
+        .with_channel_size(LINES)
+        .with_sync_level(Level::Error)
+        .with_ordered_sync(true)
+        .init()
+        .expect("Failed to initialize log_nonblock");
+
+    for i in 0..LINES {
+        if i % 100 == 99 {
+            error!("Line {}", i);
+        } else {
+            info!("Line {}", i);
+        }
+    }
+
+    logger.flush();
+}
+
+fn main() {
+    if std::env::args().nth(1).as_deref() == Some("child") {
+        return child();
+    }
+
+    let output = Command::new(std::env::current_exe().unwrap())
+        .arg("child")
+        .output()
+        .expect("Failed to run the child process");
+    assert!(output.status.success());
+
+    let output = String::from_utf8(output.stdout).unwrap();
+    let numbers: Vec<usize> = output
+        .lines()
+        .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+        .collect();
+
+    assert_eq!(numbers, (0..LINES).collect::<Vec<_>>());
+    assert!(
+        output
+            .lines()
+            .any(|line| line == "ERROR [ordered_sync] Line 99")
+    );
+
+    println!(
+        "All {} lines were written in the order they were logged",
+        LINES
+    );
+}
@@ -0,0 +1,69 @@
+/// Re-entrant Flush Test
+///
+/// Uses a sink which flushes the global logger from inside its own writes and its `Drop`, on the
+/// worker thread. Such a flush used to wait for the worker, which was busy calling it, and hang
+/// forever. A watchdog fails the test instead of hanging.
+///
+/// Usage:
+///   cargo run --example reentrant_flush
+use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder, Sink};
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+static FLUSHES: AtomicUsize = AtomicUsize::new(0);
+
+/// Calls `log::logger().flush()` on every write and when dropped
+struct FlushingSink;
+
+impl Sink for FlushingSink {
+    fn write_all(&mut self, _buf: &[u8]) -> io::Result<()> {
+        log::logger().flush();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        FLUSHES.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+impl Drop for FlushingSink {
+    fn drop(&mut self) {
+        log::logger().flush();
+    }
+}
+
+fn main() {
+    std::thread::spawn(|| {
+        std::thread::sleep(Duration::from_secs(10));
+        eprintln!("Deadlock: flush from the worker thread never returned");
+        std::process::exit(1);
+    });
+
+    let capture = CaptureSink::new();
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_capture(&capture)
+        .add_sink(FlushingSink)
+        .init()
+        .expect("Failed to initialize log_nonblock");
+
+    for i in 0..100 {
+        log::info!("Message {}", i);
+    }
+
+    log::logger().flush();
+
+    let lines = capture.lines();
+    assert_eq!(lines.len(), 100, "Unexpected lines: {:?}", lines);
+    assert!(
+        FLUSHES.load(Ordering::SeqCst) > 0,
+        "The sink was never flushed"
+    );
+
+    // Drops the sinks on the worker thread, which flushes once more
+    logger.shutdown().expect("Failed to shut down log_nonblock");
+
+    println!("Flushing from the worker thread doesn't deadlock");
+}
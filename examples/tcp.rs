@@ -0,0 +1,119 @@
+/// TCP Output Test
+///
+/// Spins up a local TcpListener acting as a log collector, sends a few log lines to it
+/// through the logger and verifies that all of them arrive. Then does the same with
+/// length-prefixed framing and a multi-line message, which has to arrive as one frame.
+///
+/// Usage:
+///   cargo run --example tcp
+use log::{Level, Log, Record};
+use log_nonblock::{Framing, NonBlockingLoggerBuilder};
+use std::io::{BufRead, BufReader, Read};
+use std::net::TcpListener;
+use std::thread;
+
+const MESSAGE_COUNT: usize = 100;
+
+fn main() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind collector");
+    let addr = listener
+        .local_addr()
+        .expect("Failed to get collector address");
+
+    let collector = thread::spawn(move || {
+        let (stream, _) = listener.accept().expect("Failed to accept connection");
+
+        BufReader::new(stream)
+            .lines()
+            .take(MESSAGE_COUNT)
+            .map(|line| line.expect("Failed to read line"))
+            .collect::<Vec<_>>()
+    });
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_tcp(addr)
+        .init()
+        .expect("Failed to initialize log_nonblock");
+
+    for i in 0..MESSAGE_COUNT {
+        log::info!("Message {}", i);
+    }
+
+    log::logger().flush();
+
+    let lines = collector.join().expect("Collector thread panicked");
+
+    for (i, line) in lines.iter().enumerate() {
+        assert!(
+            line.ends_with(&format!("Message {}", i)),
+            "Unexpected line: {}",
+            line
+        );
+    }
+
+    logger.shutdown().expect("Failed to shutdown logger");
+
+    println!("Received all {} lines over TCP", lines.len());
+
+    length_prefixed();
+}
+
+fn length_prefixed() {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind collector");
+    let addr = listener
+        .local_addr()
+        .expect("Failed to get collector address");
+
+    let collector = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().expect("Failed to accept connection");
+
+        (0..2)
+            .map(|_| {
+                let mut len = [0u8; 4];
+                stream.read_exact(&mut len).expect("Failed to read length");
+
+                let mut frame = vec![0u8; u32::from_be_bytes(len) as usize];
+                stream.read_exact(&mut frame).expect("Failed to read frame");
+
+                String::from_utf8(frame).expect("Frame is not UTF-8")
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_tcp(addr)
+        .with_framing(Framing::LengthPrefixed)
+        .build()
+        .expect("Failed to build log_nonblock");
+
+    for message in [
+        "panicked at src/main.rs:1:1\nstack backtrace:\n  0: main",
+        "Done",
+    ] {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Error)
+                .target("tcp")
+                .build(),
+        );
+    }
+    logger.flush();
+
+    let frames = collector.join().expect("Collector thread panicked");
+
+    assert!(
+        frames[0].ends_with("[tcp] panicked at src/main.rs:1:1\nstack backtrace:\n  0: main"),
+        "Unexpected frame: {:?}",
+        frames[0]
+    );
+    assert!(
+        frames[1].ends_with("[tcp] Done"),
+        "Unexpected frame: {:?}",
+        frames[1]
+    );
+
+    logger.shutdown().expect("Failed to shutdown logger");
+
+    println!("Received a multi-line message as a single length-prefixed frame");
+}
@@ -0,0 +1,98 @@
+/// Termination Handler Test
+///
+/// Starts itself as a child process which logs through a slow sink, so lines are still waiting
+/// in the channel, and sends it SIGTERM. With `install_term_handler` the child writes every
+/// line before it is terminated by the signal.
+///
+/// Usage:
+///   cargo run --example term_handler --features unix-signals
+use log::info;
+use log_nonblock::{NonBlockingLoggerBuilder, Sink};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+const LINES: usize = 200;
+
+/// The same on every Unix
+const SIGTERM: i32 = 15;
+
+/// Takes a millisecond per write, to keep the worker behind
+struct SlowSink;
+
+impl Sink for SlowSink {
+    fn write_all(&mut self, _buf: &[u8]) -> io::Result<()> {
+        std::thread::sleep(Duration::from_millis(1));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn child() {
+    let logger = NonBlockingLoggerBuilder::new()
+        .without_timestamps()
+        .with_colors(false)
+        .with_channel_size(LINES)
+        .add_sink(SlowSink)
+        .init()
+        .expect("Failed to initialize log_nonblock");
+    logger
+        .install_term_handler()
+        .expect("Failed to install the termination handler");
+
+    for i in 0..LINES {
+        info!("Line {}", i);
+    }
+
+    eprintln!("logged");
+    std::thread::sleep(Duration::from_secs(60));
+
+    eprintln!("The child wasn't terminated by SIGTERM");
+    std::process::exit(1);
+}
+
+fn main() {
+    if std::env::args().nth(1).as_deref() == Some("child") {
+        return child();
+    }
+
+    let mut child = Command::new(std::env::current_exe().unwrap())
+        .arg("child")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to start the child process");
+
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    stderr.read_line(&mut line).unwrap();
+    assert_eq!(line, "logged\n");
+
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()
+        .expect("Failed to run kill");
+    assert!(status.success());
+
+    let mut output = String::new();
+    child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    let status = child.wait().unwrap();
+
+    assert_eq!(status.signal(), Some(SIGTERM));
+    assert_eq!(output.lines().count(), LINES);
+    assert_eq!(output.lines().last(), Some("INFO  [term_handler] Line 199"));
+
+    println!(
+        "All {} lines were written before SIGTERM terminated the child",
+        LINES
+    );
+}
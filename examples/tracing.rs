@@ -0,0 +1,59 @@
+/// Tracing Layer Test
+///
+/// Emits `tracing` events inside spans and verifies how they are written by the logger.
+///
+/// Usage:
+///   cargo run --example tracing --features tracing
+use log_nonblock::{CaptureSink, NonBlockingLayer, NonBlockingLoggerBuilder};
+use tracing_subscriber::layer::SubscriberExt;
+
+fn main() {
+    let capture = CaptureSink::new();
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_level(log::LevelFilter::Info)
+        .with_capture(&capture)
+        .build()
+        .expect("Failed to build log_nonblock");
+
+    let subscriber = tracing_subscriber::registry().with(NonBlockingLayer::new(logger.clone()));
+    tracing::subscriber::set_global_default(subscriber)
+        .expect("Failed to set the global subscriber");
+
+    tracing::info!("No fields");
+    tracing::debug!("Filtered out");
+    tracing::warn!(user_id = 42, active = true, "User logged in");
+    tracing::info!(name = "John Doe", path = "/tmp");
+
+    {
+        let request = tracing::info_span!("request", id = 7);
+        let _request = request.enter();
+
+        let _handler = tracing::info_span!("handler").entered();
+        request.record("id", 8);
+
+        tracing::error!("Failed");
+    }
+
+    log::Log::flush(&logger);
+
+    let lines = capture.lines();
+    let expected = [
+        "[tracing] No fields",
+        "[tracing] User logged in user_id=42 active=true",
+        r#"[tracing] name="John Doe" path=/tmp"#,
+        "[tracing] request{id=7 id=8}:handler: Failed",
+    ];
+
+    assert_eq!(lines.len(), expected.len(), "Unexpected lines: {:?}", lines);
+
+    for (line, expected) in lines.iter().zip(expected) {
+        assert!(line.ends_with(expected), "Unexpected line: {}", line);
+    }
+
+    logger.shutdown().expect("Failed to shutdown logger");
+
+    for line in lines {
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,81 @@
+/// Message Truncation Test
+///
+/// Logs messages with multi-byte characters (accents, CJK, emoji) cut at every possible length,
+/// and verifies the truncated messages never split a character.
+///
+/// Usage:
+///   cargo run --example truncation
+use log::{Level, Log, Record};
+use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder, TRUNCATED_MARKER};
+
+const MESSAGES: [&str; 4] = ["héllo wörld", "日本語のログ", "😀🚀 launch 🎉", "a日😀é"];
+
+fn main() {
+    for deferred in [false, true] {
+        for message in MESSAGES {
+            for max_len in 0..=message.len() + 1 {
+                check(message, max_len, deferred);
+            }
+        }
+    }
+
+    println!("All truncated messages are valid UTF-8 cut on character boundaries");
+}
+
+fn check(message: &str, max_len: usize, deferred: bool) {
+    let capture = CaptureSink::new();
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_max_message_len(max_len)
+        .with_deferred_formatting(deferred)
+        .with_capture(&capture)
+        .build()
+        .expect("Failed to build log_nonblock");
+
+    // Split in two arguments, so the cut can also fall into a later write
+    let middle = message.char_indices().nth(1).map_or(0, |(i, _)| i);
+    let (head, tail) = message.split_at(middle);
+
+    logger.log(
+        &Record::builder()
+            .args(format_args!("{}{}", head, tail))
+            .level(Level::Info)
+            .target("truncation")
+            .build(),
+    );
+    logger.flush();
+
+    let lines = capture.lines();
+    let logged = lines[0]
+        .split_once("[truncation] ")
+        .map(|(_, logged)| logged)
+        .expect("Missing target");
+
+    // The capture replaces invalid UTF-8 with U+FFFD
+    assert!(!logged.contains('\u{FFFD}'), "Invalid UTF-8: {}", logged);
+
+    match logged.strip_suffix(TRUNCATED_MARKER) {
+        Some(kept) => {
+            assert!(
+                message.len() > max_len,
+                "Truncated a short message: {}",
+                logged
+            );
+            assert!(kept.len() <= max_len, "Kept too much: {}", logged);
+            assert!(message.starts_with(kept), "Not a prefix: {}", logged);
+
+            // Nothing more would have fit without splitting the next character
+            let next = message[kept.len()..].chars().next().unwrap();
+            assert!(
+                kept.len() + next.len_utf8() > max_len,
+                "Cut too early: {}",
+                logged
+            );
+        }
+        None => {
+            assert!(message.len() <= max_len, "Not truncated: {}", logged);
+            assert_eq!(logged, message);
+        }
+    }
+
+    logger.shutdown().expect("Failed to shutdown logger");
+}
@@ -0,0 +1,49 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+
+// The notifier is pinged once the channel is as full as when adaptive sampling starts
+use crate::sampling::HIGH_WATER_PERCENT;
+
+/// After a ping, the next one is only sent once the channel went back to this percentage
+const LOW_WATER_PERCENT: usize = 50;
+
+/// Pings the application when the channel is close to full,
+/// see [`NonBlockingLoggerBuilder::with_backpressure_notify`](crate::NonBlockingLoggerBuilder::with_backpressure_notify)
+#[derive(Debug)]
+pub(crate) struct Backpressure {
+    notify: Sender<()>,
+    /// Set by a ping, cleared once the channel drained to the low-water mark
+    notified: AtomicBool,
+}
+
+impl Backpressure {
+    pub fn new(notify: Sender<()>) -> Self {
+        Self {
+            notify,
+            notified: AtomicBool::new(false),
+        }
+    }
+
+    /// Pings the notifier if `len` messages waiting in a channel of `capacity` crossed the
+    /// high-water mark since the last ping
+    pub fn check(&self, len: usize, capacity: Option<usize>) {
+        // Unbounded channels never fill up
+        let Some(capacity) = capacity else {
+            return;
+        };
+
+        // At least one message, and the low-water mark below it, so small channels re-arm too
+        let high_water = (capacity * HIGH_WATER_PERCENT / 100).max(1);
+        let low_water = (capacity * LOW_WATER_PERCENT / 100).min(high_water - 1);
+
+        if len >= high_water {
+            // Only the thread which sets the flag pings, the others are coalesced with it
+            if !self.notified.swap(true, Ordering::Relaxed) {
+                // Fails once the receiver is gone, there is nobody left to throttle
+                let _ = self.notify.send(());
+            }
+        } else if len <= low_water {
+            self.notified.store(false, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+use crate::NonBlockingOptions;
+
+/// Source of the timestamps of log lines
+pub(crate) trait Clock {
+    fn now(&self) -> OffsetDateTime;
+}
+
+/// The real clock, used unless [`NonBlockingLoggerBuilder::with_clock`](crate::NonBlockingLoggerBuilder::with_clock) is called
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+}
+
+/// Closure set with [`NonBlockingLoggerBuilder::with_clock`](crate::NonBlockingLoggerBuilder::with_clock)
+#[derive(Clone)]
+pub(crate) struct CustomClock(pub Arc<dyn Fn() -> OffsetDateTime + Send + Sync>);
+
+impl Clock for CustomClock {
+    fn now(&self) -> OffsetDateTime {
+        (self.0)()
+    }
+}
+
+impl std::fmt::Debug for CustomClock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CustomClock")
+    }
+}
+
+impl NonBlockingOptions {
+    /// Current time for the timestamp of a line
+    ///
+    /// The real clock is called directly, only a custom clock goes through a closure.
+    pub(crate) fn now(&self) -> OffsetDateTime {
+        match &self.clock {
+            Some(clock) => clock.now(),
+            None => SystemClock.now(),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use crate::{FullPolicy, NonBlockingLoggerBuilder, NonBlockingLoggerError, Output};
+
+/// Serializable subset of the builder options, to load the logger configuration from a file.
+///
+/// Every field is optional, fields which are missing or `None` keep the builder default.
+/// Levels are names like `"debug"`, as accepted by
+/// [`with_level_str`](NonBlockingLoggerBuilder::with_level_str). Fields for features which
+/// aren't enabled (`colors`, `timestamps`, `threads`) are ignored.
+///
+/// Options which hold closures, formats or sinks can't be serialized and are set on the builder
+/// returned by [`NonBlockingLoggerBuilder::from_config`] instead: filters, rate limits, level
+/// patterns, clocks, timestamp formats, level labels and colors, rotating files, syslog, sinks
+/// and captures.
+///
+/// ```
+/// use log_nonblock::NonBlockingConfig;
+///
+/// let config: NonBlockingConfig = serde_json::from_str(
+///     r#"{ "level": "info", "module_levels": { "hyper": "warn" }, "channel_size": 4096 }"#,
+/// )
+/// .unwrap();
+///
+/// let json = serde_json::to_string(&config).unwrap();
+/// assert_eq!(serde_json::from_str::<NonBlockingConfig>(&json).unwrap(), config);
+/// ```
+///
+/// This type is only available if the `serde` feature is enabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NonBlockingConfig {
+    /// Default level, see [`with_level`](NonBlockingLoggerBuilder::with_level)
+    pub level: Option<String>,
+    /// Levels by target, see [`with_module_level`](NonBlockingLoggerBuilder::with_module_level)
+    pub module_levels: BTreeMap<String, String>,
+    pub colors: Option<bool>,
+    /// UTC timestamps when `true`, no timestamps when `false`
+    pub timestamps: Option<bool>,
+    pub uptime_timestamps: Option<bool>,
+    pub target: Option<bool>,
+    pub threads: Option<bool>,
+    pub thread_ids: Option<bool>,
+    pub source_location: Option<bool>,
+    pub module_path: Option<bool>,
+    pub pid: Option<bool>,
+    pub hostname: Option<bool>,
+    pub tag: Option<String>,
+    pub dedup: Option<bool>,
+    pub adaptive_sampling: Option<bool>,
+    pub deferred_formatting: Option<bool>,
+    pub max_message_len: Option<usize>,
+    pub channel_size: Option<usize>,
+    pub unbounded_channel: Option<bool>,
+    pub full_policy: Option<FullPolicy>,
+    pub worker_thread_name: Option<String>,
+    /// `"stdout"` or `"stderr"`, see [`with_output`](NonBlockingLoggerBuilder::with_output)
+    pub output: Option<Output>,
+    /// Write to this file instead of STDOUT/STDERR, see [`with_file`](NonBlockingLoggerBuilder::with_file)
+    pub file: Option<PathBuf>,
+    /// Send to this log collector instead of STDOUT/STDERR, see [`with_tcp`](NonBlockingLoggerBuilder::with_tcp)
+    pub tcp: Option<SocketAddr>,
+    /// Fail to build when the output can't be opened, see [`with_validate_on_init`](NonBlockingLoggerBuilder::with_validate_on_init)
+    pub validate_on_init: Option<bool>,
+}
+
+fn invalid_level(level: &str) -> NonBlockingLoggerError {
+    NonBlockingLoggerError::Error {
+        reason: format!(
+            "Invalid level {:?} in config, expected one of off, error, warn, info, debug, trace",
+            level
+        ),
+    }
+}
+
+impl NonBlockingLoggerBuilder {
+    /// Creates a builder with the options of `config`, see [`NonBlockingConfig`].
+    ///
+    /// Fails if a level in the config isn't a valid level name.
+    ///
+    /// ```
+    /// use log::{Level, Log, Metadata};
+    /// use log_nonblock::{NonBlockingConfig, NonBlockingLoggerBuilder};
+    ///
+    /// let config: NonBlockingConfig =
+    ///     serde_json::from_str(r#"{ "level": "warn", "module_levels": { "app": "debug" } }"#).unwrap();
+    ///
+    /// let logger = NonBlockingLoggerBuilder::from_config(&config).unwrap().build().unwrap();
+    ///
+    /// assert!(logger.enabled(&Metadata::builder().level(Level::Debug).target("app").build()));
+    /// assert!(!logger.enabled(&Metadata::builder().level(Level::Info).target("other").build()));
+    ///
+    /// let config: NonBlockingConfig = serde_json::from_str(r#"{ "level": "loud" }"#).unwrap();
+    /// assert!(NonBlockingLoggerBuilder::from_config(&config).is_err());
+    /// ```
+    ///
+    /// This method is only available if the `serde` feature is enabled.
+    pub fn from_config(config: &NonBlockingConfig) -> Result<Self, NonBlockingLoggerError> {
+        let mut builder = Self::new();
+
+        if let Some(level) = &config.level {
+            builder = builder
+                .with_level_str(level)
+                .map_err(|_| invalid_level(level))?;
+        }
+
+        for (target, level) in &config.module_levels {
+            builder = builder
+                .with_module_level_str(target, level)
+                .map_err(|_| invalid_level(level))?;
+        }
+
+        #[cfg(feature = "colors")]
+        if let Some(colors) = config.colors {
+            builder = builder.with_colors(colors);
+        }
+
+        #[cfg(feature = "timestamps")]
+        match config.timestamps {
+            Some(true) => builder = builder.with_utc_timestamps(),
+            Some(false) => builder = builder.without_timestamps(),
+            None => {}
+        }
+
+        if config.uptime_timestamps == Some(true) {
+            builder = builder.with_uptime_timestamps();
+        }
+
+        if let Some(target) = config.target {
+            builder = builder.with_target(target);
+        }
+
+        #[cfg(feature = "threads")]
+        if let Some(threads) = config.threads {
+            builder = builder.with_threads(threads);
+        }
+
+        if let Some(thread_ids) = config.thread_ids {
+            builder = builder.with_thread_ids(thread_ids);
+        }
+
+        if let Some(source_location) = config.source_location {
+            builder = builder.with_source_location(source_location);
+        }
+
+        if let Some(module_path) = config.module_path {
+            builder = builder.with_module_path(module_path);
+        }
+
+        if let Some(pid) = config.pid {
+            builder = builder.with_pid(pid);
+        }
+
+        if let Some(hostname) = config.hostname {
+            builder = builder.with_hostname(hostname);
+        }
+
+        if let Some(tag) = &config.tag {
+            builder = builder.with_tag(tag);
+        }
+
+        if let Some(dedup) = config.dedup {
+            builder = builder.with_dedup(dedup);
+        }
+
+        if let Some(sampling) = config.adaptive_sampling {
+            builder = builder.with_adaptive_sampling(sampling);
+        }
+
+        if let Some(deferred) = config.deferred_formatting {
+            builder = builder.with_deferred_formatting(deferred);
+        }
+
+        if let Some(max_len) = config.max_message_len {
+            builder = builder.with_max_message_len(max_len);
+        }
+
+        if let Some(size) = config.channel_size {
+            builder = builder.with_channel_size(size);
+        }
+
+        if config.unbounded_channel == Some(true) {
+            builder = builder.with_unbounded_channel();
+        }
+
+        if let Some(policy) = config.full_policy {
+            builder = builder.with_full_policy(policy);
+        }
+
+        if let Some(name) = &config.worker_thread_name {
+            builder = builder.with_worker_thread_name(name);
+        }
+
+        if let Some(output) = config.output {
+            builder = builder.with_output(output);
+        }
+
+        if let Some(path) = &config.file {
+            builder = builder.with_file(path);
+        }
+
+        if let Some(addr) = config.tcp {
+            builder = builder.with_tcp(addr);
+        }
+
+        if let Some(validate) = config.validate_on_init {
+            builder = builder.with_validate_on_init(validate);
+        }
+
+        Ok(builder)
+    }
+}
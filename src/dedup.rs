@@ -0,0 +1,110 @@
+use log::{Level, Record};
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use crate::format::Message;
+
+/// How long repeats are collected before the summary is written, even if the message keeps repeating
+pub(crate) const REPEAT_SUMMARY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks the last message, see [`NonBlockingLoggerBuilder::with_dedup`](crate::NonBlockingLoggerBuilder::with_dedup)
+#[derive(Debug, Default)]
+pub(crate) struct Dedup {
+    last: Option<LastMessage>,
+}
+
+#[derive(Debug)]
+struct LastMessage {
+    /// Level, target and message, without the timestamp
+    key: String,
+    level: Level,
+    target: String,
+    repeated: u64,
+    since: Instant,
+}
+
+/// Summary of suppressed repeats, written as `last message repeated N times`
+pub(crate) struct Repeated {
+    pub level: Level,
+    pub target: String,
+    pub count: u64,
+}
+
+impl LastMessage {
+    fn take_repeated(&mut self) -> Option<Repeated> {
+        if self.repeated == 0 {
+            return None;
+        }
+
+        Some(Repeated {
+            level: self.level,
+            target: self.target.clone(),
+            count: std::mem::take(&mut self.repeated),
+        })
+    }
+}
+
+impl Dedup {
+    /// Checks the record against the previous message.
+    ///
+    /// Returns whether the record is a repeat which must be suppressed, and the summary of
+    /// the previous repeats to write first, if there is one.
+    pub fn check(&mut self, record: &Record) -> (bool, Option<Repeated>) {
+        let mut key = String::new();
+
+        // Writing into a String can't fail
+        let _ = write!(
+            key,
+            "{} [{}] {}",
+            record.level(),
+            record.target(),
+            Message::new(record)
+        );
+
+        if let Some(last) = &mut self.last
+            && last.key == key
+        {
+            if last.since.elapsed() < REPEAT_SUMMARY_INTERVAL {
+                last.repeated += 1;
+
+                return (true, None);
+            }
+
+            last.since = Instant::now();
+
+            return (false, last.take_repeated());
+        }
+
+        let repeated = self.take_repeated();
+
+        self.last = Some(LastMessage {
+            key,
+            level: record.level(),
+            target: record.target().to_string(),
+            repeated: 0,
+            since: Instant::now(),
+        });
+
+        (false, repeated)
+    }
+
+    /// Takes the summary of pending repeats, if any
+    pub fn take_repeated(&mut self) -> Option<Repeated> {
+        self.last.as_mut().and_then(LastMessage::take_repeated)
+    }
+
+    /// Takes the summary of repeats collected for at least [`REPEAT_SUMMARY_INTERVAL`], if any,
+    /// so they are reported even if nothing else is logged
+    pub fn take_due_repeated(&mut self) -> Option<Repeated> {
+        let last = self.last.as_mut()?;
+
+        if last.since.elapsed() < REPEAT_SUMMARY_INTERVAL {
+            return None;
+        }
+
+        let repeated = last.take_repeated()?;
+        last.since = Instant::now();
+
+        Some(repeated)
+    }
+}
@@ -0,0 +1,241 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "compression")]
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Where and how the worker writes the log file, see [`NonBlockingLoggerBuilder::with_file`](crate::NonBlockingLoggerBuilder::with_file)
+#[derive(Clone, Debug)]
+pub(crate) struct FileOptions {
+    pub path: PathBuf,
+    /// Rotate once the file would grow past this size, `None` disables rotation
+    pub max_bytes: Option<u64>,
+    /// How many rotated files (`app.log.1` .. `app.log.N`) are kept
+    pub max_files: usize,
+    /// Gzip rotated files into `app.log.1.gz` .. `app.log.N.gz`
+    #[cfg(feature = "compression")]
+    pub compress: bool,
+}
+
+/// Appends `suffix` to the file name of `path`, `app.log` -> `app.log.1`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.to_path_buf().into_os_string();
+    path.push(suffix);
+    path.into()
+}
+
+/// Log file owned by the worker thread, which rotates itself once it reaches the size limit.
+pub(crate) struct RotatingFile {
+    options: FileOptions,
+    file: File,
+    /// Bytes in the current file, including the ones written before it was opened
+    written: u64,
+    #[cfg(feature = "compression")]
+    compressor: Option<Compressor>,
+}
+
+impl RotatingFile {
+    pub fn open(options: FileOptions) -> io::Result<Self> {
+        let file = Self::open_file(&options.path)?;
+        let written = file.metadata()?.len();
+
+        Ok(Self {
+            #[cfg(feature = "compression")]
+            compressor: options
+                .compress
+                .then(|| Compressor::new(options.path.clone(), options.max_files)),
+            options,
+            file,
+            written,
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Path of the `index`-th rotated file, `app.log` -> `app.log.1`
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        with_suffix(&self.options.path, &format!(".{}", index))
+    }
+
+    /// Shifts `app.log.N-1` -> `app.log.N` .. `app.log` -> `app.log.1`, dropping the oldest
+    /// file, and starts a fresh `app.log`.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.options.max_files == 0 {
+            fs::remove_file(&self.options.path)?;
+        } else if !self.compress_rotated()? {
+            for index in (1..self.options.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+
+            fs::rename(&self.options.path, self.rotated_path(1))?;
+        }
+
+        self.file = Self::open_file(&self.options.path)?;
+        self.written = 0;
+
+        Ok(())
+    }
+
+    /// Hands the full file over to the compressor, returns `false` if compression is disabled
+    #[cfg(feature = "compression")]
+    fn compress_rotated(&mut self) -> io::Result<bool> {
+        match &mut self.compressor {
+            Some(compressor) => compressor.rotate().map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    #[cfg(not(feature = "compression"))]
+    fn compress_rotated(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+
+    pub fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if let Some(max_bytes) = self.options.max_bytes {
+            // A buffer larger than the limit is still written whole, but into a fresh file
+            if self.written > 0 && self.written + buf.len() as u64 > max_bytes {
+                self.rotate()?;
+            }
+        }
+
+        self.file.write_all(buf)?;
+        self.written += buf.len() as u64;
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Opens the file at the configured path again, e.g. after logrotate renamed it
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file = Self::open_file(&self.options.path)?;
+        self.written = self.file.metadata()?.len();
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "compression")]
+#[derive(Default)]
+struct CompressorQueue {
+    /// Renamed log files waiting to be compressed, oldest first
+    pending: VecDeque<PathBuf>,
+    /// Whether a helper thread is currently draining `pending`
+    running: bool,
+}
+
+/// Compresses rotated files on a short-lived helper thread, so the worker keeps writing.
+///
+/// The worker only renames the full file to a unique `app.log.rotating.N` name and queues it.
+/// The helper then shifts the archives (`app.log.1.gz` -> `app.log.2.gz`, ...) and compresses the
+/// queued file into `app.log.1.gz`. Jobs are processed strictly in order, so a rotation which
+/// happens while a previous compression is still running waits in the queue instead of racing it.
+#[cfg(feature = "compression")]
+struct Compressor {
+    path: PathBuf,
+    max_files: usize,
+    /// Sequence number to make the names of queued files unique
+    sequence: u64,
+    queue: Arc<Mutex<CompressorQueue>>,
+}
+
+#[cfg(feature = "compression")]
+impl Compressor {
+    fn new(path: PathBuf, max_files: usize) -> Self {
+        Self {
+            path,
+            max_files,
+            sequence: 0,
+            queue: Arc::default(),
+        }
+    }
+
+    /// Moves the current file out of the way and queues it for compression
+    fn rotate(&mut self) -> io::Result<()> {
+        self.sequence += 1;
+        let pending = with_suffix(&self.path, &format!(".rotating.{}", self.sequence));
+        fs::rename(&self.path, &pending)?;
+
+        let mut queue = self.queue.lock().unwrap_or_else(|err| err.into_inner());
+        queue.pending.push_back(pending);
+
+        if !queue.running {
+            queue.running = true;
+
+            let path = self.path.clone();
+            let max_files = self.max_files;
+            let shared = self.queue.clone();
+
+            let spawned = thread::Builder::new()
+                .name("log_nonblock-compress".to_string())
+                .spawn(move || Self::drain(&path, max_files, &shared));
+
+            if let Err(err) = spawned {
+                queue.running = false;
+                return Err(err);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Helper thread body, compresses queued files until the queue is empty
+    fn drain(path: &Path, max_files: usize, queue: &Mutex<CompressorQueue>) {
+        loop {
+            let pending = {
+                let mut queue = queue.lock().unwrap_or_else(|err| err.into_inner());
+                match queue.pending.pop_front() {
+                    Some(pending) => pending,
+                    None => {
+                        queue.running = false;
+                        return;
+                    }
+                }
+            };
+
+            if let Err(err) = Self::compress(path, max_files, &pending) {
+                crate::io::write_stderr_with_retry_internal(&format!(
+                    "Failed to compress rotated log file {}: {}",
+                    pending.display(),
+                    err
+                ));
+            }
+        }
+    }
+
+    fn compress(path: &Path, max_files: usize, pending: &Path) -> io::Result<()> {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        // Compress into a temporary file first, so an interrupted compression never leaves
+        // a truncated archive behind
+        let tmp = with_suffix(path, ".gz.tmp");
+        let mut encoder = GzEncoder::new(File::create(&tmp)?, Compression::default());
+        io::copy(&mut File::open(pending)?, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+
+        for index in (1..max_files).rev() {
+            let from = with_suffix(path, &format!(".{}.gz", index));
+            if from.exists() {
+                fs::rename(from, with_suffix(path, &format!(".{}.gz", index + 1)))?;
+            }
+        }
+
+        fs::rename(&tmp, with_suffix(path, ".1.gz"))?;
+        fs::remove_file(pending)
+    }
+}
@@ -0,0 +1,508 @@
+#[cfg(feature = "colored")]
+use colored::{Color, Colorize};
+#[cfg(feature = "kv")]
+use log::kv::{self, Key, Source, Value, VisitSource};
+use log::{Level, Record};
+use std::borrow::Cow;
+use std::fmt::{self, Display, Write};
+use std::time::Duration;
+#[cfg(feature = "timestamps")]
+use time::{OffsetDateTime, formatting::Formattable};
+
+use crate::line::LineWriter;
+use crate::{NonBlockingOptions, TRUNCATED_MARKER};
+#[cfg(feature = "timestamps")]
+use crate::{TIMESTAMP_FORMAT_OFFSET, TIMESTAMP_FORMAT_UTC, Timestamps};
+
+/// Everything needed to assemble a log line.
+///
+/// Values which depend on the moment of logging (timestamp, thread) are captured on the
+/// calling thread, so the line is identical no matter which thread assembles it.
+pub(crate) struct LineParts<'a> {
+    pub level: Level,
+    pub target: &'a str,
+    pub thread: &'a str,
+    /// Module of the record, only set with [`with_module_path`](crate::NonBlockingLoggerBuilder::with_module_path)
+    pub module: Option<&'a str>,
+    /// Source file, only set with [`with_source_location`](crate::NonBlockingLoggerBuilder::with_source_location)
+    pub file: Option<&'a str>,
+    pub line: Option<u32>,
+    /// Time since the logger was built, only set with [`with_uptime_timestamps`](crate::NonBlockingLoggerBuilder::with_uptime_timestamps)
+    pub uptime: Option<Duration>,
+    #[cfg(feature = "timestamps")]
+    pub now: OffsetDateTime,
+    pub args: &'a dyn Display,
+    /// Key-values of the record, only set when the line is assembled on the logging thread
+    #[cfg(feature = "kv")]
+    pub key_values: Option<&'a dyn Source>,
+}
+
+/// Owned copy of a record, sent to the worker when formatting is deferred.
+///
+/// See [`NonBlockingLoggerBuilder::with_deferred_formatting`](crate::NonBlockingLoggerBuilder::with_deferred_formatting)
+pub(crate) struct DeferredRecord {
+    pub level: Level,
+    pub target: String,
+    pub thread: Cow<'static, str>,
+    pub module: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub uptime: Option<Duration>,
+    #[cfg(feature = "timestamps")]
+    pub now: OffsetDateTime,
+    /// Rendered `record.args()`
+    pub args: String,
+    /// Index of the `route_target` sink the line is written to, instead of the other sinks
+    pub route: Option<usize>,
+}
+
+impl LineParts<'_> {
+    /// Appends `separator` and `path/to/file.rs:42`, or nothing if the record has no file.
+    ///
+    /// Returns whether anything was written.
+    pub fn write_location(&self, separator: &str, out: &mut dyn Write) -> bool {
+        let Some(file) = self.file else {
+            return false;
+        };
+
+        let _ = write!(out, "{}{}", separator, file);
+
+        if let Some(line) = self.line {
+            let _ = write!(out, ":{}", line);
+        }
+
+        true
+    }
+}
+
+impl DeferredRecord {
+    pub fn parts(&self) -> LineParts<'_> {
+        LineParts {
+            level: self.level,
+            target: &self.target,
+            thread: &self.thread,
+            module: self.module.as_deref(),
+            file: self.file.as_deref(),
+            line: self.line,
+            uptime: self.uptime,
+            #[cfg(feature = "timestamps")]
+            now: self.now,
+            args: &self.args,
+            #[cfg(feature = "kv")]
+            key_values: None,
+        }
+    }
+}
+
+/// The message of a record: `record.args()` followed by its key-value pairs, if any.
+///
+/// With the `kv` feature, pairs are appended as ` key=value`. Values which are empty or contain
+/// whitespace, quotes or `=` are quoted and escaped, e.g. `user="John \"Doe\""`.
+pub(crate) struct Message<'a> {
+    args: &'a fmt::Arguments<'a>,
+    #[cfg(feature = "kv")]
+    key_values: &'a dyn Source,
+}
+
+impl<'a> Message<'a> {
+    pub fn new(record: &'a Record<'a>) -> Self {
+        Self {
+            args: record.args(),
+            #[cfg(feature = "kv")]
+            key_values: record.key_values(),
+        }
+    }
+}
+
+impl Display for Message<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.args, f)?;
+
+        #[cfg(feature = "kv")]
+        self.key_values
+            .visit(&mut KeyValueWriter(f))
+            .map_err(|_| fmt::Error)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kv")]
+struct KeyValueWriter<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+#[cfg(feature = "kv")]
+impl<'kvs> VisitSource<'kvs> for KeyValueWriter<'_, '_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        write_key_value(self.0, key.as_str(), &value.to_string())
+            .map_err(|_| kv::Error::msg("failed to write key-value pair"))
+    }
+}
+
+/// Appends ` key=value`, quoting and escaping values which are empty or contain whitespace,
+/// quotes or `=`
+#[cfg(any(feature = "kv", feature = "tracing"))]
+pub(crate) fn write_key_value(out: &mut dyn Write, key: &str, value: &str) -> fmt::Result {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c == '"' || c == '=');
+
+    if needs_quotes {
+        write!(out, " {}={:?}", key, value)
+    } else {
+        write!(out, " {}={}", key, value)
+    }
+}
+
+impl NonBlockingOptions {
+    /// Time since the logger was built, `None` without uptime timestamps
+    pub(crate) fn uptime(&self) -> Option<Duration> {
+        self.started.map(|started| started.elapsed())
+    }
+
+    /// Returns the `@thread` suffix for the target bracket, or an empty string
+    ///
+    /// `@name` with thread names, `@id` with thread ids, `@name(id)` with both.
+    pub(crate) fn thread_label(&self) -> Cow<'static, str> {
+        #[cfg(feature = "threads")]
+        let names = self.threads;
+
+        #[cfg(not(feature = "threads"))]
+        let names = false;
+
+        if !names && !self.thread_ids {
+            return Cow::Borrowed("");
+        }
+
+        let thread = std::thread::current();
+        let name = thread.name().unwrap_or("?");
+
+        Cow::Owned(if !self.thread_ids {
+            format!("@{}", name)
+        } else {
+            // `ThreadId` has no stable accessor for the number, only `ThreadId(N)` from Debug
+            let id = format!("{:?}", thread.id());
+            let id = id
+                .strip_prefix("ThreadId(")
+                .and_then(|id| id.strip_suffix(')'))
+                .unwrap_or(&id);
+
+            if names {
+                format!("@{}({})", name, id)
+            } else {
+                format!("@{}", id)
+            }
+        })
+    }
+
+    /// Color of the level, `None` when it isn't colored
+    #[cfg(feature = "colors")]
+    fn level_color(&self, level: Level) -> Option<Color> {
+        if !self.colors {
+            return None;
+        }
+
+        if let Some(color) = self.level_colors[level as usize - 1] {
+            return Some(color);
+        }
+
+        match level {
+            Level::Error => Some(Color::Red),
+            Level::Warn => Some(Color::Yellow),
+            Level::Info => Some(Color::Cyan),
+            Level::Debug => Some(Color::Magenta),
+            Level::Trace => None,
+        }
+    }
+
+    #[cfg_attr(not(feature = "colors"), allow(unused_variables))]
+    fn write_level(&self, level: Level, colored: bool, out: &mut dyn Write) -> fmt::Result {
+        let label = self.level_labels[level as usize - 1].as_str();
+
+        let (label, width) = if self.compact_levels {
+            // Just the first character of the label
+            let end = label.chars().next().map_or(0, char::len_utf8);
+            (&label[..end], 1)
+        } else {
+            (label, self.level_width)
+        };
+
+        #[cfg(feature = "colors")]
+        if colored && let Some(color) = self.level_color(level) {
+            let label = format!("{:<width$}", label, width = width);
+            return write!(out, "{}", label.color(color));
+        }
+
+        write!(out, "{:<width$}", label, width = width)
+    }
+
+    /// Writes the timestamp followed by a space
+    #[cfg(feature = "timestamps")]
+    fn write_timestamp(&self, now: OffsetDateTime, out: &mut dyn Write) -> fmt::Result {
+        let (now, default_format) = match self.timestamps {
+            Timestamps::None => return Ok(()),
+            Timestamps::Utc => (now, TIMESTAMP_FORMAT_UTC),
+            Timestamps::UtcOffset(offset) => (now.to_offset(offset), TIMESTAMP_FORMAT_OFFSET),
+        };
+
+        // Only built when no format was set with `with_timestamp_format`
+        if let Some(format) = &self.subsecond_format {
+            return write_timestamp_with(now, format, out);
+        }
+
+        write_timestamp_with(now, self.timestamps_format.unwrap_or(default_format), out)
+    }
+
+    /// Appends the fully formatted line, including the line ending, to `out`
+    pub(crate) fn write_line<F: FnMut() -> String>(
+        &self,
+        parts: &LineParts,
+        out: &mut LineWriter<F>,
+    ) {
+        #[cfg(all(unix, feature = "syslog"))]
+        if let Some(syslog) = &self.syslog {
+            return syslog.write_line(parts, self.host.as_deref(), &self.severity_map, out);
+        }
+
+        #[cfg(all(target_os = "linux", feature = "journald"))]
+        if self.journald {
+            let strip_ansi = self.strip_ansi.unwrap_or(true);
+            return crate::journald::write_entry(
+                parts,
+                &self.severity_map,
+                strip_ansi,
+                out.bytes_mut(),
+            );
+        }
+
+        // Outside of the colors, so collectors matching on them see them as they were set
+        let _ = out.write_str(&self.line_prefix);
+
+        #[cfg(feature = "colors")]
+        if self.full_line_colors
+            && let Some(color) = self.level_color(parts.level)
+        {
+            let mut line = String::new();
+            self.write_text(parts, false, &mut line);
+
+            // Every line of a multi-line message is colored on its own, so the terminal is reset
+            // before each line ending and the color can't leak past the message
+            let mut segments = line.split('\n').peekable();
+
+            while let Some(segment) = segments.next() {
+                let (text, ending) = match segment.strip_suffix('\r') {
+                    Some(text) => (text, "\r\n"),
+                    None => (segment, "\n"),
+                };
+
+                if !text.is_empty() {
+                    let _ = write!(out, "{}", text.color(color));
+                }
+
+                if segments.peek().is_some() {
+                    let _ = out.write_str(ending);
+                }
+            }
+
+            self.write_line_end(out);
+
+            return;
+        }
+
+        self.write_text(parts, true, out);
+        self.write_line_end(out);
+    }
+
+    /// Appends the [suffix](crate::NonBlockingLoggerBuilder::with_line_suffix), and the line
+    /// ending unless [`with_append_newline`](crate::NonBlockingLoggerBuilder::with_append_newline) is off
+    fn write_line_end(&self, out: &mut dyn Write) {
+        let _ = out.write_str(&self.line_suffix);
+
+        if self.append_newline {
+            let _ = out.write_str("\r\n");
+        }
+    }
+
+    /// Appends the line without the line ending, coloring just the level if `colored_level` is set
+    fn write_text(&self, parts: &LineParts, colored_level: bool, out: &mut dyn Write) {
+        // Writing a line can't fail
+        if let Some(uptime) = parts.uptime {
+            let _ = write!(out, "[{:8.3}] ", uptime.as_secs_f64());
+        }
+
+        #[cfg(feature = "timestamps")]
+        let _ = self.write_timestamp(parts.now, out);
+
+        let _ = self.write_level(parts.level, colored_level, out);
+
+        if let Some(tag) = &self.tag {
+            let _ = write!(out, " {}", tag);
+        }
+
+        let target = if self.target { parts.target } else { "" };
+        let empty = target.is_empty()
+            && parts.thread.is_empty()
+            && parts.module.is_none()
+            && parts.file.is_none()
+            && !self.pid
+            && self.host.is_none();
+
+        // Without anything to put in it, the bracket and its space are left out entirely
+        if empty {
+            let _ = write!(out, " {}", parts.args);
+            return;
+        }
+
+        let _ = write!(out, " [{}{}", target, parts.thread);
+
+        // The first item after an empty target and thread isn't preceded by a space
+        let mut separator = if target.is_empty() && parts.thread.is_empty() {
+            ""
+        } else {
+            " "
+        };
+
+        if let Some(module) = parts.module {
+            let _ = write!(out, "{}module={}", separator, module);
+            separator = " ";
+        }
+
+        if parts.write_location(separator, out) {
+            separator = " ";
+        }
+
+        if self.pid {
+            let _ = write!(out, "{}pid={}", separator, std::process::id());
+            separator = " ";
+        }
+
+        if let Some(host) = &self.host {
+            let _ = write!(out, "{}host={}", separator, host);
+        }
+
+        let _ = write!(out, "] {}", parts.args);
+    }
+}
+
+/// Writes `now` followed by a space, formatted on the stack when it fits
+#[cfg(feature = "timestamps")]
+fn write_timestamp_with(
+    now: OffsetDateTime,
+    format: &(impl Formattable + ?Sized),
+    out: &mut dyn Write,
+) -> fmt::Result {
+    let mut buf = [0u8; 64];
+    let mut cursor = &mut buf[..];
+
+    match now.format_into(&mut cursor, format) {
+        Ok(len) => {
+            // The formatter writes UTF-8
+            let timestamp = std::str::from_utf8(&buf[..len]).map_err(|_| fmt::Error)?;
+            write!(out, "{} ", timestamp)
+        }
+        // Longer than the stack buffer
+        Err(_) => write!(out, "{} ", now.format(format).map_err(|_| fmt::Error)?),
+    }
+}
+
+/// Appends `args` to `out`, cut to at most `max_len` bytes followed by [`TRUNCATED_MARKER`]
+/// if it is longer.
+pub(crate) fn write_truncated(out: &mut String, args: &dyn Display, max_len: usize) {
+    let mut writer = TruncatingWriter {
+        end: out.len() + max_len,
+        out,
+        truncated: false,
+    };
+
+    // The writer fails once the limit is reached, which stops the formatting early
+    let _ = write!(writer, "{}", args);
+
+    if writer.truncated {
+        out.push_str(TRUNCATED_MARKER);
+    }
+}
+
+/// Appends to `out` until it reaches `end` bytes, cutting on a character boundary
+struct TruncatingWriter<'a> {
+    out: &'a mut String,
+    end: usize,
+    truncated: bool,
+}
+
+impl Write for TruncatingWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // A `Display` impl may ignore the error and keep writing
+        if self.truncated {
+            return Err(fmt::Error);
+        }
+
+        let room = self.end - self.out.len();
+
+        if s.len() <= room {
+            self.out.push_str(s);
+            return Ok(());
+        }
+
+        self.out.push_str(&s[..floor_char_boundary(s, room)]);
+        self.truncated = true;
+
+        Err(fmt::Error)
+    }
+}
+
+/// Largest index not greater than `index` which doesn't split a character of `s`, so the
+/// string can be sliced there without panicking
+fn floor_char_boundary(s: &str, index: usize) -> usize {
+    if index >= s.len() {
+        return s.len();
+    }
+
+    // A UTF-8 character is at most 4 bytes long
+    (index.saturating_sub(3)..=index)
+        .rev()
+        .find(|&i| s.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+/// Copies `buf` to `out` without ANSI escape sequences (`ESC [`, parameters, final byte)
+pub(crate) fn strip_ansi(buf: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+
+    while i < buf.len() {
+        if buf[i] == 0x1b && buf.get(i + 1) == Some(&b'[') {
+            i += 2;
+
+            // Skip parameter and intermediate bytes, then the final byte
+            while i < buf.len() && !(0x40..=0x7e).contains(&buf[i]) {
+                i += 1;
+            }
+
+            i += 1;
+        } else {
+            out.push(buf[i]);
+            i += 1;
+        }
+    }
+}
+
+/// Returns the name of this machine
+pub(crate) fn hostname() -> Option<String> {
+    #[cfg(unix)]
+    {
+        let mut buf = [0u8; 256];
+
+        if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+            return None;
+        }
+
+        // The name is truncated without a NUL terminator if it doesn't fit
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+
+        String::from_utf8(buf[..len].to_vec()).ok()
+    }
+
+    #[cfg(not(unix))]
+    {
+        std::env::var("COMPUTERNAME").ok()
+    }
+}
@@ -0,0 +1,25 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Stage of logging timed by a hook set with
+/// [`with_stage_hook`](crate::NonBlockingLoggerBuilder::with_stage_hook)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Formatting a record and sending it to the worker, on the logging thread
+    Enqueue,
+    /// Writing a batch of lines to the sinks, on the worker thread
+    Write {
+        /// Lines in the batch
+        lines: usize,
+    },
+}
+
+/// Closure set with [`with_stage_hook`](crate::NonBlockingLoggerBuilder::with_stage_hook)
+#[derive(Clone)]
+pub(crate) struct StageHook(pub Arc<dyn Fn(Stage, Duration) + Send + Sync>);
+
+impl std::fmt::Debug for StageHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("StageHook")
+    }
+}
@@ -1,9 +1,38 @@
+use std::cell::Cell;
 use std::io;
 use std::io::Write;
+use std::time::Duration;
 
 #[cfg(unix)]
 use std::os::fd::{AsRawFd, RawFd};
 
+#[cfg(all(windows, feature = "colors"))]
+use std::os::windows::io::AsRawHandle;
+#[cfg(all(windows, feature = "colors"))]
+use windows_sys::Win32::Foundation::HANDLE;
+#[cfg(all(windows, feature = "colors"))]
+use windows_sys::Win32::System::Console::{
+    ENABLE_VIRTUAL_TERMINAL_PROCESSING, GetConsoleMode, SetConsoleMode,
+};
+
+/// How long to sleep before retrying a write which wasn't accepted on Windows
+#[cfg(windows)]
+const WINDOWS_RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+thread_local! {
+    /// How long the worker thread waits for its output to become writable, see `with_write_timeout`
+    static WRITE_TIMEOUT: Cell<Option<Duration>> = const { Cell::new(None) };
+}
+
+/// Sets the write timeout for outputs written from the current thread
+pub(crate) fn set_write_timeout(timeout: Option<Duration>) {
+    WRITE_TIMEOUT.with(|cell| cell.set(timeout));
+}
+
+pub(crate) fn write_timeout() -> Option<Duration> {
+    WRITE_TIMEOUT.with(Cell::get)
+}
+
 /// Sets a file descriptor to non-blocking mode on Unix systems
 #[cfg(unix)]
 pub fn set_nonblocking(fd: RawFd) -> Result<(), io::Error> {
@@ -20,33 +49,125 @@ pub fn set_nonblocking(fd: RawFd) -> Result<(), io::Error> {
     Ok(())
 }
 
+/// Returns the status flags of a file descriptor, to restore them later with [`clear_nonblocking`]
+#[cfg(all(unix, feature = "nonblock-io"))]
+pub fn fd_flags(fd: RawFd) -> Result<libc::c_int, io::Error> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(flags)
+}
+
+/// Sets a file descriptor back to blocking mode on Unix systems, unless it was already
+/// non-blocking in `original_flags` (as returned by [`fd_flags`] before [`set_nonblocking`])
+#[cfg(all(unix, feature = "nonblock-io"))]
+pub fn clear_nonblocking(fd: RawFd, original_flags: libc::c_int) -> Result<(), io::Error> {
+    if original_flags & libc::O_NONBLOCK != 0 {
+        return Ok(());
+    }
+
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        if libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
 /// Waits for a file descriptor to become writable using poll().
 /// This is more efficient than sleeping when handling WouldBlock errors.
-/// Returns Ok(()) if the fd becomes writable, or Err if poll fails.
+/// Returns Ok(()) if the fd becomes writable or `timeout` elapses, or Err if poll fails.
 #[cfg(unix)]
-pub(crate) fn wait_writable(fd: RawFd) -> Result<(), io::Error> {
-    unsafe {
-        let mut pollfd = libc::pollfd {
-            fd,
-            events: libc::POLLOUT,
-            revents: 0,
-        };
+pub(crate) fn wait_writable(fd: RawFd, timeout: Option<Duration>) -> Result<(), io::Error> {
+    // -1 waits indefinitely
+    let timeout_ms = match timeout {
+        Some(timeout) => timeout.as_millis().min(i32::MAX as u128) as i32,
+        None => -1,
+    };
 
-        // Wait indefinitely for the fd to become writable
-        let ret = libc::poll(&mut pollfd as *mut libc::pollfd, 1, -1);
+    loop {
+        let ret = unsafe {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+
+            libc::poll(&mut pollfd as *mut libc::pollfd, 1, timeout_ms)
+        };
 
         if ret == -1 {
-            return Err(io::Error::last_os_error());
+            let err = io::Error::last_os_error();
+
+            // A signal delivered to this thread interrupted poll() before the fd became writable,
+            // that's not a failure of the output, so just wait again. Any other errno is a hard error.
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+
+            return Err(err);
         }
 
-        Ok(())
+        return Ok(());
+    }
+}
+
+/// Sleeps before retrying a write on Windows, instead of retrying it in a busy loop.
+///
+/// STDOUT/STDERR handles aren't opened for overlapped I/O, so there is no readiness
+/// notification like `poll()` to wait for: the write is simply retried after a short sleep,
+/// or after `timeout` if that's shorter.
+#[cfg(windows)]
+pub(crate) fn wait_writable(timeout: Option<Duration>) {
+    let interval = timeout.map_or(WINDOWS_RETRY_INTERVAL, |timeout| {
+        timeout.min(WINDOWS_RETRY_INTERVAL)
+    });
+
+    std::thread::sleep(interval);
+}
+
+/// Makes a Windows console interpret ANSI escape sequences, which consoles older than
+/// Windows 10 and legacy console hosts otherwise print as is.
+///
+/// Sets `ENABLE_VIRTUAL_TERMINAL_PROCESSING` on the console of the output. Does nothing if the
+/// output isn't a console, e.g. a pipe or a file, or if the mode is already set. Fails if the
+/// console doesn't support the mode.
+#[cfg(all(windows, feature = "colors"))]
+pub(crate) fn enable_virtual_terminal(output: crate::Output) -> Result<(), io::Error> {
+    let handle = match output {
+        crate::Output::Stdout => io::stdout().as_raw_handle(),
+        crate::Output::Stderr => io::stderr().as_raw_handle(),
+    } as HANDLE;
+
+    let mut mode = 0;
+
+    // Fails for handles which aren't consoles, there is nothing to enable
+    if unsafe { GetConsoleMode(handle, &mut mode) } == 0 {
+        return Ok(());
+    }
+
+    if mode & ENABLE_VIRTUAL_TERMINAL_PROCESSING != 0 {
+        return Ok(());
+    }
+
+    if unsafe { SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) } == 0 {
+        return Err(io::Error::last_os_error());
     }
+
+    Ok(())
 }
 
 macro_rules! write_with_retry_internal {
     ($out:expr, $msg:expr) => {{
         let mut out = $out;
-        let bytes = $msg.as_bytes();
+        let bytes: &[u8] = $msg.as_ref();
         let mut written = 0;
 
         #[cfg(unix)]
@@ -58,7 +179,7 @@ macro_rules! write_with_retry_internal {
                     #[cfg(unix)]
                     {
                         // Nothing accepted, wait for fd to become writable
-                        if wait_writable(raw_fd).is_err() {
+                        if wait_writable(raw_fd, None).is_err() {
                             // If poll fails, give up
                             break;
                         }
@@ -66,7 +187,8 @@ macro_rules! write_with_retry_internal {
 
                     #[cfg(windows)]
                     {
-                        // On Windows, just retry
+                        // Nothing to wait on, retry after a short sleep
+                        wait_writable(None);
                     }
                 }
                 Ok(n) => {
@@ -77,7 +199,7 @@ macro_rules! write_with_retry_internal {
                     #[cfg(unix)]
                     {
                         // Wait for fd to become writable
-                        if wait_writable(raw_fd).is_err() {
+                        if wait_writable(raw_fd, None).is_err() {
                             // If poll fails, give up
                             break;
                         }
@@ -85,9 +207,60 @@ macro_rules! write_with_retry_internal {
 
                     #[cfg(windows)]
                     {
-                        // On Windows, just retry
+                        // Nothing to wait on, retry after a short sleep
+                        wait_writable(None);
+                    }
+                }
+                Err(_) => {
+                    // Hard error, give up
+                    break;
+                }
+            }
+        }
+    }};
+}
+
+/// Writes a log line to the selected standard stream, with retry logic.
+///
+/// Used by the logging thread for records written synchronously, see `with_sync_level`.
+pub(crate) fn write_stdio_with_retry(line: &[u8], output: crate::Output, strip_ansi: bool) {
+    let mut stripped = Vec::new();
+
+    let bytes = if strip_ansi {
+        crate::format::strip_ansi(line, &mut stripped);
+        &stripped[..]
+    } else {
+        line
+    };
+
+    match output {
+        crate::Output::Stdout => write_with_retry_internal!(io::stdout().lock(), bytes),
+        crate::Output::Stderr => write_with_retry_internal!(io::stderr().lock(), bytes),
+    }
+}
+
+#[cfg(feature = "macros")]
+macro_rules! flush_with_retry_internal {
+    ($out:expr) => {{
+        let mut out = $out;
+
+        #[cfg(unix)]
+        let raw_fd = out.as_raw_fd();
+
+        loop {
+            match out.flush() {
+                Ok(()) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    // The buffer keeps what wasn't written yet, wait and flush the rest
+                    #[cfg(unix)]
+                    if wait_writable(raw_fd, None).is_err() {
+                        break;
                     }
+
+                    #[cfg(windows)]
+                    wait_writable(None);
                 }
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
                 Err(_) => {
                     // Hard error, give up
                     break;
@@ -122,6 +295,40 @@ pub fn write_stdout_with_retry(msg: &str) {
     write_with_retry_internal!(out.lock(), msg);
 }
 
+/// Sends a message to the worker of the global logger, to be written to stdout.
+/// Falls back to [`write_stdout_with_retry`] when no logger was registered with `init()`,
+/// or the message can't be sent to it.
+/// This function is used by the `aprintln!` macro when the `macros` feature is enabled.
+#[doc(hidden)]
+#[cfg(feature = "macros")]
+pub fn write_stdout_async(msg: String) {
+    match crate::GLOBAL_LOGGER.get() {
+        Some(logger) => {
+            if let Err(msg) = logger.print(crate::worker::Stream::Stdout, msg) {
+                write_stdout_with_retry(&msg);
+            }
+        }
+        None => write_stdout_with_retry(&msg),
+    }
+}
+
+/// Sends a message to the worker of the global logger, to be written to stderr.
+/// Falls back to [`write_stderr_with_retry`] when no logger was registered with `init()`,
+/// or the message can't be sent to it.
+/// This function is used by the `aeprintln!` macro when the `macros` feature is enabled.
+#[doc(hidden)]
+#[cfg(feature = "macros")]
+pub fn write_stderr_async(msg: String) {
+    match crate::GLOBAL_LOGGER.get() {
+        Some(logger) => {
+            if let Err(msg) = logger.print(crate::worker::Stream::Stderr, msg) {
+                write_stderr_with_retry(&msg);
+            }
+        }
+        None => write_stderr_with_retry(&msg),
+    }
+}
+
 /// Writes a message to stderr with retry logic, without adding any prefix.
 /// This function is used by the `eprintln!` macro when the `macros` feature is enabled.
 #[doc(hidden)]
@@ -130,3 +337,21 @@ pub fn write_stderr_with_retry(msg: &str) {
     let out = io::stderr();
     write_with_retry_internal!(out.lock(), msg);
 }
+
+/// Flushes stdout with retry logic, so partial lines which are buffered by stdout appear.
+/// This function is used by the `print!` macro when the `macros` feature is enabled.
+#[doc(hidden)]
+#[cfg(feature = "macros")]
+pub fn flush_stdout_with_retry() {
+    let out = io::stdout();
+    flush_with_retry_internal!(out.lock());
+}
+
+/// Flushes stderr with retry logic.
+/// This function is used by the `eprint!` macro when the `macros` feature is enabled.
+#[doc(hidden)]
+#[cfg(feature = "macros")]
+pub fn flush_stderr_with_retry() {
+    let out = io::stderr();
+    flush_with_retry_internal!(out.lock());
+}
@@ -0,0 +1,145 @@
+#[cfg(feature = "kv")]
+use log::kv::{self, Key, Value, VisitSource};
+use std::io;
+use std::os::unix::net::UnixDatagram;
+
+use crate::format::LineParts;
+use crate::{SeverityMap, Sink};
+
+/// Path of the journald native protocol socket
+const JOURNAL_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+/// Appends a journal entry in the native protocol to `out`.
+///
+/// `PRIORITY`, `TARGET`, `CODE_MODULE`, `CODE_FILE`, `CODE_LINE`, the key-values of the record
+/// and `MESSAGE`. Values without a newline are written as `KEY=value`, others as the key, a
+/// newline, the length of the value as a little-endian 64-bit integer and the value, each field
+/// followed by a newline. The entry is binary, so color codes are removed from the values here
+/// with `strip_ansi` rather than from the whole entry by the worker.
+pub(crate) fn write_entry(
+    parts: &LineParts,
+    severity: &SeverityMap,
+    strip_ansi: bool,
+    out: &mut Vec<u8>,
+) {
+    let mut fields = FieldWriter { out, strip_ansi };
+
+    // Priorities only go up to 7, like syslog severities
+    fields.write("PRIORITY", &severity.get(parts.level).min(7).to_string());
+    fields.write("TARGET", parts.target);
+
+    if let Some(module) = parts.module {
+        fields.write("CODE_MODULE", module);
+    }
+
+    if let Some(file) = parts.file {
+        fields.write("CODE_FILE", file);
+    }
+
+    if let Some(line) = parts.line {
+        fields.write("CODE_LINE", &line.to_string());
+    }
+
+    #[cfg(feature = "kv")]
+    if let Some(key_values) = parts.key_values {
+        let _ = key_values.visit(&mut fields);
+    }
+
+    fields.write("MESSAGE", &parts.args.to_string());
+}
+
+struct FieldWriter<'a> {
+    out: &'a mut Vec<u8>,
+    strip_ansi: bool,
+}
+
+impl FieldWriter<'_> {
+    fn write(&mut self, key: &str, value: &str) {
+        let stripped;
+        let value = if self.strip_ansi && value.contains('\x1b') {
+            let mut buf = Vec::with_capacity(value.len());
+            crate::format::strip_ansi(value.as_bytes(), &mut buf);
+            stripped = buf;
+            &stripped[..]
+        } else {
+            value.as_bytes()
+        };
+
+        self.out.extend_from_slice(key.as_bytes());
+
+        if value.contains(&b'\n') {
+            self.out.push(b'\n');
+            self.out
+                .extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            self.out.push(b'=');
+        }
+
+        self.out.extend_from_slice(value);
+        self.out.push(b'\n');
+    }
+}
+
+/// Turns a key into a valid field name: uppercase ASCII letters, digits and underscores, not
+/// starting with an underscore, at most 64 characters
+#[cfg(feature = "kv")]
+fn field_name(key: &str) -> String {
+    key.chars()
+        .map(|c| match c {
+            'a'..='z' => c.to_ascii_uppercase(),
+            'A'..='Z' | '0'..='9' => c,
+            _ => '_',
+        })
+        .skip_while(|c| *c == '_')
+        .take(64)
+        .collect()
+}
+
+#[cfg(feature = "kv")]
+impl<'kvs> VisitSource<'kvs> for FieldWriter<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let name = field_name(key.as_str());
+
+        // Fields which would be dropped by journald, or shadow the ones above
+        if !name.is_empty() && !matches!(name.as_str(), "MESSAGE" | "PRIORITY") {
+            self.write(&name, &value.to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Connection to journald, owned by the worker thread
+pub(crate) struct JournalSocket {
+    socket: UnixDatagram,
+}
+
+impl JournalSocket {
+    /// Connects to `/run/systemd/journal/socket`, which doesn't exist on hosts without systemd
+    pub fn connect() -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET_PATH)?;
+
+        Ok(Self { socket })
+    }
+}
+
+impl Sink for JournalSocket {
+    /// Sends a single entry, reconnecting once if journald went away (e.g. it was restarted)
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.socket.send(buf).is_err() {
+            self.socket = Self::connect()?.socket;
+            self.socket.send(buf)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn is_message_oriented(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,192 @@
+use log::{Level, Log, Metadata, Record};
+use std::fmt::{self, Write};
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id, Record as SpanRecord};
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::NonBlockingLogger;
+use crate::format::write_key_value;
+
+/// [`Layer`] which writes `tracing` events through a [`NonBlockingLogger`].
+///
+/// Events go through the same levels, filters, channel and worker thread as records logged with
+/// the `log` macros, so they get the same backpressure and non-blocking writes. Fields of the
+/// event are appended as ` key=value`, and the spans the event happened in are prepended as
+/// `outer{key=value}:inner: `.
+///
+/// Requires a subscriber which stores span data, such as `tracing_subscriber::Registry`.
+///
+/// ```
+/// use log_nonblock::{CaptureSink, NonBlockingLayer, NonBlockingLoggerBuilder};
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let capture = CaptureSink::new();
+/// let logger = NonBlockingLoggerBuilder::new()
+///     .with_capture(&capture)
+///     .build()
+///     .unwrap();
+///
+/// let subscriber = tracing_subscriber::registry().with(NonBlockingLayer::new(logger.clone()));
+///
+/// tracing::subscriber::with_default(subscriber, || {
+///     let _span = tracing::info_span!(target: "app", "request", id = 7).entered();
+///     tracing::info!(target: "app", user = "alice", "Logged in");
+/// });
+/// log::Log::flush(&logger);
+///
+/// assert!(capture.lines()[0].ends_with("[app] request{id=7}: Logged in user=alice"));
+/// ```
+///
+/// This type is only available if the `tracing` feature is enabled.
+#[derive(Clone, Debug)]
+pub struct NonBlockingLayer {
+    logger: NonBlockingLogger,
+}
+
+impl NonBlockingLayer {
+    pub fn new(logger: NonBlockingLogger) -> Self {
+        Self { logger }
+    }
+}
+
+/// Formatted fields of a span, kept in its extensions until the span is closed
+struct SpanFields(String);
+
+/// Collects the `message` field of an event and the other fields as ` key=value`
+struct FieldWriter {
+    /// `None` for spans, which write `message` as a regular field
+    message: Option<String>,
+    fields: String,
+}
+
+impl FieldWriter {
+    fn event() -> Self {
+        Self {
+            message: Some(String::new()),
+            fields: String::new(),
+        }
+    }
+
+    fn span() -> Self {
+        Self {
+            message: None,
+            fields: String::new(),
+        }
+    }
+
+    fn record(&mut self, field: &Field, value: &str) {
+        match &mut self.message {
+            Some(message) if field.name() == "message" => message.push_str(value),
+            _ => {
+                // Writing into a String can't fail
+                let _ = write_key_value(&mut self.fields, field.name(), value);
+            }
+        }
+    }
+}
+
+impl Visit for FieldWriter {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, &format!("{:?}", value));
+    }
+}
+
+fn log_level(level: &tracing_core::Level) -> Level {
+    match *level {
+        tracing_core::Level::ERROR => Level::Error,
+        tracing_core::Level::WARN => Level::Warn,
+        tracing_core::Level::INFO => Level::Info,
+        tracing_core::Level::DEBUG => Level::Debug,
+        tracing_core::Level::TRACE => Level::Trace,
+    }
+}
+
+impl<S> Layer<S> for NonBlockingLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut writer = FieldWriter::span();
+        attrs.record(&mut writer);
+
+        span.extensions_mut().insert(SpanFields(writer.fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &SpanRecord<'_>, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+
+        let mut writer = FieldWriter::span();
+        values.record(&mut writer);
+
+        if let Some(fields) = span.extensions_mut().get_mut::<SpanFields>() {
+            fields.0.push_str(&writer.fields);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = log_level(metadata.level());
+
+        // Skip formatting the fields of events the logger would drop anyway
+        let log_metadata = Metadata::builder()
+            .level(level)
+            .target(metadata.target())
+            .build();
+
+        if !self.logger.enabled(&log_metadata) {
+            return;
+        }
+
+        let mut message = String::new();
+
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                message.push_str(span.name());
+
+                if let Some(fields) = span.extensions().get::<SpanFields>()
+                    && !fields.0.is_empty()
+                {
+                    let _ = write!(message, "{{{}}}", fields.0.trim_start());
+                }
+
+                message.push(':');
+            }
+
+            message.push(' ');
+        }
+
+        let mut writer = FieldWriter::event();
+        event.record(&mut writer);
+
+        match writer.message {
+            Some(text) if !text.is_empty() => {
+                message.push_str(&text);
+                message.push_str(&writer.fields);
+            }
+            _ => message.push_str(writer.fields.trim_start()),
+        }
+
+        self.logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(level)
+                .target(metadata.target())
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .build(),
+        );
+    }
+}
@@ -1,20 +1,74 @@
-#[cfg(feature = "colored")]
-use colored::Colorize;
-use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use crossbeam_channel::TrySendError;
+use log::{Level, LevelFilter, Log, Metadata, ParseLevelError, Record, SetLoggerError};
+use std::fmt::Write;
+use std::net::SocketAddr;
 #[cfg(all(unix, feature = "nonblock-io"))]
 use std::os::fd::AsRawFd;
-use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 #[cfg(feature = "timestamps")]
-use time::{OffsetDateTime, UtcOffset, format_description::FormatItem};
+use time::{
+    OffsetDateTime, UtcOffset,
+    format_description::{FormatItem, OwnedFormatItem},
+};
 
 #[cfg(feature = "macros")]
 pub mod io;
 #[cfg(not(feature = "macros"))]
 mod io;
 
+mod backpressure;
+#[cfg(feature = "timestamps")]
+mod clock;
+#[cfg(feature = "serde")]
+mod config;
+mod dedup;
+mod file;
+mod format;
+#[cfg(feature = "bench-hooks")]
+mod hooks;
+#[cfg(all(target_os = "linux", feature = "journald"))]
+mod journald;
+#[cfg(feature = "tracing")]
+mod layer;
+mod line;
+mod rate_limit;
+mod record;
+mod sampling;
+mod scoped;
+mod severity;
+#[cfg(all(unix, feature = "unix-signals"))]
+mod signals;
+mod sink;
+#[cfg(all(unix, feature = "syslog"))]
+mod syslog;
+mod tcp;
+mod thread_batch;
+#[cfg(unix)]
+mod unix_socket;
 mod worker;
 
+#[cfg(feature = "colors")]
+pub use colored::Color;
+#[cfg(feature = "serde")]
+pub use config::NonBlockingConfig;
+#[cfg(feature = "bench-hooks")]
+pub use hooks::Stage;
+pub use record::OwnedRecord;
+pub use scoped::ScopedLevel;
+pub use severity::SeverityMap;
+pub use sink::{CaptureSink, FileSink, Sink, StderrSink, StdoutSink};
+pub use tcp::Framing;
+
+#[cfg(feature = "tracing")]
+pub use layer::NonBlockingLayer;
+#[cfg(all(unix, feature = "syslog"))]
+pub use syslog::Facility;
+
 #[cfg(feature = "macros")]
 mod macros;
 
@@ -36,6 +90,71 @@ const TIMESTAMP_FORMAT_UTC: &[FormatItem] = time::macros::format_description!(
     "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"
 );
 
+/// Default timestamp format with `digits` digits of the second fraction, without one for `0`
+#[cfg(feature = "timestamps")]
+fn timestamp_format_with_digits(timestamps: &Timestamps, digits: u8) -> OwnedFormatItem {
+    let mut description = String::from("[year]-[month]-[day]T[hour]:[minute]:[second]");
+
+    if digits > 0 {
+        let _ = write!(description, ".[subsecond digits:{}]", digits);
+    }
+
+    description.push_str(match timestamps {
+        Timestamps::UtcOffset(_) => "[offset_hour sign:mandatory]:[offset_minute]",
+        _ => "Z",
+    });
+
+    time::format_description::parse_owned::<1>(&description)
+        .expect("The timestamp format is assembled from valid components")
+}
+
+/// What happens to a log message when the channel to the worker is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum FullPolicy {
+    /// Drop the message, so logging never blocks the calling thread
+    #[default]
+    Drop,
+    /// Block the calling thread until the worker makes room in the channel
+    Block,
+}
+
+/// Standard stream written by the logger, see [`NonBlockingLoggerBuilder::with_output`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Output {
+    Stdout,
+    Stderr,
+}
+
+impl Default for Output {
+    /// STDOUT, or STDERR with the `stderr` feature
+    fn default() -> Self {
+        if cfg!(feature = "stderr") {
+            Output::Stderr
+        } else {
+            Output::Stdout
+        }
+    }
+}
+
+impl Output {
+    fn sink(self) -> Box<dyn Sink> {
+        match self {
+            Output::Stdout => Box::new(StdoutSink),
+            Output::Stderr => Box::new(StderrSink),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct NonBlockingOptions {
     /// The default logging level
@@ -49,20 +168,268 @@ pub struct NonBlockingOptions {
     /// vector for the first match to give us the desired log level for a module.
     module_levels: Vec<(String, LevelFilter)>,
 
+    /// Levels for targets matching a pattern, checked in the order they were added
+    /// when no entry of `module_levels` matches.
+    #[cfg(feature = "regex")]
+    module_level_regexes: Vec<(regex::Regex, LevelFilter)>,
+
+    /// Passed to `log::set_max_level` instead of the level computed from the rules above
+    max_level_override: Option<LevelFilter>,
+
+    /// Whether `log::set_max_level` is called at all, see [`NonBlockingLoggerBuilder::with_manage_max_level`]
+    manage_max_level: bool,
+
+    /// Checked before the rules above, see [`NonBlockingLogger::scoped_module_level`]
+    scoped_levels: Arc<scoped::ScopedLevels>,
+
+    /// Add `@name` of the logging thread after the target
+    #[cfg(feature = "threads")]
+    threads: bool,
+
+    /// Add the id of the logging thread after the target
+    thread_ids: bool,
+
+    /// Display the target in the bracket before the message
+    target: bool,
+
     #[cfg(feature = "colors")]
     colors: bool,
 
+    /// Colors set with [`with_level_color`](NonBlockingLoggerBuilder::with_level_color), indexed like `level_labels`
+    #[cfg(feature = "colors")]
+    level_colors: [Option<Color>; 5],
+
+    /// Color the whole line instead of just the level
+    #[cfg(feature = "colors")]
+    full_line_colors: bool,
+
+    /// Displayed level names, in `Level` order: error, warn, info, debug, trace
+    level_labels: [String; 5],
+
+    /// Labels are padded to the longest one, to keep messages aligned
+    level_width: usize,
+
+    /// Only write the first character of the labels, see [`NonBlockingLoggerBuilder::with_compact_levels`]
+    compact_levels: bool,
+
+    /// End every line with `\r\n`, off when messages bring their own terminator
+    append_newline: bool,
+
+    /// Written before and after every line, see [`NonBlockingLoggerBuilder::with_line_prefix`]
+    line_prefix: String,
+    line_suffix: String,
+
     #[cfg(feature = "timestamps")]
     timestamps: Timestamps,
 
     #[cfg(feature = "timestamps")]
     timestamps_format: Option<&'static [FormatItem<'static>]>,
 
+    /// Digits of the second fraction in the default formats, see `with_subsecond_digits`
+    #[cfg(feature = "timestamps")]
+    subsecond_digits: Option<u8>,
+
+    /// Default format with `subsecond_digits`, built at runtime by `build`
+    #[cfg(feature = "timestamps")]
+    subsecond_format: Option<OwnedFormatItem>,
+
+    /// Prefix lines with the time since the logger was built
+    uptime_timestamps: bool,
+
+    /// Moment the logger was built, set in `build` when `uptime_timestamps` is enabled
+    started: Option<Instant>,
+
+    /// Clock set with [`NonBlockingLoggerBuilder::with_clock`], `None` for the real clock
+    #[cfg(feature = "timestamps")]
+    clock: Option<clock::CustomClock>,
+
     channel_size: usize,
+
+    /// Use an unbounded channel, `channel_size` is ignored
+    unbounded_channel: bool,
+
+    /// Remove ANSI color codes before writing, `None` strips them for sinks which aren't terminals
+    strip_ansi: Option<bool>,
+
+    /// Add `file:line` to the target bracket
+    source_location: bool,
+
+    /// Add `module=...` to the target bracket
+    module_path: bool,
+
+    /// Target prefixes of `route_target`, most specific first, indexed like the worker's routes
+    route_prefixes: Vec<String>,
+
+    /// Add `pid=...` to the target bracket
+    pid: bool,
+
+    /// Add `host=...` to the target bracket
+    hostname: bool,
+
+    /// Hostname read once in [`build`](NonBlockingLoggerBuilder::build), when `hostname` is enabled
+    host: Option<String>,
+
+    /// Written after the level of every line, see [`NonBlockingLoggerBuilder::with_tag`]
+    tag: Option<String>,
+
+    /// Numeric severity of each level, see [`NonBlockingLoggerBuilder::with_severity_map`]
+    severity_map: SeverityMap,
+
+    buffer_pool_size: usize,
+
+    /// Largest batch of waiting lines written at once, see [`NonBlockingLoggerBuilder::with_max_batch_bytes`]
+    max_batch_bytes: Option<usize>,
+
+    /// Lines shorter than this are merged with the next one, see [`NonBlockingLoggerBuilder::with_large_message_threshold`]
+    large_message_threshold: usize,
+
+    deferred_formatting: bool,
+
+    /// Lines collected by each logging thread, shared by all clones of the logger, see
+    /// [`NonBlockingLoggerBuilder::with_thread_local_batching`]
+    thread_batches: Option<Arc<thread_batch::ThreadBatches>>,
+
+    /// Longest message in bytes, see [`NonBlockingLoggerBuilder::with_max_message_len`]
+    max_message_len: Option<usize>,
+
+    file: Option<file::FileOptions>,
+
+    #[cfg(feature = "compression")]
+    compress_rotated: bool,
+
+    #[cfg(all(unix, feature = "syslog"))]
+    syslog: Option<syslog::SyslogOptions>,
+
+    /// Send entries to journald, see [`NonBlockingLoggerBuilder::with_journald`]
+    #[cfg(all(target_os = "linux", feature = "journald"))]
+    journald: bool,
+
+    tcp: Option<SocketAddr>,
+
+    /// See [`NonBlockingLoggerBuilder::with_unix_socket`]
+    #[cfg(unix)]
+    unix_socket: Option<unix_socket::UnixSocket>,
+
+    /// Standard stream written when there is no file, TCP or syslog output
+    output: Output,
+
+    /// Descriptor of `output` and its flags before `build` made it non-blocking, restored by
+    /// the worker when it stops
+    #[cfg(all(unix, feature = "nonblock-io"))]
+    original_output_flags: Option<(std::os::fd::RawFd, libc::c_int)>,
+
+    /// How lines are delimited on the TCP connection
+    framing: Framing,
+
+    full_policy: FullPolicy,
+
+    /// Fail to build when the output can't be opened, see
+    /// [`NonBlockingLoggerBuilder::with_validate_on_init`]
+    validate_on_init: bool,
+
+    /// Error records which can't be sent to the worker are written to STDERR, see
+    /// [`NonBlockingLoggerBuilder::with_error_fallback`]
+    error_fallback: bool,
+
+    /// Records at this level or more severe are written by the logging thread, see
+    /// [`NonBlockingLoggerBuilder::with_sync_level`]. Cleared in `build` unless the output is STDOUT/STDERR.
+    sync_level: Option<Level>,
+
+    /// The worker flushes the outputs after writing a record at this level or more severe, see
+    /// [`NonBlockingLoggerBuilder::with_flush_on_level`]
+    flush_level: Option<Level>,
+
+    /// Write every record on the logging thread, without a worker, see
+    /// [`NonBlockingLoggerBuilder::with_synchronous`]
+    synchronous: bool,
+
+    /// Whether synchronously written lines are stripped of color codes, resolved in `build`
+    sync_strip_ansi: bool,
+
+    /// Whether synchronously written lines are also sent to the worker, for the sinks other
+    /// than the output and `on_log`. Resolved in `build`
+    sync_copies: bool,
+
+    /// Wait for the worker before a synchronous line, see [`NonBlockingLoggerBuilder::with_ordered_sync`]
+    ordered_sync: bool,
+
+    /// Whether a slow call to `log()` was already reported, shared by all clones of the logger.
+    /// See [`NonBlockingLoggerBuilder::with_detect_blocking`]
+    detect_blocking: Option<Arc<AtomicBool>>,
+
+    write_timeout: Option<Duration>,
+
+    worker_thread_name: String,
+
+    rate_limits: Vec<Arc<rate_limit::RateLimit>>,
+
+    /// Last message and its repeats, shared by all clones of the logger
+    dedup: Option<Arc<Mutex<dedup::Dedup>>>,
+
+    /// Shared by all clones of the logger, see [`NonBlockingLoggerBuilder::with_adaptive_sampling`]
+    sampler: Option<Arc<sampling::Sampler>>,
+
+    /// Shared by all clones of the logger, see [`NonBlockingLoggerBuilder::with_backpressure_notify`]
+    backpressure: Option<Arc<backpressure::Backpressure>>,
+
+    filter: Option<Filter>,
+
+    /// Called by the worker with every line, see [`NonBlockingLoggerBuilder::with_on_log`]
+    on_log: Option<OnLog>,
+
+    /// Receives a copy of every record, see [`NonBlockingLoggerBuilder::with_record_sink`]
+    record_sink: Option<std::sync::mpsc::Sender<OwnedRecord>>,
+
+    #[cfg(feature = "bench-hooks")]
+    stage_hook: Option<hooks::StageHook>,
+}
+
+/// Closure set with [`NonBlockingLoggerBuilder::with_filter`]
+#[derive(Clone)]
+struct Filter(Arc<dyn Fn(&Metadata) -> bool + Send + Sync>);
+
+impl std::fmt::Debug for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Filter")
+    }
+}
+
+type OnLogFn = dyn Fn(Level, &str) + Send + Sync;
+
+/// Closure set with [`NonBlockingLoggerBuilder::with_on_log`]
+#[derive(Clone)]
+struct OnLog(Arc<OnLogFn>);
+
+impl std::fmt::Debug for OnLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OnLog")
+    }
+}
+
+/// Outputs opened before the logger is built, see `NonBlockingLoggerBuilder::open_outputs`
+struct OpenedOutputs {
+    /// Shared with the TCP and Unix stream outputs, which stop retrying once it is cleared
+    running: Arc<AtomicBool>,
+    output: Box<dyn Sink>,
+    error_file: Option<(Level, Box<dyn Sink>)>,
 }
 
 pub struct NonBlockingLoggerBuilder {
     options: NonBlockingOptions,
+    /// Sinks added with [`add_sink`](#method.add_sink), written after the primary output
+    sinks: Vec<(Option<String>, Box<dyn Sink>)>,
+    /// See [`with_capture`](#method.with_capture)
+    capture: Option<CaptureSink>,
+    /// See [`with_ring_buffer`](#method.with_ring_buffer)
+    ring_buffer: Option<sink::RingBuffer>,
+    /// Target prefixes and their sinks, see [`route_target`](#method.route_target)
+    routes: Vec<(String, Box<dyn Sink>)>,
+    /// Path and least severe level, see [`with_error_file`](#method.with_error_file)
+    error_file: Option<(std::path::PathBuf, Level)>,
+    /// Whether [`with_colors`](#method.with_colors) was called, which takes precedence over
+    /// `NO_COLOR` and `CLICOLOR_FORCE`
+    #[cfg(feature = "colors")]
+    colors_explicit: bool,
 }
 
 impl Default for NonBlockingLoggerBuilder {
@@ -73,6 +440,89 @@ impl Default for NonBlockingLoggerBuilder {
 
 pub const DEFAULT_CHANNEL_SIZE: usize = 16384;
 
+pub const DEFAULT_BUFFER_POOL_SIZE: usize = 1024;
+
+/// Lines shorter than this are written together with the next waiting line, see
+/// [`NonBlockingLoggerBuilder::with_large_message_threshold`].
+///
+/// Merging two lines costs a copy into a shared buffer and saves a write call. 1280 bytes is the
+/// cutoff the worker has always used, a heuristic rather than a measured crossover, which keeps
+/// merged writes well below the 4KB a pipe accepts in one atomic write on Linux.
+pub const DEFAULT_LARGE_MESSAGE_THRESHOLD: usize = 1280;
+
+/// Buffers with a larger capacity are dropped by the worker instead of being returned to the pool,
+/// so a single huge message doesn't pin its allocation for the lifetime of the logger.
+pub const MAX_POOLED_BUFFER_CAPACITY: usize = 16 * 1024;
+
+pub const DEFAULT_WORKER_THREAD_NAME: &str = "log_nonblock-worker";
+
+/// A call to `log()` taking longer than this is reported, see
+/// [`NonBlockingLoggerBuilder::with_detect_blocking`]
+pub const BLOCKING_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// Logger set up by [`NonBlockingLoggerBuilder::init`], behind [`GlobalLogger`] and used by the
+/// `aprintln!` and `aeprintln!` macros
+static GLOBAL_LOGGER: OnceLock<NonBlockingLogger> = OnceLock::new();
+
+/// Whether [`GlobalLogger`] is registered with the `log` crate, which can only be done once.
+///
+/// It is registered before the logger is built, so an `init` which can't register doesn't
+/// spawn a worker thread or touch the output streams. [`GLOBAL_LOGGER`] is only set once the
+/// logger is built, so an `init` which fails after registering can be called again. Held
+/// during the whole `init`, so concurrent calls can't both fill the slot.
+static REGISTERED: Mutex<bool> = Mutex::new(false);
+
+/// Registered with the `log` crate by [`NonBlockingLoggerBuilder::init`], forwards records to
+/// [`GLOBAL_LOGGER`], and discards them until it is set.
+struct GlobalLogger;
+
+impl Log for GlobalLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        GLOBAL_LOGGER
+            .get()
+            .is_some_and(|logger| logger.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some(logger) = GLOBAL_LOGGER.get() {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(logger) = GLOBAL_LOGGER.get() {
+            logger.flush();
+        }
+    }
+}
+
+/// Most verbose level the logger writes, whatever levels are configured.
+///
+/// `Info` in release builds (without `debug_assertions`) with the `release-max-level-info`
+/// feature, `Trace` otherwise. Debug and trace records are then rejected by `enabled` before
+/// any level lookup, and [`NonBlockingLogger::max_level`] never goes above `Info`, so `log`
+/// skips them right in the macros.
+///
+/// This only saves the work done for those records at runtime. To also compile the calls out
+/// of the binary, enable the matching feature of the `log` crate as well:
+///
+/// ```toml
+/// [dependencies]
+/// log = { version = "0.4", features = ["release_max_level_info"] }
+/// log_nonblock = { version = "0.1", features = ["release-max-level-info"] }
+/// ```
+pub const STATIC_MAX_LEVEL: LevelFilter = if cfg!(all(
+    feature = "release-max-level-info",
+    not(debug_assertions)
+)) {
+    LevelFilter::Info
+} else {
+    LevelFilter::Trace
+};
+
+/// Appended to messages cut by [`NonBlockingLoggerBuilder::with_max_message_len`]
+pub const TRUNCATED_MARKER: &str = "…[truncated]";
+
 impl NonBlockingLoggerBuilder {
     pub fn new() -> Self {
         Self {
@@ -80,20 +530,131 @@ impl NonBlockingLoggerBuilder {
                 default_level: LevelFilter::Trace,
                 module_levels: Vec::new(),
 
+                #[cfg(feature = "regex")]
+                module_level_regexes: Vec::new(),
+
+                max_level_override: None,
+                manage_max_level: true,
+                scoped_levels: Arc::default(),
+
                 #[cfg(feature = "threads")]
                 threads: false,
 
+                thread_ids: false,
+                target: true,
+
                 #[cfg(feature = "timestamps")]
                 timestamps: Timestamps::Utc,
 
                 #[cfg(feature = "timestamps")]
                 timestamps_format: None,
+                #[cfg(feature = "timestamps")]
+                subsecond_digits: None,
+                #[cfg(feature = "timestamps")]
+                subsecond_format: None,
+                uptime_timestamps: false,
+                started: None,
+                #[cfg(feature = "timestamps")]
+                clock: None,
 
                 #[cfg(feature = "colors")]
                 colors: true,
 
+                #[cfg(feature = "colors")]
+                level_colors: [None; 5],
+
+                #[cfg(feature = "colors")]
+                full_line_colors: false,
+
+                level_labels: ["ERROR", "WARN", "INFO", "DEBUG", "TRACE"].map(str::to_string),
+
+                level_width: 5,
+
+                compact_levels: false,
+
+                append_newline: true,
+                line_prefix: String::new(),
+                line_suffix: String::new(),
+
                 channel_size: DEFAULT_CHANNEL_SIZE,
+
+                unbounded_channel: false,
+
+                strip_ansi: None,
+
+                source_location: false,
+                module_path: false,
+                route_prefixes: Vec::new(),
+
+                pid: false,
+
+                hostname: false,
+
+                host: None,
+                tag: None,
+                severity_map: SeverityMap::SYSLOG,
+
+                buffer_pool_size: DEFAULT_BUFFER_POOL_SIZE,
+                max_batch_bytes: None,
+                large_message_threshold: DEFAULT_LARGE_MESSAGE_THRESHOLD,
+
+                deferred_formatting: false,
+                thread_batches: None,
+                max_message_len: None,
+
+                file: None,
+
+                #[cfg(feature = "compression")]
+                compress_rotated: false,
+
+                #[cfg(all(unix, feature = "syslog"))]
+                syslog: None,
+
+                #[cfg(all(target_os = "linux", feature = "journald"))]
+                journald: false,
+
+                tcp: None,
+                #[cfg(unix)]
+                unix_socket: None,
+                output: Output::default(),
+                #[cfg(all(unix, feature = "nonblock-io"))]
+                original_output_flags: None,
+                framing: Framing::Newline,
+
+                full_policy: FullPolicy::Drop,
+                error_fallback: false,
+                validate_on_init: false,
+                sync_level: None,
+                flush_level: None,
+                synchronous: false,
+                ordered_sync: false,
+                detect_blocking: None,
+                sync_strip_ansi: false,
+                sync_copies: false,
+
+                write_timeout: None,
+                worker_thread_name: DEFAULT_WORKER_THREAD_NAME.to_string(),
+
+                rate_limits: Vec::new(),
+
+                dedup: None,
+                sampler: None,
+                backpressure: None,
+
+                filter: None,
+                on_log: None,
+                record_sink: None,
+
+                #[cfg(feature = "bench-hooks")]
+                stage_hook: None,
             },
+            sinks: Vec::new(),
+            capture: None,
+            ring_buffer: None,
+            routes: Vec::new(),
+            error_file: None,
+            #[cfg(feature = "colors")]
+            colors_explicit: false,
         }
     }
 
@@ -111,355 +672,4494 @@ impl NonBlockingLoggerBuilder {
         self
     }
 
+    /// Set the level for a module and its sub-modules, overriding the default level.
+    ///
+    /// A rule for `foo` matches the targets `foo` and `foo::bar`, but not `foobar`.
+    /// When several rules match, the most specific (longest) one wins. Rules of the same length
+    /// never match the same target, and setting the level of a module again replaces its rule,
+    /// so the order of the calls doesn't matter.
+    ///
+    /// ```
+    /// use log::{Level, LevelFilter, Log, Metadata};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(LevelFilter::Info)
+    ///     .with_module_level("foo", LevelFilter::Off)
+    ///     .with_module_level("foo::bar", LevelFilter::Trace)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let enabled = |target| logger.enabled(&Metadata::builder().level(Level::Debug).target(target).build());
+    /// assert!(!enabled("foo"));
+    /// assert!(!enabled("foo::baz"));
+    /// assert!(!enabled("foobar"));
+    /// assert!(!enabled("foo::barbaz"));
+    /// assert!(enabled("foo::bar"));
+    /// assert!(enabled("foo::bar::baz"));
+    ///
+    /// let info = |target| logger.enabled(&Metadata::builder().level(Level::Info).target(target).build());
+    /// assert!(info("foobar"));
+    /// assert!(!info("foo::barbaz"));
+    /// ```
+    ///
+    /// Competing rules of the same length:
+    ///
+    /// ```
+    /// use log::{Level, LevelFilter, Log, Metadata};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(LevelFilter::Off)
+    ///     .with_module_level("foo::baz", LevelFilter::Error)
+    ///     .with_module_level("foo::bar", LevelFilter::Warn)
+    ///     .with_module_level("foo::bat", LevelFilter::Debug)
+    ///     .with_module_level("foo::bar", LevelFilter::Info)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let level = |target| {
+    ///     [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error]
+    ///         .into_iter()
+    ///         .find(|&level| logger.enabled(&Metadata::builder().level(level).target(target).build()))
+    /// };
+    /// assert_eq!(level("foo::bar::x"), Some(Level::Info));
+    /// assert_eq!(level("foo::bar"), Some(Level::Info));
+    /// assert_eq!(level("foo::baz::x"), Some(Level::Error));
+    /// assert_eq!(level("foo::bat::x"), Some(Level::Debug));
+    /// assert_eq!(level("foo::bay"), None);
+    /// ```
     #[must_use = "You must call init() to begin logging"]
     pub fn with_module_level(mut self, target: &str, level: LevelFilter) -> Self {
-        self.options.module_levels.push((target.to_string(), level));
-        self.options
-            .module_levels
-            .sort_by_key(|(name, _level)| name.len().wrapping_neg());
+        let module_levels = &mut self.options.module_levels;
+
+        match module_levels
+            .iter_mut()
+            .find(|(name, _level)| name == target)
+        {
+            Some((_name, existing)) => *existing = level,
+            None => module_levels.push((target.to_string(), level)),
+        }
+
+        // Longest first, so the first match is the most specific one. Different rules of the
+        // same length can't match the same target, the name only makes the order deterministic.
+        module_levels.sort_by(|(a, _), (b, _)| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
         self
     }
 
-    /// Control whether messages are colored or not.
+    /// Set the 'default' log level from its name, e.g. a level read from a config file.
     ///
-    /// This method is only available if the `colored` feature is enabled.
-    #[must_use = "You must call init() to begin logging"]
-    #[cfg(feature = "colors")]
-    pub fn with_colors(mut self, colors: bool) -> Self {
-        self.options.colors = colors;
-        self
+    /// Accepts the names `off`, `error`, `warn`, `info`, `debug` and `trace`, ignoring case.
+    /// Fails on any other string. See [`with_level`](#method.with_level).
+    ///
+    /// ```
+    /// use log::{Level, Log, Metadata};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level_str("Debug")
+    ///     .unwrap()
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert!(logger.enabled(&Metadata::builder().level(Level::Debug).build()));
+    /// assert!(!logger.enabled(&Metadata::builder().level(Level::Trace).build()));
+    ///
+    /// assert!(NonBlockingLoggerBuilder::new().with_level_str("verbose").is_err());
+    /// ```
+    pub fn with_level_str(self, level: &str) -> Result<Self, ParseLevelError> {
+        Ok(self.with_level(level.parse()?))
     }
 
-    /// Don't display any timestamps.
+    /// Set the level for a module and its sub-modules from its name, like
+    /// [`with_level_str`](#method.with_level_str). See [`with_module_level`](#method.with_module_level).
+    pub fn with_module_level_str(self, target: &str, level: &str) -> Result<Self, ParseLevelError> {
+        Ok(self.with_module_level(target, level.parse()?))
+    }
+
+    /// Set a closure deciding whether a record is logged, for filtering that levels can't express.
     ///
-    /// This method is only available if the `timestamps` feature is enabled.
+    /// The closure is called by [`enabled`](log::Log::enabled) after the level check passed, and
+    /// records for which it returns `false` are dropped. It runs on every enabled check, on the
+    /// logging thread, so it should be cheap. Setting a new filter replaces the previous one.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_filter(|metadata| metadata.target() != "chatty")
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for target in ["chatty", "app"] {
+    ///     logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target(target).build());
+    /// }
+    /// logger.flush();
+    ///
+    /// let lines = capture.lines();
+    /// assert_eq!(lines.len(), 1);
+    /// assert!(lines[0].ends_with("[app] Hello"));
+    /// ```
     #[must_use = "You must call init() to begin logging"]
-    #[cfg(feature = "timestamps")]
-    pub fn without_timestamps(mut self) -> Self {
-        self.options.timestamps = Timestamps::None;
+    pub fn with_filter(
+        mut self,
+        filter: impl Fn(&Metadata) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.options.filter = Some(Filter(Arc::new(filter)));
         self
     }
 
-    /// Display timestamps using UTC.
+    /// Call `on_log` with the level and text of every line, e.g. to count errors in a metrics
+    /// system or forward them to an alerting service.
     ///
-    /// This method is only available if the `timestamps` feature is enabled.
+    /// The closure is called by the worker thread, off the logging path, just before the line is
+    /// written. The text is the line as written to the outputs, without the line ending. It
+    /// includes the messages of the logger itself, like the summary of dropped messages, and
+    /// the lines written synchronously, once the worker gets their copy, see
+    /// [`with_sync_level`](#method.with_sync_level).
+    ///
+    /// The worker writes nothing while the closure runs, so a slow closure slows down every
+    /// output and lets the channel fill up. Hand expensive work, like a network call, over to
+    /// another thread. A panic in the closure is caught and reported on STDERR, the line is
+    /// still written. Setting a new closure replaces the previous one.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    /// use std::sync::Arc;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    ///
+    /// let errors = Arc::new(AtomicUsize::new(0));
+    /// let counted = errors.clone();
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_on_log(move |level, _line| {
+    ///         if level == Level::Error {
+    ///             counted.fetch_add(1, Ordering::Relaxed);
+    ///         }
+    ///     })
+    ///     .with_capture(&CaptureSink::new())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for level in [Level::Error, Level::Info, Level::Error, Level::Warn] {
+    ///     logger.log(&Record::builder().args(format_args!("Hello")).level(level).build());
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(errors.load(Ordering::Relaxed), 2);
+    /// ```
     #[must_use = "You must call init() to begin logging"]
-    #[cfg(feature = "timestamps")]
-    pub fn with_utc_timestamps(mut self) -> Self {
-        self.options.timestamps = Timestamps::Utc;
+    pub fn with_on_log(mut self, on_log: impl Fn(Level, &str) + Send + Sync + 'static) -> Self {
+        self.options.on_log = Some(OnLog(Arc::new(on_log)));
         self
     }
 
-    /// Display timestamps using a static UTC offset.
+    /// Send an owned copy of every record to `sender`, in addition to writing it, e.g. for a
+    /// test harness or a processor which needs the fields rather than the formatted line.
     ///
-    /// This method is only available if the `timestamps` feature is enabled.
+    /// The copy is made on the logging thread, for every record which passes the levels,
+    /// [filter](#method.with_filter), sampling, deduplication and rate limits: the message is
+    /// rendered a second time, and the target, message, module and file of the [`OwnedRecord`]
+    /// are each allocated, on top of the formatting of the line. The channel is unbounded, so
+    /// the logger never waits for the receiver; a receiver which falls behind grows the channel
+    /// instead. Once the receiver is dropped, the copies are discarded.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let (sender, records) = std::sync::mpsc::channel();
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(log::LevelFilter::Info)
+    ///     .with_record_sink(sender)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for level in [Level::Warn, Level::Debug] {
+    ///     logger.log(
+    ///         &Record::builder()
+    ///             .args(format_args!("Disk {}% full", 91))
+    ///             .level(level)
+    ///             .target("app::disk")
+    ///             .file(Some("src/disk.rs"))
+    ///             .line(Some(42))
+    ///             .build(),
+    ///     );
+    /// }
+    /// logger.flush();
+    ///
+    /// let record = records.try_recv().unwrap();
+    /// assert_eq!(record.level, Level::Warn);
+    /// assert_eq!(record.target, "app::disk");
+    /// assert_eq!(record.args, "Disk 91% full");
+    /// assert_eq!((record.file.as_deref(), record.line), (Some("src/disk.rs"), Some(42)));
+    ///
+    /// // The debug record was filtered out, and the line is still written
+    /// assert!(records.try_recv().is_err());
+    /// assert_eq!(capture.lines().len(), 1);
+    /// ```
+    ///
+    /// Default: no record sink
     #[must_use = "You must call init() to begin logging"]
-    #[cfg(feature = "timestamps")]
-    pub fn with_utc_offset(mut self, offset: UtcOffset) -> Self {
-        self.options.timestamps = Timestamps::UtcOffset(offset);
+    pub fn with_record_sink(mut self, sender: std::sync::mpsc::Sender<OwnedRecord>) -> Self {
+        self.options.record_sink = Some(sender);
         self
     }
 
-    /// Control the format used for timestamps.
+    /// Call `hook` with the time spent in each stage of logging, to measure the impact of
+    /// options in benchmarks.
     ///
-    /// Without this, a default format is used depending on the timestamps type.
+    /// [`Stage::Enqueue`] times formatting a record and sending it to the worker, on the
+    /// logging thread. [`Stage::Write`] times writing a batch of lines to the sinks, on the
+    /// worker thread. The hook runs inline with both, so it should be cheap, e.g. add to
+    /// atomic counters. See `benches/stages.rs`.
     ///
-    /// The syntax for the format_description macro can be found in the
-    /// [`time` crate book](https://time-rs.github.io/book/api/format-description.html).
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder, Stage};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// let enqueued = Arc::new(AtomicUsize::new(0));
+    /// let written = Arc::new(AtomicUsize::new(0));
+    /// let (enqueued_hook, written_hook) = (enqueued.clone(), written.clone());
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_stage_hook(move |stage, _elapsed| match stage {
+    ///         Stage::Enqueue => _ = enqueued_hook.fetch_add(1, Ordering::Relaxed),
+    ///         Stage::Write { lines } => _ = written_hook.fetch_add(lines, Ordering::Relaxed),
+    ///     })
+    ///     .with_capture(&CaptureSink::new())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for i in 0..10 {
+    ///     logger.log(&Record::builder().args(format_args!("Message {}", i)).level(Level::Info).build());
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(enqueued.load(Ordering::Relaxed), 10);
+    /// assert_eq!(written.load(Ordering::Relaxed), 10);
+    /// ```
+    ///
+    /// This method is only available if the `bench-hooks` feature is enabled. Without it, the
+    /// timing isn't compiled in at all.
     #[must_use = "You must call init() to begin logging"]
-    #[cfg(feature = "timestamps")]
-    pub fn with_timestamp_format(mut self, format: &'static [FormatItem<'static>]) -> Self {
-        self.options.timestamps_format = Some(format);
+    #[cfg(feature = "bench-hooks")]
+    pub fn with_stage_hook(
+        mut self,
+        hook: impl Fn(Stage, Duration) + Send + Sync + 'static,
+    ) -> Self {
+        self.options.stage_hook = Some(hooks::StageHook(Arc::new(hook)));
         self
     }
 
-    /// Set the size of the internal channel buffer.
+    /// Suppress consecutive repeats of the same message.
     ///
-    /// The channel buffer holds log messages before they are written to output.
-    /// A larger buffer allows more messages to be queued during bursts of logging,
-    /// but uses more memory. If the buffer fills up, new log messages may be dropped.
+    /// Messages are compared on level, target and the rendered message, so differing timestamps
+    /// don't defeat deduplication. Repeats are counted instead of written, and
+    /// `last message repeated N times` is written once a different message arrives, on
+    /// [`flush`](log::Log::flush), and otherwise by the worker about once a second while repeats
+    /// are pending, even if nothing else is logged. Without a worker, see
+    /// [`with_synchronous`](#method.with_synchronous), only the first two apply.
     ///
-    /// Default: [`DEFAULT_CHANNEL_SIZE`] (16384 messages)
+    /// Default: `false`
     ///
-    /// # Panics
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_dedup(true)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
     ///
-    /// Panics if `size` is 0. The channel size must be at least 1 to allow the logger
-    /// to buffer messages between the calling thread and the worker thread. A zero-sized
-    /// channel would not be able to hold any messages, making the logger non-functional.
+    /// for message in ["Retrying", "Retrying", "Retrying", "Connected"] {
+    ///     logger.log(&Record::builder().args(format_args!("{}", message)).level(Level::Info).target("db").build());
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(
+    ///     capture.lines(),
+    ///     [
+    ///         "INFO  [db] Retrying",
+    ///         "INFO  [db] last message repeated 2 times",
+    ///         "INFO  [db] Connected",
+    ///     ]
+    /// );
+    /// ```
     #[must_use = "You must call init() to begin logging"]
-    pub fn with_channel_size(mut self, size: usize) -> Self {
-        assert!(size > 0, "Channel size must be greater than 0");
-        self.options.channel_size = size;
+    pub fn with_dedup(mut self, dedup: bool) -> Self {
+        self.options.dedup = dedup.then(Default::default);
         self
     }
 
-    /// Initializes the non-blocking logger and sets it as the global logger.
+    /// Sample debug and trace records while the channel is close to full, instead of dropping
+    /// whatever arrives once it is full.
     ///
-    /// This method builds a logger instance, configures the global max log level,
-    /// and registers it with the `log` crate as the global logger.
+    /// Once more than 75% of the channel is filled, only 1 in 2 debug and trace records is
+    /// kept, and fewer as the backlog grows, down to 1 in 16 near a full channel. Error, warn
+    /// and info records are never sampled, so they keep flowing during bursts. Sampling stops
+    /// as soon as the worker catches up. The number of records sampled out is reported by
+    /// [`NonBlockingLogger::sampled_out`].
     ///
-    /// # Errors
+    /// Has no effect with [`with_unbounded_channel`](#method.with_unbounded_channel).
     ///
-    /// Returns an error if the global logger has already been set.
-    pub fn init(self) -> Result<NonBlockingLogger, SetLoggerError> {
-        let logger = self.build()?;
-
-        log::set_max_level(logger.max_level());
-        log::set_boxed_logger(Box::new(logger.clone()))?;
-
-        Ok(logger)
+    /// Default: `false`
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_channel_size(1024)
+    ///     .with_adaptive_sampling(true)
+    ///     .with_capture(&Default::default())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// // Nothing is sampled out while the worker keeps up
+    /// assert_eq!(logger.sampled_out(), 0);
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_adaptive_sampling(mut self, sampling: bool) -> Self {
+        self.options.sampler = sampling.then(Default::default);
+        self
     }
 
-    /// Builds a non-blocking logger instance without setting it as the global logger.
+    /// Set the level for targets matching a regular expression, e.g. `.*::db` or `hyper::.*`.
     ///
-    /// Use this method if you want to manage the logger instance yourself. Otherwise,
+    /// The pattern must match the whole target. Rules from [`with_module_level`](#method.with_module_level)
+    /// take precedence: patterns are only checked when no prefix rule matches, in the order
+    /// they were added, and the first matching pattern wins.
+    ///
+    /// Returns an error if the pattern is invalid.
+    ///
+    /// This method is only available if the `regex` feature is enabled.
+    ///
+    /// ```
+    /// use log::{Level, LevelFilter, Log, Metadata};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(LevelFilter::Info)
+    ///     .with_module_level_regex(".*::db", LevelFilter::Warn)
+    ///     .unwrap()
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let enabled = |target| logger.enabled(&Metadata::builder().level(Level::Info).target(target).build());
+    /// assert!(!enabled("app::db"));
+    /// assert!(enabled("app::db::pool"));
+    /// assert!(enabled("app"));
+    ///
+    /// assert!(NonBlockingLoggerBuilder::new().with_module_level_regex("(", LevelFilter::Off).is_err());
+    /// ```
+    #[cfg(feature = "regex")]
+    pub fn with_module_level_regex(
+        mut self,
+        pattern: &str,
+        level: LevelFilter,
+    ) -> Result<Self, regex::Error> {
+        let regex = regex::Regex::new(&format!("^(?:{})$", pattern))?;
+
+        self.options.module_level_regexes.push((regex, level));
+        Ok(self)
+    }
+
+    /// Set the global `log` max level, instead of computing it from the default and module levels.
+    ///
+    /// [`init`](#method.init) passes [`NonBlockingLogger::max_level`] to `log::set_max_level`,
+    /// which makes the `log` macros skip records above it before their arguments are evaluated.
+    /// By default it is the most verbose of the default and module levels, so every enabled
+    /// record gets through.
+    ///
+    /// A lower override caps all logging below the module rules, e.g. to avoid the cost of
+    /// evaluating expensive debug arguments: records above it are discarded by the macros even
+    /// when a module level enables them. A higher override lets more records reach the logger,
+    /// where the default and module levels still decide which of them are written.
+    ///
+    /// ```
+    /// use log::LevelFilter;
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(LevelFilter::Warn)
+    ///     .with_module_level("app::db", LevelFilter::Trace)
+    ///     .with_max_level_override(LevelFilter::Info)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(logger.max_level(), LevelFilter::Info);
+    /// ```
+    ///
+    /// Default: computed from the levels
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_max_level_override(mut self, level: LevelFilter) -> Self {
+        self.options.max_level_override = Some(level);
+        self
+    }
+
+    /// Control whether [`init`](#method.init) sets the global `log` max level.
+    ///
+    /// With `false` the logger is registered, but `log::set_max_level` is never called, neither
+    /// by `init` nor by [`scoped_module_level`](NonBlockingLogger::scoped_module_level). Use it
+    /// when another component manages the max level, e.g. a second logging facade in the same
+    /// process. The `log` crate starts with `Off`, so the max level has to be set by someone
+    /// for records to reach the logger.
+    ///
+    /// The logger still applies its own levels and filters in [`enabled`](log::Log::enabled)
+    /// to every record it receives.
+    ///
+    /// ```
+    /// use log::LevelFilter;
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// log::set_max_level(LevelFilter::Warn);
+    ///
+    /// let _logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(LevelFilter::Trace)
+    ///     .with_manage_max_level(false)
+    ///     .init()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(log::max_level(), LevelFilter::Warn);
+    /// ```
+    ///
+    /// Either way, the global logger also rejects records above `log::max_level()` in
+    /// `enabled`, so a level lowered with `log::set_max_level` elsewhere, e.g. to toggle
+    /// verbosity at runtime, applies to records passed to the logger directly as well, not only
+    /// to the `log` macros. It only lowers the levels of the logger, raising the max level
+    /// above them doesn't let more records through. Loggers created with
+    /// [`build`](#method.build) ignore it.
+    ///
+    /// ```
+    /// use log::{Level, LevelFilter, Log, Metadata};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(LevelFilter::Info)
+    ///     .init()
+    ///     .unwrap();
+    ///
+    /// let info = Metadata::builder().level(Level::Info).target("app").build();
+    /// let warn = Metadata::builder().level(Level::Warn).target("app").build();
+    /// assert!(logger.enabled(&info));
+    ///
+    /// log::set_max_level(LevelFilter::Warn);
+    /// assert!(!logger.enabled(&info));
+    /// assert!(!log::logger().enabled(&info));
+    /// assert!(logger.enabled(&warn));
+    ///
+    /// log::set_max_level(LevelFilter::Trace);
+    /// assert!(logger.enabled(&info));
+    /// assert!(!logger.enabled(&Metadata::builder().level(Level::Debug).target("app").build()));
+    /// ```
+    ///
+    /// Default: `true`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_manage_max_level(mut self, manage: bool) -> Self {
+        self.options.manage_max_level = manage;
+        self
+    }
+
+    /// Limit how many messages a target (and its sub-modules) can log.
+    ///
+    /// Each limited target gets a token bucket holding up to `max_per_interval` tokens, refilled
+    /// continuously at `max_per_interval` per `interval`. Messages arriving when the bucket is
+    /// empty are dropped. The next message that gets through is preceded by a warning
+    /// `suppressed N messages from <target>`, so a flood shows up as one summary per refill.
+    ///
+    /// This is intentional throttling of noisy targets, unrelated to the channel [`FullPolicy`].
+    /// Targets without a limit are unaffected.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    /// use std::time::Duration;
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_rate_limit("noisy", 2, Duration::from_millis(100))
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let log = |target: &str, i: i32| {
+    ///     logger.log(&Record::builder().args(format_args!("{}", i)).level(Level::Info).target(target).build())
+    /// };
+    ///
+    /// for i in 0..5 {
+    ///     log("noisy", i);
+    ///     log("quiet", i);
+    /// }
+    ///
+    /// std::thread::sleep(Duration::from_millis(150));
+    /// log("noisy", 5);
+    /// logger.flush();
+    ///
+    /// let noisy = capture.lines().into_iter().filter(|line| line.contains("[noisy]")).collect::<Vec<_>>();
+    /// assert_eq!(
+    ///     noisy,
+    ///     [
+    ///         "INFO  [noisy] 0",
+    ///         "INFO  [noisy] 1",
+    ///         "WARN  [noisy] suppressed 3 messages from noisy",
+    ///         "INFO  [noisy] 5",
+    ///     ]
+    /// );
+    /// assert_eq!(capture.lines().iter().filter(|line| line.contains("[quiet]")).count(), 5);
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_rate_limit(
+        mut self,
+        target: &str,
+        max_per_interval: u32,
+        interval: Duration,
+    ) -> Self {
+        self.options
+            .rate_limits
+            .push(Arc::new(rate_limit::RateLimit::new(
+                target,
+                max_per_interval,
+                interval,
+            )));
+        self
+    }
+
+    /// Display the name of the logging thread after the target, as `[target@name]`.
+    ///
+    /// Unnamed threads are displayed as `?`, use [`with_thread_ids`](#method.with_thread_ids)
+    /// to tell them apart.
+    ///
+    /// This method is only available if the `threads` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "threads")]
+    pub fn with_threads(mut self, threads: bool) -> Self {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Display the target of the record in the bracket before the message.
+    ///
+    /// With `false`, lines only keep what else is displayed in the bracket, e.g. the thread.
+    /// When nothing is left the bracket is left out too, for clean output in CLI tools.
+    ///
+    /// Default: `true`
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let id = format!("{:?}", std::thread::current().id()).replace("ThreadId(", "").replace(')', "");
+    ///
+    /// for (target, thread_ids, expected) in [
+    ///     (true, false, "INFO  [app] Hello".to_string()),
+    ///     (true, true, format!("INFO  [app@{}] Hello", id)),
+    ///     (false, true, format!("INFO  [@{}] Hello", id)),
+    ///     (false, false, "INFO  Hello".to_string()),
+    /// ] {
+    ///     let capture = CaptureSink::new();
+    ///     let logger = NonBlockingLoggerBuilder::new()
+    ///         .without_timestamps()
+    ///         .with_colors(false)
+    ///         .with_target(target)
+    ///         .with_thread_ids(thread_ids)
+    ///         .with_capture(&capture)
+    ///         .build()
+    ///         .unwrap();
+    ///
+    ///     logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target("app").build());
+    ///     logger.flush();
+    ///
+    ///     assert_eq!(capture.lines(), [expected]);
+    /// }
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_target(mut self, target: bool) -> Self {
+        self.options.target = target;
+        self
+    }
+
+    /// Display the id of the logging thread after the target, as `[target@5]`.
+    ///
+    /// Combined with [`with_threads`](#method.with_threads) both are displayed, as `[target@name(5)]`.
+    ///
+    /// Default: `false`
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_thread_ids(true)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// let id = format!("{:?}", std::thread::current().id()).replace("ThreadId(", "").replace(')', "");
+    /// assert!(capture.lines()[0].ends_with(&format!("[app@{}] Hello", id)));
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_thread_ids(mut self, thread_ids: bool) -> Self {
+        self.options.thread_ids = thread_ids;
+        self
+    }
+
+    /// Control whether messages are colored or not.
+    ///
+    /// Whether lines are colored is decided in this order, the first that applies wins:
+    ///
+    /// 1. `with_colors(false)` disables colors, `with_colors(true)` enables them even when
+    ///    the output isn't a terminal or `NO_COLOR` is set.
+    /// 2. `NO_COLOR` set to a non-empty value disables colors.
+    /// 3. `CLICOLOR_FORCE` set to a non-empty value other than `0` enables colors, even when the
+    ///    output isn't a terminal, and keeps them in every sink unless
+    ///    [`with_strip_ansi`](#method.with_strip_ansi) says otherwise.
+    /// 4. Otherwise colors are used when the [output](#method.with_output) stream is a terminal.
+    ///
+    /// The environment is read when the logger is built. Colors are still removed from sinks
+    /// which aren't terminals, see [`with_strip_ansi`](#method.with_strip_ansi).
+    ///
+    /// On Windows, building the logger turns on the interpretation of color codes by the console
+    /// (`ENABLE_VIRTUAL_TERMINAL_PROCESSING`), which older consoles don't do by default. If the
+    /// console doesn't support it, an error is reported and colors are disabled.
+    ///
+    /// This method is only available if the `colored` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "colors")]
+    pub fn with_colors(mut self, colors: bool) -> Self {
+        self.options.colors = colors;
+        self.colors_explicit = true;
+        self
+    }
+
+    /// Change the color of a level.
+    ///
+    /// Default colors: error is red, warn is yellow, info is cyan, debug is purple, trace isn't colored.
+    ///
+    /// Has no effect with [`with_colors(false)`](#method.with_colors), or when the output isn't
+    /// a terminal and colors are disabled by the `colored` crate.
+    ///
+    /// This method is only available if the `colors` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "colors")]
+    pub fn with_level_color(mut self, level: Level, color: Color) -> Self {
+        self.options.level_colors[level as usize - 1] = Some(color);
+        self
+    }
+
+    /// Color the whole line with the color of its level, instead of just the level.
+    ///
+    /// The trailing line ending is written after the color reset. Each line of a multi-line
+    /// message, e.g. a pretty-printed struct, is colored on its own, with the color reset before
+    /// every newline, so it doesn't carry over to the next log line or to a pager. Like other
+    /// colors, this has no effect with [`with_colors(false)`](#method.with_colors).
+    ///
+    /// This method is only available if the `colors` feature is enabled.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(true)
+    ///     .with_full_line_colors(true)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Boom")).level(Level::Error).target("app").build());
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines(), ["\x1b[31mERROR [app] Boom\x1b[0m"]);
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Boom\nat main")).level(Level::Error).target("app").build());
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines()[1], "\x1b[31mERROR [app] Boom\x1b[0m\n\x1b[31mat main\x1b[0m");
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "colors")]
+    pub fn with_full_line_colors(mut self, full_line_colors: bool) -> Self {
+        self.options.full_line_colors = full_line_colors;
+        self
+    }
+
+    /// Control whether ANSI color codes are removed from lines before they are written.
+    ///
+    /// By default the worker removes them for sinks which aren't terminals (files, TCP, syslog,
+    /// redirected STDOUT/STDERR, see [`Sink::is_terminal`]), so escape codes don't leak into files
+    /// even when colors are forced. `true` strips them for every sink, `false` never strips them.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, FileSink, NonBlockingLoggerBuilder};
+    ///
+    /// let path = std::env::temp_dir().join(format!("log_nonblock_strip_{}.log", std::process::id()));
+    /// let _ = std::fs::remove_file(&path);
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_colors(true)
+    ///     .with_capture(&capture)
+    ///     .add_sink(FileSink::open(&path).unwrap())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Boom")).level(Level::Error).target("app").build());
+    /// logger.flush();
+    ///
+    /// // The capture sink behaves like a terminal and keeps the colors, the file doesn't
+    /// assert!(capture.lines()[0].contains("\x1b["));
+    ///
+    /// let written = std::fs::read_to_string(&path).unwrap();
+    /// assert!(written.ends_with("ERROR [app] Boom\r\n"));
+    /// assert!(!written.contains("\x1b["));
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_strip_ansi(mut self, strip_ansi: bool) -> Self {
+        self.options.strip_ansi = Some(strip_ansi);
+        self
+    }
+
+    /// Replace the displayed level names, given in error, warn, info, debug, trace order.
+    ///
+    /// Labels are left-aligned and padded to the width of the longest one. Colors still follow
+    /// the actual level and wrap the padded label.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(true)
+    ///     .with_level_labels(["ERR", "WRN", "INFO", "DBG", "TRC"])
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for level in [Level::Error, Level::Info] {
+    ///     logger.log(&Record::builder().args(format_args!("Hello")).level(level).target("app").build());
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(
+    ///     capture.lines(),
+    ///     ["\x1b[31mERR \x1b[0m [app] Hello", "\x1b[36mINFO\x1b[0m [app] Hello"]
+    /// );
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_level_labels(mut self, labels: [&str; 5]) -> Self {
+        self.options.level_width = labels
+            .iter()
+            .map(|label| label.chars().count())
+            .max()
+            .unwrap_or_default();
+        self.options.level_labels = labels.map(str::to_string);
+        self
+    }
+
+    /// End every line with `\r\n`.
+    ///
+    /// Turn this off when messages carry their own terminator, or for binary framing done by
+    /// the message itself, so lines are written exactly as formatted. Nothing is appended
+    /// instead: a message without a terminator runs into the next one, since the worker writes
+    /// several lines with a single call. Sinks which frame every line themselves, like
+    /// [`Framing::LengthPrefixed`], the Unix datagram socket, syslog and journald, are not
+    /// affected.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_append_newline(false)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// // The message ends with its own record separator
+    /// logger.log(&Record::builder().args(format_args!("first\x1e")).level(Level::Info).build());
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines(), ["INFO  first\x1e"]);
+    /// ```
+    ///
+    /// Default: `true`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_append_newline(mut self, append_newline: bool) -> Self {
+        self.options.append_newline = append_newline;
+        self
+    }
+
+    /// Write `prefix` at the start of every line, before the timestamp, e.g. the `@cee: ` cookie
+    /// rsyslog expects before JSON.
+    ///
+    /// The prefix is written once per record, so the continuation lines of a multi-line message
+    /// don't get it. It isn't colored, even with
+    /// [`with_full_line_colors`](#method.with_full_line_colors). Syslog and journald output
+    /// aren't affected. See [`with_line_suffix`](#method.with_line_suffix) for the end of the
+    /// line.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    /// use time::OffsetDateTime;
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_clock(|| OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap())
+    ///     .with_colors(false)
+    ///     .with_line_prefix("<<")
+    ///     .with_line_suffix(">>")
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// // The line ending comes after the suffix, `CaptureSink` removes it
+    /// assert_eq!(capture.lines(), ["<<2023-11-14T22:13:20.000Z INFO  [app] Hello>>"]);
+    /// ```
+    ///
+    /// Default: no prefix
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_line_prefix(mut self, prefix: &str) -> Self {
+        self.options.line_prefix = prefix.to_string();
+        self
+    }
+
+    /// Write `suffix` at the end of every line, before the line ending, e.g. a framing
+    /// character expected by a collector.
+    ///
+    /// Like the [prefix](#method.with_line_prefix), it is written once per record, after the
+    /// last line of a multi-line message, and isn't colored. Without a line ending, see
+    /// [`with_append_newline`](#method.with_append_newline), it is the last thing written.
+    ///
+    /// Default: no suffix
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_line_suffix(mut self, suffix: &str) -> Self {
+        self.options.line_suffix = suffix.to_string();
+        self
+    }
+
+    /// Display levels as a single character: `E`, `W`, `I`, `D` and `T`.
+    ///
+    /// Saves four columns on every line, e.g. for dense trace output. The level is still colored
+    /// like the full label. Labels set with [`with_level_labels`](#method.with_level_labels)
+    /// are cut to their first character too, in whichever order both are set, so labels should
+    /// start with distinct characters.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_compact_levels(true)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for level in [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace] {
+    ///     logger.log(&Record::builder().args(format_args!("Hello")).level(level).target("app").build());
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(
+    ///     capture.lines(),
+    ///     ["E [app] Hello", "W [app] Hello", "I [app] Hello", "D [app] Hello", "T [app] Hello"]
+    /// );
+    /// ```
+    ///
+    /// Default: `false`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_compact_levels(mut self, compact: bool) -> Self {
+        self.options.compact_levels = compact;
+        self
+    }
+
+    /// Don't display any timestamps.
+    ///
+    /// This method is only available if the `timestamps` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "timestamps")]
+    pub fn without_timestamps(mut self) -> Self {
+        self.options.timestamps = Timestamps::None;
+        self
+    }
+
+    /// Display timestamps using UTC.
+    ///
+    /// This method is only available if the `timestamps` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "timestamps")]
+    pub fn with_utc_timestamps(mut self) -> Self {
+        self.options.timestamps = Timestamps::Utc;
+        self
+    }
+
+    /// Display timestamps using a static UTC offset.
+    ///
+    /// This method is only available if the `timestamps` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "timestamps")]
+    pub fn with_utc_offset(mut self, offset: UtcOffset) -> Self {
+        self.options.timestamps = Timestamps::UtcOffset(offset);
+        self
+    }
+
+    /// Control the format used for timestamps.
+    ///
+    /// Without this, a default format is used depending on the timestamps type.
+    ///
+    /// The syntax for the format_description macro can be found in the
+    /// [`time` crate book](https://time-rs.github.io/book/api/format-description.html).
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "timestamps")]
+    pub fn with_timestamp_format(mut self, format: &'static [FormatItem<'static>]) -> Self {
+        self.options.timestamps_format = Some(format);
+        self
+    }
+
+    /// Set the number of digits of the second fraction in the default timestamp formats, from
+    /// `0` (whole seconds) to `9` (nanoseconds).
+    ///
+    /// Saves writing a whole format with [`with_timestamp_format`](#method.with_timestamp_format)
+    /// just to change the precision. A format set there takes precedence.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    /// use time::OffsetDateTime;
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_clock(|| OffsetDateTime::from_unix_timestamp_nanos(1_700_000_000_123_456_789).unwrap())
+    ///     .with_subsecond_digits(6)
+    ///     .with_colors(false)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines()[0], "2023-11-14T22:13:20.123456Z INFO  [app] Hello");
+    /// ```
+    ///
+    /// This method is only available if the `timestamps` feature is enabled.
+    ///
+    /// Default: `3` (milliseconds)
+    ///
+    /// # Panics
+    ///
+    /// Panics if `digits` is greater than `9`.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "timestamps")]
+    pub fn with_subsecond_digits(mut self, digits: u8) -> Self {
+        assert!(digits <= 9, "Subsecond digits must be between 0 and 9");
+        self.options.subsecond_digits = Some(digits);
+        self
+    }
+
+    /// Take timestamps from a closure instead of the system clock, e.g. to assert exact
+    /// timestamps in tests.
+    ///
+    /// The closure is called once per line, on the logging thread, and should return the time
+    /// in UTC. Timestamps are still converted to the offset set with
+    /// [`with_utc_offset`](#method.with_utc_offset). Without a custom clock, the system clock is
+    /// read directly, without going through a closure.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    /// use time::OffsetDateTime;
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_clock(|| OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap())
+    ///     .with_colors(false)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines()[0], "2023-11-14T22:13:20.000Z INFO  [app] Hello");
+    /// ```
+    ///
+    /// This method is only available if the `timestamps` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "timestamps")]
+    pub fn with_clock(
+        mut self,
+        clock: impl Fn() -> OffsetDateTime + Send + Sync + 'static,
+    ) -> Self {
+        self.options.clock = Some(clock::CustomClock(Arc::new(clock)));
+        self
+    }
+
+    /// Set the size of the internal channel buffer.
+    ///
+    /// The channel buffer holds log messages before they are written to output.
+    /// A larger buffer allows more messages to be queued during bursts of logging,
+    /// but uses more memory. If the buffer fills up, new log messages are handled according
+    /// to the [`FullPolicy`], dropped by default.
+    ///
+    /// A `size` of 0 selects a channel without a size limit, like
+    /// [`with_unbounded_channel`](#method.with_unbounded_channel). Any other size switches back
+    /// to a bounded channel if `with_unbounded_channel` was called before, the last of the two
+    /// calls wins.
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new().with_channel_size(0).build().unwrap();
+    /// assert_eq!(logger.queue_capacity(), usize::MAX);
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new().with_channel_size(8).build().unwrap();
+    /// assert_eq!(logger.queue_capacity(), 8);
+    /// ```
+    ///
+    /// Default: [`DEFAULT_CHANNEL_SIZE`] (16384 messages)
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_channel_size(mut self, size: usize) -> Self {
+        if size == 0 {
+            return self.with_unbounded_channel();
+        }
+
+        self.options.channel_size = size;
+        self.options.unbounded_channel = false;
+        self
+    }
+
+    /// Use a channel without a size limit, so logging never blocks and never drops messages.
+    ///
+    /// **Memory risk:** if messages are logged faster than the worker can write them (e.g. a
+    /// slow terminal, a stalled TCP collector), they accumulate in memory without bound until
+    /// the process runs out of memory. Use it for batch jobs which would rather trade memory for
+    /// complete logs, and keep [`queue_len`](NonBlockingLogger::queue_len) monitored.
+    ///
+    /// The [`FullPolicy`] has no effect in this mode. Same as `with_channel_size(0)`, this and
+    /// [`with_channel_size`](#method.with_channel_size) override each other, the last of the
+    /// two calls wins.
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_unbounded_channel(mut self) -> Self {
+        self.options.unbounded_channel = true;
+        self
+    }
+
+    /// Control what happens to new log messages when the channel is full.
+    ///
+    /// Dropped messages are counted, and once the worker makes room in the channel it writes
+    /// a single `dropped N messages due to backpressure` warning with the `log_nonblock` target.
+    ///
+    /// Default: [`FullPolicy::Drop`]
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder, Sink};
+    /// use std::time::Duration;
+    ///
+    /// struct SlowSink;
+    ///
+    /// impl Sink for SlowSink {
+    ///     fn write_all(&mut self, _buf: &[u8]) -> std::io::Result<()> {
+    ///         std::thread::sleep(Duration::from_millis(10));
+    ///         Ok(())
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_channel_size(1)
+    ///     .with_capture(&capture)
+    ///     .add_sink(SlowSink)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for i in 0..20 {
+    ///     logger.log(&Record::builder().args(format_args!("{}", i)).level(Level::Info).build());
+    /// }
+    /// logger.flush();
+    ///
+    /// let lines = capture.lines();
+    /// assert!(lines.len() < 20);
+    /// assert!(lines.iter().any(|line| line.contains("[log_nonblock] dropped")));
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_full_policy(mut self, policy: FullPolicy) -> Self {
+        self.options.full_policy = policy;
+        self
+    }
+
+    /// Write error records to STDERR from the logging thread when they can't be sent to the
+    /// worker, instead of losing them.
+    ///
+    /// Applies when the channel is full with [`FullPolicy::Drop`], and once the worker is gone.
+    /// The line is written as formatted for the outputs, with the same retries as the
+    /// [sync level](#method.with_sync_level): a non-blocking STDERR is polled until it's
+    /// writable again, so the caller isn't blocked for longer than the terminal or pipe needs to
+    /// drain. Colors are stripped unless STDERR is a terminal. Records at other levels are
+    /// still dropped, and counted in the `dropped` summary.
+    ///
+    /// Lines written this way can come out before lines logged earlier but still waiting in the
+    /// channel, and don't reach the other sinks. With
+    /// [thread-local batching](#method.with_thread_local_batching), the error lines of a batch
+    /// which doesn't fit are written and only the others are dropped.
+    ///
+    /// ```
+    /// use log_nonblock::{FullPolicy, NonBlockingLoggerBuilder};
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_full_policy(FullPolicy::Drop)
+    ///     .with_error_fallback(true)
+    ///     .with_capture(&Default::default())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// Default: `false`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_error_fallback(mut self, fallback: bool) -> Self {
+        self.options.error_fallback = fallback;
+        self
+    }
+
+    /// Ping `notify` when the channel is close to full, so the application can slow down its
+    /// own logging instead of blocking or losing records, see [`FullPolicy`].
+    ///
+    /// The logger pings once the channel is filled to 75% of its capacity, as seen by a call to
+    /// `log()`. Pings are coalesced: after one, the next is only sent once a call to `log()`
+    /// sees the channel back to 50%, however many records are logged in between. A channel of
+    /// a few messages pings once it holds at least one, and again after it was seen empty.
+    /// Pings are best effort, as the channel length is sampled without synchronization with
+    /// the worker, and nothing is sent while nobody logs. An
+    /// [unbounded](#method.with_unbounded_channel) channel never pings.
+    ///
+    /// ```
+    /// use log_nonblock::{FullPolicy, NonBlockingLoggerBuilder};
+    ///
+    /// let (notify, pings) = std::sync::mpsc::channel();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_full_policy(FullPolicy::Drop)
+    ///     .with_backpressure_notify(notify)
+    ///     .with_capture(&Default::default())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// // Nothing is pinged while the worker keeps up
+    /// assert!(pings.try_recv().is_err());
+    /// ```
+    ///
+    /// Default: no notification
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_backpressure_notify(mut self, notify: std::sync::mpsc::Sender<()>) -> Self {
+        self.options.backpressure = Some(Arc::new(backpressure::Backpressure::new(notify)));
+        self
+    }
+
+    /// Write records at `level` or more severe synchronously, bypassing the channel.
+    ///
+    /// Such records are written to the [output](#method.with_output) stream by the logging
+    /// thread itself, waiting for the output to accept them, so they are never dropped under
+    /// backpressure. Less severe records still go through the worker and can be dropped, see
+    /// [`with_full_policy`](#method.with_full_policy).
+    ///
+    /// A synchronous line is written immediately, so it can appear before lines which were
+    /// logged earlier but are still waiting in the channel, unless
+    /// [`with_ordered_sync`](#method.with_ordered_sync) is set. The sinks added with
+    /// [`add_sink`](#method.add_sink), the [error file](#method.with_error_file), the
+    /// [ring buffer](#method.with_ring_buffer) and [`with_on_log`](#method.with_on_log) still
+    /// get a copy of the line from the worker. The copy is sent through the channel like any
+    /// other line, so it follows the [full policy](#method.with_full_policy).
+    ///
+    /// Only applies when the logger writes to STDOUT/STDERR, with a file, TCP, syslog or
+    /// capture output all records go through the worker.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder, Output};
+    /// # #[cfg(unix)] {
+    /// use std::io::Read;
+    /// use std::os::fd::FromRawFd;
+    ///
+    /// // Replace STDOUT with a pipe, to read what is written to it without waiting
+    /// let mut fds = [0; 2];
+    /// unsafe {
+    ///     assert_eq!(libc::pipe(fds.as_mut_ptr()), 0);
+    ///     libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK);
+    ///     libc::dup2(fds[1], libc::STDOUT_FILENO);
+    /// }
+    /// let mut stdout = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+    ///
+    /// let copies = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_output(Output::Stdout)
+    ///     .with_sync_level(Level::Error)
+    ///     .add_sink(copies.clone())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Disk full")).level(Level::Error).build());
+    ///
+    /// // Already written when `log()` returns
+    /// let mut written = [0; 64];
+    /// let len = stdout.read(&mut written).unwrap();
+    /// assert_eq!(&written[..len], b"ERROR Disk full\r\n");
+    ///
+    /// // The other sinks get it from the worker
+    /// logger.flush();
+    /// assert_eq!(copies.lines(), ["ERROR Disk full"]);
+    /// # }
+    /// ```
+    ///
+    /// Default: all records are written by the worker
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_sync_level(mut self, level: Level) -> Self {
+        self.options.sync_level = Some(level);
+        self
+    }
+
+    /// Flush the outputs as soon as the worker has written a record at `level` or more severe.
+    ///
+    /// Important lines then reach the OS, or the network, without waiting for an explicit
+    /// [`flush`](log::Log::flush), which matters for sinks buffering their writes and when the
+    /// process may crash right after. The logging thread doesn't wait, so this is much lighter
+    /// than [`with_sync_level`](#method.with_sync_level), but each flush costs the worker a
+    /// call to every sink.
+    ///
+    /// Lines written together with the record in the same batch are flushed with it. A record
+    /// sent to a sink of [`route_target`](#method.route_target) only flushes that sink.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{NonBlockingLoggerBuilder, Sink};
+    /// use std::io::{BufWriter, Write};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// /// Keeps lines in memory until it is flushed
+    /// struct BufferedFile(BufWriter<std::fs::File>);
+    ///
+    /// impl Sink for BufferedFile {
+    ///     fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+    ///         self.0.write_all(buf)
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         self.0.flush()
+    ///     }
+    /// }
+    ///
+    /// let path = std::env::temp_dir().join(format!("log_nonblock_flush_on_level_{}.log", std::process::id()));
+    /// let file = std::fs::File::create(&path).unwrap();
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_capture(&Default::default())
+    ///     .add_sink(BufferedFile(BufWriter::new(file)))
+    ///     .with_flush_on_level(Level::Error)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Disk full")).level(Level::Error).target("app").build());
+    ///
+    /// // No call to flush, the worker flushes the file once it has written the error
+    /// let started = Instant::now();
+    /// while !std::fs::read_to_string(&path).unwrap().contains("[app] Disk full") {
+    ///     assert!(started.elapsed() < Duration::from_secs(5), "the error wasn't flushed");
+    ///     std::thread::sleep(Duration::from_millis(1));
+    /// }
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    ///
+    /// Default: the outputs are only flushed by [`flush`](log::Log::flush) and on shutdown
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_flush_on_level(mut self, level: Level) -> Self {
+        self.options.flush_level = Some(level);
+        self
+    }
+
+    /// Keep synchronous lines in order with the lines logged before them.
+    ///
+    /// Before writing a record at the [sync level](#method.with_sync_level), the logging thread
+    /// sends a flush through the channel and waits until the worker has written and flushed
+    /// every line logged before it. The output then reads in the order the records were logged,
+    /// e.g. an error always comes after the info lines which led to it.
+    ///
+    /// The cost is latency: each synchronous record waits for the whole backlog of the channel
+    /// to be written, and for the outputs to be flushed. Under load that is much longer than
+    /// writing the line itself, so keep the sync level to rare, severe records.
+    ///
+    /// Has no effect without [`with_sync_level`](#method.with_sync_level). See the
+    /// `ordered_sync` example for a test of the ordering.
+    ///
+    /// Default: `false`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_ordered_sync(mut self, ordered: bool) -> Self {
+        self.options.ordered_sync = ordered;
+        self
+    }
+
+    /// Report calls to `log()` which take longer than [`BLOCKING_THRESHOLD`].
+    ///
+    /// Logging is expected to return in microseconds. A call which takes a millisecond or more
+    /// waited on something: a full channel with [`FullPolicy::Block`], a record written
+    /// synchronously because of the [sync level](#method.with_sync_level), a contended
+    /// [deduplication](#method.with_dedup) lock or a slow [filter](#method.with_filter). The
+    /// first such call is reported once on STDERR, with its duration and the state of the
+    /// channel, to help find why a hot path is slow without a profiler.
+    ///
+    /// Each call to `log()` reads the clock twice, which is why this is off by default, and why
+    /// it only works in debug builds: with `debug_assertions` disabled the option is ignored
+    /// and costs nothing. See the `detect_blocking` example.
+    ///
+    /// Default: `false`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_detect_blocking(mut self, detect: bool) -> Self {
+        self.options.detect_blocking = detect.then(|| Arc::new(AtomicBool::new(false)));
+        self
+    }
+
+    /// Write every record synchronously, without a worker thread or channel.
+    ///
+    /// Meant for small command line tools, where the worker isn't worth it and log lines should
+    /// interleave with direct `println!` output exactly in the order they were written. Each
+    /// record is written to the [output](#method.with_output) stream by the logging thread, like
+    /// with [`with_sync_level`](#method.with_sync_level), retrying until the stream accepts it.
+    ///
+    /// The tradeoff is that nothing is buffered: a slow output blocks every call to `log()`.
+    /// [`flush`](log::Log::flush) returns at once, as there is nothing pending, and
+    /// [`shutdown`](NonBlockingLogger::shutdown) only marks the logger as stopped. With the
+    /// `nonblock-io` feature the stream is left in blocking mode.
+    ///
+    /// Only STDOUT/STDERR can be written this way, so [`build`](#method.build) returns an error
+    /// when a file, TCP, syslog, capture or other sink is set.
+    /// [`init_with_handle`](#method.init_with_handle) returns an error too, as there is no thread
+    /// to join.
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_synchronous(true)
+    ///     .with_worker_thread_name("sync-worker")
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(logger.is_healthy());
+    ///
+    /// #[cfg(target_os = "linux")]
+    /// {
+    /// let workers = std::fs::read_dir("/proc/self/task")
+    ///     .unwrap()
+    ///     .map(|task| std::fs::read_to_string(task.unwrap().path().join("comm")).unwrap())
+    ///     .filter(|name| name.trim() == "sync-worker")
+    ///     .count();
+    /// assert_eq!(workers, 0);
+    /// }
+    /// ```
+    ///
+    /// Default: `false`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_synchronous(mut self, synchronous: bool) -> Self {
+        self.options.synchronous = synchronous;
+        self
+    }
+
+    /// Give up on a message once the output hasn't accepted any data for `timeout`.
+    ///
+    /// By default the worker waits indefinitely for STDOUT/STDERR (or a TCP collector) to become
+    /// writable. If the consumer never reads again, the worker parks forever and the channel
+    /// fills up. With a timeout the stalled message is dropped, the error is reported to STDERR
+    /// and the worker moves on to the next message.
+    ///
+    /// Default: no timeout
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_write_timeout(mut self, timeout: Duration) -> Self {
+        self.options.write_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the name of the worker thread which writes the log lines, as shown in debuggers,
+    /// profilers and panic messages.
+    ///
+    /// Default: [`DEFAULT_WORKER_THREAD_NAME`]
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{NonBlockingLoggerBuilder, Sink};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// /// Records the name of the thread writing to it
+    /// #[derive(Clone, Default)]
+    /// struct ThreadNameSink(Arc<Mutex<Option<String>>>);
+    ///
+    /// impl Sink for ThreadNameSink {
+    ///     fn write_all(&mut self, _buf: &[u8]) -> std::io::Result<()> {
+    ///         *self.0.lock().unwrap() = std::thread::current().name().map(str::to_string);
+    ///         Ok(())
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let sink = ThreadNameSink::default();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_worker_thread_name("app-logger")
+    ///     .add_sink(sink.clone())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// assert_eq!(sink.0.lock().unwrap().as_deref(), Some("app-logger"));
+    /// ```
+    ///
+    /// A name containing a null byte can't be given to a thread, building the logger then
+    /// returns [`NonBlockingLoggerError::SpawnWorker`]:
+    ///
+    /// ```
+    /// use log_nonblock::{NonBlockingLoggerBuilder, NonBlockingLoggerError};
+    ///
+    /// let result = NonBlockingLoggerBuilder::new()
+    ///     .with_worker_thread_name("app\0logger")
+    ///     .build();
+    ///
+    /// assert!(matches!(result, Err(NonBlockingLoggerError::SpawnWorker(_))));
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_worker_thread_name(mut self, name: &str) -> Self {
+        self.options.worker_thread_name = name.to_string();
+        self
+    }
+
+    /// Set the number of spare message buffers kept for reuse.
+    ///
+    /// Lines up to 128 bytes are assembled in place and sent to the worker without a heap
+    /// buffer. Longer lines, and messages with
+    /// [deferred formatting](NonBlockingLoggerBuilder::with_deferred_formatting), need a
+    /// `String`. Instead of allocating a fresh one each time, the logger takes a spare buffer
+    /// from a pool and the worker thread hands it back once the message has been written.
+    /// Buffers that grew very large (more than [`MAX_POOLED_BUFFER_CAPACITY`] bytes) are not
+    /// returned to the pool.
+    ///
+    /// Pass `0` to disable pooling and allocate a new `String` for every long message.
+    ///
+    /// Default: [`DEFAULT_BUFFER_POOL_SIZE`] (1024 buffers)
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_buffer_pool_size(mut self, size: usize) -> Self {
+        self.options.buffer_pool_size = size;
+        self
+    }
+
+    /// Let the worker write all lines waiting in the channel in batches of up to `max_bytes`,
+    /// instead of at most two lines at a time.
+    ///
+    /// The worker only batches lines which are already waiting, it never waits for more to fill
+    /// a batch. Once a batch reaches `max_bytes` it is written even if more lines are waiting,
+    /// so a line waits at most for the write of one batch after it reached the worker. A larger
+    /// budget means fewer write calls under load, a smaller one shows lines sooner. A batch
+    /// can exceed the budget by its last line.
+    ///
+    /// Batching only decides how lines are grouped into writes. Data buffered by a sink is
+    /// written out on [`flush`](log::Log::flush), so together the batch budget and the flush
+    /// calls bound how long a line takes to reach its destination.
+    ///
+    /// Default: disabled, the worker writes one line, or two small ones, at a time
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{NonBlockingLoggerBuilder, Sink};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// /// Records the size of each write, blocks the worker while the test holds the gate
+    /// struct WriteSizes(Arc<Mutex<()>>, Arc<Mutex<Vec<usize>>>);
+    ///
+    /// impl Sink for WriteSizes {
+    ///     fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+    ///         drop(self.0.lock());
+    ///         self.1.lock().unwrap().push(buf.len());
+    ///         Ok(())
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let gate = Arc::new(Mutex::new(()));
+    /// let sizes = Arc::new(Mutex::new(Vec::new()));
+    /// let closed = gate.lock().unwrap();
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_max_batch_bytes(1024)
+    ///     .add_sink(WriteSizes(gate.clone(), sizes.clone()))
+    ///     .with_capture(&Default::default())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for i in 0..100 {
+    ///     logger.log(&Record::builder().args(format_args!("Message {}", i)).level(Level::Info).build());
+    /// }
+    ///
+    /// drop(closed);
+    /// logger.flush();
+    ///
+    /// let sizes = sizes.lock().unwrap();
+    /// assert!(sizes.len() > 1);
+    /// assert!(sizes.iter().all(|&size| size < 1024 + 100));
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_max_batch_bytes(mut self, max_bytes: usize) -> Self {
+        self.options.max_batch_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Set the size from which a line is written on its own, instead of together with the next
+    /// waiting line.
+    ///
+    /// Without [`with_max_batch_bytes`](#method.with_max_batch_bytes), the worker copies a line
+    /// shorter than `threshold` and the next waiting line into one buffer and writes both with a
+    /// single call. Longer lines are written directly, without the copy. When most lines are a
+    /// bit longer than the default, a higher threshold saves write calls, a lower one saves
+    /// copies. The output is the same either way.
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// // Lines are mostly a bit longer than the default threshold
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_large_message_threshold(4096)
+    ///     .with_capture(&Default::default())
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// Default: [`DEFAULT_LARGE_MESSAGE_THRESHOLD`] (1280 bytes)
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_large_message_threshold(mut self, threshold: usize) -> Self {
+        self.options.large_message_threshold = threshold;
+        self
+    }
+
+    /// Include the module of the record in every line, as `module=...` right after the target:
+    /// `[http module=myapp::server]`.
+    ///
+    /// Useful when records set a custom target, which otherwise hides the module they come
+    /// from. Records without a module path are logged without it.
+    ///
+    /// Default: `false`
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_module_path(true)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for module in [Some("myapp::server"), None] {
+    ///     logger.log(
+    ///         &Record::builder()
+    ///             .args(format_args!("Hello"))
+    ///             .level(Level::Info)
+    ///             .target("http")
+    ///             .module_path(module)
+    ///             .build(),
+    ///     );
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(
+    ///     capture.lines(),
+    ///     ["INFO  [http module=myapp::server] Hello", "INFO  [http] Hello"]
+    /// );
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_module_path(mut self, module_path: bool) -> Self {
+        self.options.module_path = module_path;
+        self
+    }
+
+    /// Include the source location of the record in every line, as `path/to/file.rs:42` right
+    /// after the target: `[target src/main.rs:42]`.
+    ///
+    /// Records without a file are logged without a location, a missing line number leaves
+    /// just the file.
+    ///
+    /// Default: `false`
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_source_location(true)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for (file, line) in [(Some("src/main.rs"), Some(42)), (Some("src/main.rs"), None), (None, None)] {
+    ///     logger.log(
+    ///         &Record::builder()
+    ///             .args(format_args!("Hello"))
+    ///             .level(Level::Info)
+    ///             .target("app")
+    ///             .file(file)
+    ///             .line(line)
+    ///             .build(),
+    ///     );
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(
+    ///     capture.lines(),
+    ///     [
+    ///         "INFO  [app src/main.rs:42] Hello",
+    ///         "INFO  [app src/main.rs] Hello",
+    ///         "INFO  [app] Hello",
+    ///     ]
+    /// );
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_source_location(mut self, source_location: bool) -> Self {
+        self.options.source_location = source_location;
+        self
+    }
+
+    /// Include the process ID in every line, as `pid=1234` inside the target bracket:
+    /// `[target pid=1234]`.
+    ///
+    /// Default: `false`
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_pid(true)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Started")).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// let expected = format!("[app pid={}] Started", std::process::id());
+    /// assert!(capture.lines()[0].ends_with(&expected));
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_pid(mut self, pid: bool) -> Self {
+        self.options.pid = pid;
+        self
+    }
+
+    /// Prefix every line with the seconds elapsed since the logger was built, e.g. `[  12.345] `.
+    ///
+    /// The uptime is read from a monotonic clock, so it never goes backwards, unlike wall-clock
+    /// timestamps. It doesn't depend on the `timestamps` feature. When wall-clock timestamps
+    /// are enabled too, the uptime is written before them.
+    ///
+    /// Default: disabled
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_uptime_timestamps()
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for message in ["First", "Second"] {
+    ///     logger.log(&Record::builder().args(format_args!("{}", message)).level(Level::Info).target("app").build());
+    /// }
+    /// logger.flush();
+    ///
+    /// let uptimes: Vec<f64> = capture
+    ///     .lines()
+    ///     .iter()
+    ///     .map(|line| line[1..line.find(']').unwrap()].trim().parse().unwrap())
+    ///     .collect();
+    /// assert_eq!(uptimes.len(), 2);
+    /// assert!(uptimes[0] <= uptimes[1]);
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_uptime_timestamps(mut self) -> Self {
+        self.options.uptime_timestamps = true;
+        self
+    }
+
+    /// Include the hostname in every line, as `host=myhost` inside the target bracket, after the
+    /// PID: `[target pid=1234 host=myhost]`.
+    ///
+    /// The hostname is read once when the logger is built. With syslog output it fills the
+    /// HOSTNAME field of the message instead.
+    ///
+    /// Default: `false`
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_pid(true)
+    ///     .with_hostname(true)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for _ in 0..2 {
+    ///     logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target("app").build());
+    /// }
+    /// logger.flush();
+    ///
+    /// let lines = capture.lines();
+    /// let prefix = format!("INFO  [app pid={} host=", std::process::id());
+    /// let host = lines[0].strip_prefix(&prefix).unwrap().strip_suffix("] Hello").unwrap();
+    /// assert!(!host.is_empty());
+    ///
+    /// // Both lines carry the name read by `build()`
+    /// assert_eq!(lines[1], lines[0]);
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_hostname(mut self, hostname: bool) -> Self {
+        self.options.hostname = hostname;
+        self
+    }
+
+    /// Write a constant tag in every line, e.g. `svc=checkout` to tell services apart in
+    /// aggregated logs.
+    ///
+    /// The tag always goes right after the level, before the target bracket:
+    /// `2024-01-01T00:00:00.000Z INFO  svc=checkout [target] Message`. With syslog output it
+    /// isn't written.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_tag("svc=checkout")
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for (level, target) in [(Level::Info, "app"), (Level::Warn, "app::db")] {
+    ///     logger.log(&Record::builder().args(format_args!("Hello")).level(level).target(target).build());
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(
+    ///     capture.lines(),
+    ///     ["INFO  svc=checkout [app] Hello", "WARN  svc=checkout [app::db] Hello"]
+    /// );
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_tag(mut self, tag: &str) -> Self {
+        self.options.tag = Some(tag.to_string());
+        self
+    }
+
+    /// Move assembling of the log line from the calling thread to the worker thread.
+    ///
+    /// By default `log()` renders the timestamp, the colored level and the whole line on the
+    /// thread that logs. With deferred formatting the calling thread only renders the message
+    /// arguments and sends an owned copy of the record, leaving the rest to the worker.
+    ///
+    /// The timestamp and the thread name are still captured at log time, so output is the same
+    /// in both modes. The cost moves rather than disappears: callers get cheaper, while the worker
+    /// spends more time per message and can fall behind sooner under sustained load.
+    /// See `BENCH_RESULTS.md` for numbers.
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_deferred_formatting(mut self, deferred: bool) -> Self {
+        self.options.deferred_formatting = deferred;
+        self
+    }
+
+    /// Collect lines in a small batch per thread, and send each batch to the worker at once.
+    ///
+    /// Every message sent through the channel is an atomic operation on state shared by all
+    /// logging threads. With many threads logging at a high rate they contend on it; batching
+    /// sends one message per 32 lines instead of one per line. The batch is sent once it's
+    /// full, and on [`flush`](log::Log::flush), [`flush_sink`](NonBlockingLogger::flush_sink),
+    /// [`stop`](NonBlockingLogger::stop) and [`reopen`](NonBlockingLogger::reopen), which send
+    /// the batches of every thread. A batch left by a thread which exited, or which stopped
+    /// logging, is written by the worker once nothing was logged for 100ms.
+    ///
+    /// The lines of a thread stay in order, but lines of different threads are no longer
+    /// written in the order they were logged: the lines of a thread appear together, up to a
+    /// batch later than those of a thread which logs more. Records which don't go through the
+    /// batch overtake the lines waiting in it: those sent to a [route](#method.route_target),
+    /// written at the [sync level](#method.with_sync_level) or formatted by the worker with
+    /// [deferred formatting](#method.with_deferred_formatting). With [`FullPolicy::Drop`], a
+    /// batch which doesn't fit in the channel is dropped whole, and its lines counted.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_capture(&capture)
+    ///     .with_thread_local_batching(true)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let log = |logger: &dyn Log, i| {
+    ///     logger.log(&Record::builder().args(format_args!("Line {}", i)).level(Level::Info).build());
+    /// };
+    ///
+    /// // A thread which exits before its batch is full
+    /// let worker_logger = logger.clone();
+    /// std::thread::spawn(move || (0..5).for_each(|i| log(&worker_logger, i))).join().unwrap();
+    ///
+    /// // Its lines are written once the worker has been idle for a while
+    /// let started = Instant::now();
+    /// while capture.lines().len() < 5 {
+    ///     assert!(started.elapsed() < Duration::from_secs(5), "the batch was lost");
+    ///     std::thread::sleep(Duration::from_millis(10));
+    /// }
+    ///
+    /// (5..8).for_each(|i| log(&logger, i));
+    /// logger.flush();
+    /// assert_eq!(capture.lines().len(), 8);
+    /// ```
+    ///
+    /// Has no effect with [`with_synchronous`](#method.with_synchronous).
+    ///
+    /// Default: `false`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_thread_local_batching(mut self, batching: bool) -> Self {
+        self.options.thread_batches = batching.then(Default::default);
+        self
+    }
+
+    /// Limit the length of the message, in bytes.
+    ///
+    /// Longer messages are cut to at most `max_len` bytes, on a character boundary, and
+    /// [`TRUNCATED_MARKER`] is appended. Formatting of the message stops at the limit, so a
+    /// runaway message costs neither the time to render it nor room in the channel.
+    /// The limit applies to the message and its key-value pairs, not to the timestamp, level
+    /// and target around it.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_max_message_len(8)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let long = "x".repeat(1024 * 1024);
+    /// logger.log(&Record::builder().args(format_args!("{}", long)).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// assert!(capture.lines()[0].ends_with("[app] xxxxxxxx…[truncated]"));
+    /// ```
+    ///
+    /// Default: no limit
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_max_message_len(mut self, max_len: usize) -> Self {
+        self.options.max_message_len = Some(max_len);
+        self
+    }
+
+    /// Write log lines to a file instead of STDOUT/STDERR.
+    ///
+    /// The file is created if it doesn't exist and appended to otherwise. If it can't be opened,
+    /// an error is reported to STDERR and the logger falls back to STDOUT/STDERR, unless
+    /// [`with_validate_on_init`](#method.with_validate_on_init) is set.
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_file<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.file = Some(file::FileOptions {
+            path: path.as_ref().to_path_buf(),
+            max_bytes: None,
+            max_files: 0,
+            #[cfg(feature = "compression")]
+            compress: false,
+        });
+        self
+    }
+
+    /// Write log lines to a file, which is rotated once it reaches `max_bytes`.
+    ///
+    /// When a write would grow the file past `max_bytes`, the worker thread renames `app.log` to
+    /// `app.log.1`, shifting older files up to `app.log.<max_files>` and deleting the oldest one,
+    /// then continues with a fresh `app.log`. With `max_files` set to `0` no rotated files are kept.
+    ///
+    /// A single message larger than `max_bytes` is never split, it is written whole to a fresh file.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let dir = std::env::temp_dir().join(format!("log_nonblock_rotation_{}", std::process::id()));
+    /// std::fs::create_dir_all(&dir).unwrap();
+    ///
+    /// // Every line is 24 bytes long, so 4 lines fit into a file
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_rotating_file(dir.join("app.log"), 100, 2)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for i in 10..30 {
+    ///     logger.log(&Record::builder()
+    ///         .level(Level::Info)
+    ///         .target("app")
+    ///         .args(format_args!("message {}", i))
+    ///         .build());
+    ///     logger.flush();
+    /// }
+    ///
+    /// let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+    /// assert!(read("app.log").ends_with("message 29\r\n"));
+    /// assert!(read("app.log.1").starts_with("INFO  [app] message 22"));
+    /// assert!(read("app.log.2").starts_with("INFO  [app] message 18"));
+    /// assert!(!dir.join("app.log.3").exists());
+    ///
+    /// logger.shutdown().unwrap();
+    /// std::fs::remove_dir_all(&dir).unwrap();
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_rotating_file<P: AsRef<Path>>(
+        mut self,
+        path: P,
+        max_bytes: u64,
+        max_files: usize,
+    ) -> Self {
+        self.options.file = Some(file::FileOptions {
+            path: path.as_ref().to_path_buf(),
+            max_bytes: Some(max_bytes),
+            max_files,
+            #[cfg(feature = "compression")]
+            compress: false,
+        });
+        self
+    }
+
+    /// Gzip rotated log files, `app.log.1` becomes `app.log.1.gz`.
+    ///
+    /// Compression runs on a short-lived helper thread, so the worker keeps draining the channel
+    /// while a rotated file is compressed. Rotations which happen while a previous compression is
+    /// still running are queued and compressed in order. Until its compression is done, a rotated
+    /// file is kept as `app.log.rotating.N` next to the log file.
+    ///
+    /// Has no effect without [`with_rotating_file`](#method.with_rotating_file).
+    ///
+    /// This method is only available if the `compression` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(feature = "compression")]
+    pub fn with_compress_rotated(mut self, compress: bool) -> Self {
+        self.options.compress_rotated = compress;
+        self
+    }
+
+    /// Send log records to the local syslog daemon instead of STDOUT/STDERR.
+    ///
+    /// Each record is formatted as an [RFC 5424](https://datatracker.ietf.org/doc/html/rfc5424)
+    /// message, with the severity derived from the record level (error=3, warn=4, info=6,
+    /// debug/trace=7, see [`with_severity_map`](#method.with_severity_map)). The worker thread
+    /// owns the socket and reconnects if the daemon goes away.
+    ///
+    /// Messages are sent to `/dev/log`, or to UDP `127.0.0.1:514` if that socket doesn't exist.
+    /// If neither can be opened when the logger is built, an error is reported and the logger
+    /// falls back to writing the messages to STDERR, still formatted as RFC 5424.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, Facility, NonBlockingLoggerBuilder, SeverityMap};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_syslog(Facility::Local7, "myapp")
+    ///     // Report warnings as notices (5), the invalid trace severity is written as 7
+    ///     .with_severity_map(SeverityMap::new(3, 5, 6, 7, 100))
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for level in [Level::Error, Level::Warn, Level::Trace] {
+    ///     logger.log(&Record::builder().args(format_args!("Disk full")).level(level).build());
+    /// }
+    /// logger.flush();
+    ///
+    /// // The priority is the facility times 8 plus the severity
+    /// let lines = capture.lines();
+    /// assert!(lines[0].starts_with("<187>1 "));
+    /// assert!(lines[1].starts_with("<189>1 "));
+    /// assert!(lines[2].starts_with("<191>1 "));
+    /// assert!(lines[2].ends_with("] Disk full"));
+    /// ```
+    ///
+    /// This method is only available on Unix if the `syslog` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(all(unix, feature = "syslog"))]
+    pub fn with_syslog(mut self, facility: Facility, app_name: &str) -> Self {
+        self.options.syslog = Some(syslog::SyslogOptions {
+            facility,
+            app_name: app_name.to_string(),
+        });
+        self
+    }
+
+    /// Set the numeric severity written for each level by outputs which don't use level names.
+    ///
+    /// [Syslog](#method.with_syslog) output puts the severity in the priority of every message,
+    /// together with the facility, and [journald](#method.with_journald) output in the
+    /// `PRIORITY` field. Use [`SeverityMap::new`] for a collector which expects other values.
+    ///
+    /// The map applies to the primary output of the logger, the only one which can be syslog or
+    /// journald. Sinks added with [`add_sink`](#method.add_sink) get the formatted lines and
+    /// don't use it. To send records to two collectors expecting different severities, build a
+    /// logger for each.
+    ///
+    /// ```
+    /// use log::Level;
+    /// use log_nonblock::{NonBlockingLoggerBuilder, SeverityMap};
+    ///
+    /// // Report warnings as notices (5)
+    /// let map = SeverityMap::new(3, 5, 6, 7, 7);
+    /// assert_eq!(map.get(Level::Warn), 5);
+    ///
+    /// let builder = NonBlockingLoggerBuilder::new().with_severity_map(map);
+    /// ```
+    ///
+    /// Default: [`SeverityMap::SYSLOG`]
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_severity_map(mut self, map: SeverityMap) -> Self {
+        self.options.severity_map = map;
+        self
+    }
+
+    /// Send log records to journald instead of STDOUT/STDERR.
+    ///
+    /// Each record becomes a journal entry with the `PRIORITY` (see
+    /// [`with_severity_map`](#method.with_severity_map)), `TARGET` and `MESSAGE` fields, plus
+    /// `CODE_MODULE`, `CODE_FILE` and `CODE_LINE` when they are enabled. With the `kv` feature,
+    /// every key-value pair is also added as a field named after the key in uppercase, unless
+    /// formatting is deferred. Entries can then be filtered by target with
+    /// `journalctl TARGET=app::db`. Color codes are removed from the values unless
+    /// [`with_strip_ansi`](#method.with_strip_ansi) is turned off.
+    ///
+    /// Entries are sent as datagrams of the native protocol to `/run/systemd/journal/socket`,
+    /// the worker thread owns the socket and reconnects if journald is restarted. On hosts
+    /// without systemd the socket doesn't exist: an error is reported when the logger is built
+    /// and it writes plain lines to STDOUT/STDERR instead.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// // The capture gets each entry before it is encoded, with a field on each line
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_journald()
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Warn).target("app").build());
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines(), ["PRIORITY=4\nTARGET=app\nMESSAGE=Hello\n"]);
+    /// ```
+    ///
+    /// This method is only available on Linux if the `journald` feature is enabled.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(all(target_os = "linux", feature = "journald"))]
+    pub fn with_journald(mut self) -> Self {
+        self.options.journald = true;
+        self
+    }
+
+    /// Send log lines to a log collector over TCP instead of STDOUT/STDERR.
+    ///
+    /// The worker thread connects to `addr` and writes each formatted line to it. If the
+    /// connection drops, it reconnects with an exponential backoff (100ms up to 5s) and writes
+    /// the interrupted message again. During an outage messages are buffered in the channel,
+    /// once it fills up they are handled according to the [`FullPolicy`].
+    ///
+    /// On Unix the socket is switched to non-blocking mode and written with the same retry logic
+    /// as STDOUT.
+    ///
+    /// The first connection is only made by the worker when it writes the first line, and an
+    /// unreachable collector is retried forever, see
+    /// [`with_validate_on_init`](#method.with_validate_on_init) to connect when the logger is built.
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_tcp(mut self, addr: SocketAddr) -> Self {
+        self.options.tcp = Some(addr);
+        self
+    }
+
+    /// Send log lines to a local log shipper listening on a Unix stream socket at `path`.
+    ///
+    /// Works like [`with_tcp`](#method.with_tcp), without the network stack: the worker
+    /// connects when it writes the first line, reconnects with the same backoff when the
+    /// connection drops, and writes to the socket in non-blocking mode with the same retry
+    /// logic as STDOUT. Lines are delimited according to [`with_framing`](#method.with_framing).
+    ///
+    /// A file or TCP output takes precedence over the socket.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    /// use std::io::{BufRead, BufReader};
+    /// use std::os::unix::net::UnixListener;
+    ///
+    /// let path = std::env::temp_dir().join(format!("log_nonblock_unix_{}.sock", std::process::id()));
+    /// let _ = std::fs::remove_file(&path);
+    /// let listener = UnixListener::bind(&path).unwrap();
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_unix_socket(&path)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Shipped")).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// let (stream, _) = listener.accept().unwrap();
+    /// let line = BufReader::new(stream).lines().next().unwrap().unwrap();
+    /// assert_eq!(line.trim_end(), "INFO  [app] Shipped");
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    ///
+    /// This method is only available on Unix.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(unix)]
+    pub fn with_unix_socket<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.unix_socket = Some(unix_socket::UnixSocket::Stream(unix_socket::UnixPath(
+            path.as_ref().to_path_buf(),
+        )));
+        self
+    }
+
+    /// Send log lines to a local log shipper listening on a Unix datagram socket at `path`.
+    ///
+    /// Unlike a [stream socket](#method.with_unix_socket), every line is sent on its own as one
+    /// datagram, without its line ending, so multi-line messages arrive whole and
+    /// [`with_framing`](#method.with_framing) has no effect. A datagram is sent completely or
+    /// not at all, but can't be larger than the send buffer of the socket (usually around
+    /// 200KB on Linux), see [`with_max_message_len`](#method.with_max_message_len). If a send
+    /// fails, e.g. because the shipper was restarted, the socket is reconnected once and the
+    /// line sent again, otherwise the error is reported and the line lost.
+    ///
+    /// A file or TCP output takes precedence over the socket.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    /// use std::os::unix::net::UnixDatagram;
+    ///
+    /// let path = std::env::temp_dir().join(format!("log_nonblock_datagram_{}.sock", std::process::id()));
+    /// let _ = std::fs::remove_file(&path);
+    /// let receiver = UnixDatagram::bind(&path).unwrap();
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_unix_datagram(&path)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("First\nSecond")).level(Level::Info).target("app").build());
+    /// logger.flush();
+    ///
+    /// let mut datagram = [0; 64];
+    /// let len = receiver.recv(&mut datagram).unwrap();
+    /// assert_eq!(&datagram[..len], b"INFO  [app] First\nSecond");
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    ///
+    /// This method is only available on Unix.
+    #[must_use = "You must call init() to begin logging"]
+    #[cfg(unix)]
+    pub fn with_unix_datagram<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.unix_socket = Some(unix_socket::UnixSocket::Datagram(unix_socket::UnixPath(
+            path.as_ref().to_path_buf(),
+        )));
+        self
+    }
+
+    /// Choose the standard stream the logger writes to, STDOUT or STDERR.
+    ///
+    /// Only used when there is no file, TCP, Unix socket or syslog output. With the `nonblock-io`
+    /// feature, the chosen stream is switched to non-blocking mode when the logger is built, and
+    /// back to blocking mode when the worker stops, unless it was already non-blocking before.
+    ///
+    /// Default: [`Output::Stdout`], or [`Output::Stderr`] with the `stderr` feature
+    ///
+    /// ```
+    /// use log_nonblock::{NonBlockingLoggerBuilder, Output};
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_output(Output::Stderr)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_output(mut self, output: Output) -> Self {
+        self.options.output = output;
+        self
+    }
+
+    /// Choose how lines are delimited on the TCP connection set with [`with_tcp`](#method.with_tcp),
+    /// or the Unix stream socket set with [`with_unix_socket`](#method.with_unix_socket).
+    ///
+    /// With [`Framing::LengthPrefixed`], each line is sent on its own, without the line ending,
+    /// after its length as a 4-byte big-endian integer. The collector can then read messages
+    /// which contain newlines, such as multi-line panics or stack traces, as a single message.
+    /// Other outputs are not affected.
+    ///
+    /// Default: [`Framing::Newline`]
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_framing(mut self, framing: Framing) -> Self {
+        self.options.framing = framing;
+        self
+    }
+
+    /// Open the output when the logger is built, and fail if it can't be opened.
+    ///
+    /// Without it a misconfigured output only shows up in a diagnostic on STDERR, or not at all:
+    /// a [file](#method.with_file) which can't be opened falls back to STDOUT/STDERR, like
+    /// [syslog](#method.with_syslog) and journald, and a [TCP collector](#method.with_tcp) is
+    /// only connected to by the worker, which retries forever. With it,
+    /// [`build`](#method.build) and [`init`](#method.init) return the error instead, close to
+    /// the configuration which caused it. The TCP connection is made once, with a 5 seconds
+    /// timeout, and kept for the first lines.
+    ///
+    /// Leave it off when the collector may come up after the application, the logger then
+    /// buffers the lines until it can connect. Timestamp formats need no validation, they are
+    /// checked at compile time by `time::macros::format_description!`.
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    /// use std::net::TcpListener;
+    ///
+    /// let missing = std::env::temp_dir().join("log_nonblock_missing_dir").join("app.log");
+    /// let result = NonBlockingLoggerBuilder::new()
+    ///     .with_file(&missing)
+    ///     .with_validate_on_init(true)
+    ///     .build();
+    /// assert!(result.unwrap_err().to_string().contains("Failed to open log file"));
+    ///
+    /// // Nothing listens on this port anymore
+    /// let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    /// let result = NonBlockingLoggerBuilder::new()
+    ///     .with_tcp(addr)
+    ///     .with_validate_on_init(true)
+    ///     .build();
+    /// assert!(result.unwrap_err().to_string().contains("Failed to connect to log collector"));
+    ///
+    /// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_tcp(listener.local_addr().unwrap())
+    ///     .with_validate_on_init(true)
+    ///     .build();
+    /// assert!(logger.is_ok());
+    /// ```
+    ///
+    /// A failed [`init`](#method.init) registers nothing, so it can be retried with another
+    /// configuration:
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let missing = std::env::temp_dir().join("log_nonblock_missing_dir").join("app.log");
+    /// let result = NonBlockingLoggerBuilder::new()
+    ///     .with_file(&missing)
+    ///     .with_validate_on_init(true)
+    ///     .init();
+    /// assert!(result.is_err());
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new().init();
+    /// assert!(logger.is_ok());
+    /// ```
+    ///
+    /// Default: `false`
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_validate_on_init(mut self, validate: bool) -> Self {
+        self.options.validate_on_init = validate;
+        self
+    }
+
+    /// Store log lines in `capture` instead of writing them to STDOUT/STDERR, a file, TCP or syslog.
+    ///
+    /// Useful to assert on logged lines in tests, see [`CaptureSink`]. Sinks added with
+    /// [`add_sink`](#method.add_sink) still receive every line.
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_capture(mut self, capture: &CaptureSink) -> Self {
+        self.capture = Some(capture.clone());
+        self
+    }
+
+    /// Write records whose target is `prefix` or one of its sub-modules to `sink`, instead of
+    /// the primary output and the sinks added with [`add_sink`](#method.add_sink).
+    ///
+    /// Targets match like in [`with_module_level`](#method.with_module_level): a route for
+    /// `audit` takes `audit` and `audit::login`, but not `auditor`. When several routes
+    /// match, the most specific one wins. Records of other targets are written as usual.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let audit = CaptureSink::new();
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .route_target("audit", audit.clone())
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for target in ["audit::login", "app", "auditor"] {
+    ///     logger.log(&Record::builder().args(format_args!("Hello")).level(Level::Info).target(target).build());
+    /// }
+    /// logger.flush();
+    ///
+    /// assert_eq!(audit.lines(), ["INFO  [audit::login] Hello"]);
+    /// assert_eq!(capture.lines(), ["INFO  [app] Hello", "INFO  [auditor] Hello"]);
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn route_target(mut self, prefix: &str, sink: impl Sink + 'static) -> Self {
+        self.routes.push((prefix.to_string(), Box::new(sink)));
+        self
+    }
+
+    /// Also keep the last `capacity` bytes of log lines in memory, for crash forensics.
+    ///
+    /// The worker writes every line into a buffer allocated once at this call, in addition to
+    /// the outputs. Once it is full, each new line overwrites the oldest bytes, so the buffer
+    /// never grows: it holds the most recent lines, the oldest one possibly cut at its start.
+    /// The buffer lives on the heap and is part of a core dump, and
+    /// [`NonBlockingLogger::dump_ring_buffer`] copies its contents at any time.
+    ///
+    /// Like other sinks which aren't terminals, color codes are removed unless set otherwise
+    /// with [`with_strip_ansi`](#method.with_strip_ansi).
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_ring_buffer(32)
+    ///     .with_capture(&CaptureSink::new())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for i in 0..10 {
+    ///     logger.log(&Record::builder().args(format_args!("Line {}", i)).level(Level::Info).build());
+    /// }
+    /// logger.flush();
+    ///
+    /// // Each line takes 14 bytes, only the end of the oldest one is left
+    /// assert_eq!(logger.dump_ring_buffer(), b" 7\r\nINFO  Line 8\r\nINFO  Line 9\r\n");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is 0.
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_ring_buffer(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "Ring buffer capacity must be greater than 0");
+        self.ring_buffer = Some(sink::RingBuffer::new(capacity));
+        self
+    }
+
+    /// Add a sink which receives every log line in addition to the primary output
+    /// (STDOUT/STDERR, file, TCP or syslog).
+    ///
+    /// All sinks receive identical bytes and are written by the worker thread one after another,
+    /// in the order they were added. A sink waiting for its output to become writable delays the
+    /// following sinks, but never the logging threads. An error in one sink is reported to STDERR
+    /// and doesn't prevent writing to the others.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{FileSink, NonBlockingLoggerBuilder, Sink};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct MemorySink(Arc<Mutex<Vec<u8>>>);
+    ///
+    /// impl Sink for MemorySink {
+    ///     fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+    ///         self.0.lock().unwrap().extend_from_slice(buf);
+    ///         Ok(())
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let path = std::env::temp_dir().join(format!("log_nonblock_sinks_{}.log", std::process::id()));
+    /// let _ = std::fs::remove_file(&path);
+    ///
+    /// let memory = MemorySink::default();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .add_sink(memory.clone())
+    ///     .add_sink(FileSink::open(&path).unwrap())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for i in 0..10 {
+    ///     logger.log(
+    ///         &Record::builder()
+    ///             .args(format_args!("Message {}", i))
+    ///             .level(Level::Info)
+    ///             .target("sinks")
+    ///             .build(),
+    ///     );
+    /// }
+    /// logger.flush();
+    ///
+    /// let written = std::fs::read(&path).unwrap();
+    /// assert!(!written.is_empty());
+    /// assert_eq!(written, *memory.0.lock().unwrap());
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn add_sink(mut self, sink: impl Sink + 'static) -> Self {
+        self.sinks.push((None, Box::new(sink)));
+        self
+    }
+
+    /// Also write the records at `min_level` or more severe to the file at `path`, e.g. every
+    /// line to STDOUT and the errors to `error.log` as well.
+    ///
+    /// The file is created if it doesn't exist and appended to otherwise, and gets the same
+    /// lines as the primary output, without colors. If it can't be opened when the logger is
+    /// built, a warning is written to STDERR and the logger works without it, unless
+    /// [`with_validate_on_init`](#method.with_validate_on_init) is set. If writing to it fails
+    /// later, e.g. because the disk is full, a single warning is written until a write
+    /// succeeds again, and the other outputs are not affected.
+    ///
+    /// Lines sent to a [route](#method.route_target) don't reach the error file.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let path = std::env::temp_dir().join(format!("log_nonblock_error_file_{}.log", std::process::id()));
+    /// let _ = std::fs::remove_file(&path);
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_capture(&capture)
+    ///     .with_error_file(&path, Level::Error)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let log = |level, message: &str| {
+    ///     logger.log(&Record::builder().args(format_args!("{}", message)).level(level).target("app").build());
+    /// };
+    ///
+    /// log(Level::Info, "Started");
+    /// log(Level::Error, "Disk full");
+    /// log(Level::Warn, "Retrying");
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines(), ["INFO  [app] Started", "ERROR [app] Disk full", "WARN  [app] Retrying"]);
+    /// assert_eq!(std::fs::read_to_string(&path).unwrap(), "ERROR [app] Disk full\r\n");
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    #[must_use = "You must call init() to begin logging"]
+    pub fn with_error_file<P: AsRef<Path>>(mut self, path: P, min_level: Level) -> Self {
+        self.error_file = Some((path.as_ref().to_path_buf(), min_level));
+        self
+    }
+
+    /// Like [`add_sink`](#method.add_sink), and names the sink so it can be flushed on its own
+    /// with [`NonBlockingLogger::flush_sink`].
+    ///
+    /// Names don't have to be unique, sinks sharing a name are flushed together.
+    #[must_use = "You must call init() to begin logging"]
+    pub fn add_named_sink(mut self, name: &str, sink: impl Sink + 'static) -> Self {
+        self.sinks.push((Some(name.to_string()), Box::new(sink)));
+        self
+    }
+
+    /// Initializes the non-blocking logger and sets it as the global logger.
+    ///
+    /// This method builds a logger instance, configures the global max log level (see
+    /// [`with_manage_max_level`](#method.with_manage_max_level)), and registers it with the
+    /// `log` crate as the global logger.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NonBlockingLoggerError::SetLogger`] if the global logger has already been set.
+    /// This is checked once the output is opened but before anything else, so no worker thread
+    /// is spawned, the sinks are dropped and STDOUT or STDERR are left as they were.
+    ///
+    /// Returns [`NonBlockingLoggerError::SpawnWorker`] if the worker thread couldn't be spawned.
+    /// Nothing is logged, and `init` can be called again.
+    ///
+    /// Returns [`NonBlockingLoggerError::Error`] if [`with_synchronous`](#method.with_synchronous)
+    /// is combined with an output other than STDOUT/STDERR, or if the output can't be opened
+    /// with [`with_validate_on_init`](#method.with_validate_on_init), before anything is
+    /// registered.
+    ///
+    /// ```
+    /// use log_nonblock::{NonBlockingLoggerBuilder, NonBlockingLoggerError, Sink};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// /// Records the thread which dropped it
+    /// struct DropSink(Arc<Mutex<Option<String>>>);
+    ///
+    /// impl Sink for DropSink {
+    ///     fn write_all(&mut self, _buf: &[u8]) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    ///
+    ///     fn flush(&mut self) -> std::io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// impl Drop for DropSink {
+    ///     fn drop(&mut self) {
+    ///         *self.0.lock().unwrap() = std::thread::current().name().map(str::to_string);
+    ///     }
+    /// }
+    ///
+    /// let _logger = NonBlockingLoggerBuilder::new().init().unwrap();
+    ///
+    /// let dropped_on = Arc::new(Mutex::new(None));
+    /// let second = NonBlockingLoggerBuilder::new()
+    ///     .add_sink(DropSink(dropped_on.clone()))
+    ///     .init();
+    ///
+    /// assert!(matches!(second, Err(NonBlockingLoggerError::SetLogger(_))));
+    /// // Without a worker thread to own it, the sink was dropped right here
+    /// assert_eq!(*dropped_on.lock().unwrap(), std::thread::current().name().map(str::to_string));
+    /// ```
+    ///
+    /// After a failure, `init` can be called again, e.g. with a fallback configuration:
+    ///
+    /// ```
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder, NonBlockingLoggerError};
+    ///
+    /// // The worker thread can't be given this name
+    /// let failed = NonBlockingLoggerBuilder::new()
+    ///     .with_worker_thread_name("app\0logger")
+    ///     .init();
+    /// assert!(matches!(failed, Err(NonBlockingLoggerError::SpawnWorker(_))));
+    ///
+    /// let capture = CaptureSink::new();
+    /// let _logger = NonBlockingLoggerBuilder::new()
+    ///     .with_capture(&capture)
+    ///     .init()
+    ///     .unwrap();
+    ///
+    /// log::info!("Hello");
+    /// log::logger().flush();
+    /// assert!(capture.lines()[0].ends_with("Hello"));
+    /// ```
+    pub fn init(self) -> Result<NonBlockingLogger, NonBlockingLoggerError> {
+        self.register().map(|(logger, _worker)| logger)
+    }
+
+    /// Like [`init`](#method.init), but returns `Ok(None)` if a global logger is already set.
+    ///
+    /// Meant for libraries and tests which want logging without fighting the application for
+    /// the global logger: the first call installs the logger and returns it, later calls leave
+    /// the installed logger in place and return `None`, whichever crate set it. Nothing is
+    /// spawned or opened in that case, see [`init`](#method.init).
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let first = NonBlockingLoggerBuilder::new().try_init().unwrap();
+    /// assert!(first.is_some());
+    ///
+    /// // E.g. a library initializing logging after the application did
+    /// let second = NonBlockingLoggerBuilder::new().try_init().unwrap();
+    /// assert!(second.is_none());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Same as [`init`](#method.init), except for [`NonBlockingLoggerError::SetLogger`].
+    pub fn try_init(self) -> Result<Option<NonBlockingLogger>, NonBlockingLoggerError> {
+        match self.init() {
+            Ok(logger) => Ok(Some(logger)),
+            Err(NonBlockingLoggerError::SetLogger(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Like [`init`](#method.init), and returns a guard which shuts the logger down when it goes
+    /// out of scope, so messages aren't lost when `main` returns.
+    ///
+    /// See [`LoggerGuard`] for how long the guard has to be kept.
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// fn main() {
+    ///     let _guard = NonBlockingLoggerBuilder::new().init_guarded().unwrap();
+    ///
+    ///     log::info!("Written before main returns");
+    /// #   fn assert_send<T: Send>(_: &T) {}
+    /// #   assert_send(&_guard);
+    /// }
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Same as [`init`](#method.init).
+    pub fn init_guarded(self) -> Result<LoggerGuard, NonBlockingLoggerError> {
+        self.init().map(|logger| LoggerGuard { logger })
+    }
+
+    /// Like [`init`](#method.init), and also returns the handle of the worker thread.
+    ///
+    /// The handle gives full control over the worker, e.g. to pin it to a CPU or to join it.
+    /// It stays valid after [`shutdown`](NonBlockingLogger::shutdown), which stops the worker,
+    /// so joining the handle returns once the worker has exited. Dropping the handle detaches
+    /// the worker, as [`init`](#method.init) does.
+    ///
+    /// ```
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let (logger, worker) = NonBlockingLoggerBuilder::new().init_with_handle().unwrap();
+    ///
+    /// log::info!("Hello");
+    ///
+    /// log::logger().flush();
+    /// logger.shutdown().unwrap();
+    /// worker.join().unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Same as [`init`](#method.init). Also returns [`NonBlockingLoggerError::Error`] with
+    /// [`with_synchronous`](#method.with_synchronous), which doesn't spawn a worker thread.
+    pub fn init_with_handle(
+        self,
+    ) -> Result<(NonBlockingLogger, JoinHandle<()>), NonBlockingLoggerError> {
+        if self.options.synchronous {
+            return Err(NonBlockingLoggerError::Error {
+                reason:
+                    "Failed to initialize logger: There is no worker thread in synchronous mode"
+                        .to_string(),
+            });
+        }
+
+        self.register().map(|(logger, worker)| {
+            (
+                logger,
+                worker.expect("the worker is spawned unless the logger is synchronous"),
+            )
+        })
+    }
+
+    /// Builds the logger and sets it as the global logger, see [`init`](#method.init)
+    fn register(
+        mut self,
+    ) -> Result<(NonBlockingLogger, Option<JoinHandle<()>>), NonBlockingLoggerError> {
+        self.check_synchronous()?;
+
+        // Before registering, so an output which fails validation leaves `init` free to be
+        // called again, e.g. with a fallback configuration
+        let outputs = self.open_outputs()?;
+
+        let mut registered = REGISTERED
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        // Set by an earlier `init`, or registered by a previous one which then failed to build
+        if !*registered || GLOBAL_LOGGER.get().is_some() {
+            // Records are dropped until the logger is set below, but the max level is still `Off`
+            log::set_logger(&GlobalLogger)?;
+            *registered = true;
+        }
+
+        let (logger, worker) = self.build_opened(outputs)?;
+
+        // Can't be set yet, the registration lock is held since checking it
+        let _ = GLOBAL_LOGGER.set(logger.clone());
+
+        if logger.options.manage_max_level {
+            log::set_max_level(logger.max_level());
+        }
+
+        Ok((logger, worker))
+    }
+
+    /// Builds a non-blocking logger instance without setting it as the global logger.
+    ///
+    /// Use this method if you want to manage the logger instance yourself. Otherwise,
     /// use [`init`](#method.init) to automatically set it as the global logger.
-    pub fn build(self) -> Result<NonBlockingLogger, SetLoggerError> {
-        #[cfg(all(feature = "colored", feature = "stderr"))]
-        use_stderr_for_colors();
+    ///
+    /// # Errors
+    ///
+    /// Returns [`NonBlockingLoggerError::SpawnWorker`] if the worker thread couldn't be spawned.
+    ///
+    /// Returns [`NonBlockingLoggerError::Error`] if [`with_synchronous`](#method.with_synchronous)
+    /// is combined with an output other than STDOUT/STDERR.
+    pub fn build(self) -> Result<NonBlockingLogger, NonBlockingLoggerError> {
+        self.build_with_handle().map(|(logger, _worker)| logger)
+    }
+
+    /// Builds the logger, and spawns the worker thread unless it is synchronous
+    fn build_with_handle(
+        mut self,
+    ) -> Result<(NonBlockingLogger, Option<JoinHandle<()>>), NonBlockingLoggerError> {
+        self.check_synchronous()?;
+
+        let outputs = self.open_outputs()?;
+        self.build_opened(outputs)
+    }
+
+    /// Opens the primary output and the error file.
+    ///
+    /// Done first, so a validation error leaves STDOUT/STDERR untouched and nothing registered.
+    fn open_outputs(&mut self) -> Result<OpenedOutputs, NonBlockingLoggerError> {
+        let running = Arc::new(AtomicBool::new(false));
+
+        let output: Box<dyn Sink> = match &self.capture {
+            Some(capture) => Box::new(capture.clone()),
+            None => self.primary_output(&running)?,
+        };
+
+        let error_file = match self.error_file.take() {
+            Some((path, level)) => self.open_error_file(&path)?.map(|file| (level, file)),
+            None => None,
+        };
+
+        Ok(OpenedOutputs {
+            running,
+            output,
+            error_file,
+        })
+    }
+
+    /// Builds the logger around the outputs of [`open_outputs`](Self::open_outputs), and
+    /// spawns the worker thread unless it is synchronous
+    fn build_opened(
+        mut self,
+        outputs: OpenedOutputs,
+    ) -> Result<(NonBlockingLogger, Option<JoinHandle<()>>), NonBlockingLoggerError> {
+        let OpenedOutputs {
+            running,
+            output,
+            error_file,
+        } = outputs;
+
+        // `thread::Builder::spawn` panics on such a name, which may come from a config file.
+        // Checked before the output streams are changed below.
+        if !self.options.synchronous && self.options.worker_thread_name.contains('\0') {
+            return Err(NonBlockingLoggerError::SpawnWorker(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "worker thread name contains a null byte",
+            )));
+        }
+
+        if self.options.synchronous {
+            self.options.sync_level = Some(Level::Trace);
+        }
+
+        // The override is process-wide, so leave it alone unless this logger writes to STDERR
+        #[cfg(feature = "colored")]
+        if self.options.output == Output::Stderr && self.capture.is_none() && self.writes_to_stdio()
+        {
+            use_stderr_for_colors();
+        }
+
+        #[cfg(feature = "colors")]
+        self.apply_color_env();
+
+        // Without it older consoles print the color codes instead of rendering them
+        #[cfg(all(windows, feature = "colors"))]
+        if self.options.colors
+            && self.capture.is_none()
+            && self.writes_to_stdio()
+            && let Err(err) = io::enable_virtual_terminal(self.options.output)
+        {
+            io::write_stderr_with_retry_internal(&format!(
+                "Failed to enable colors on the Windows console, disabling them: {}",
+                err
+            ));
+            self.options.colors = false;
+        }
+
+        // A synchronous logger has no worker to restore the flags when it goes away
+        #[cfg(all(unix, feature = "nonblock-io"))]
+        if !self.options.synchronous {
+            let fd = match self.options.output {
+                Output::Stdout => std::io::stdout().as_raw_fd(),
+                Output::Stderr => std::io::stderr().as_raw_fd(),
+            };
+
+            match io::fd_flags(fd).and_then(|flags| io::set_nonblocking(fd).map(|()| flags)) {
+                Ok(flags) => self.options.original_output_flags = Some((fd, flags)),
+                Err(err) => io::write_stderr_with_retry_internal(&format!(
+                    "Failed to set {:?} to non-blocking mode: {}",
+                    self.options.output, err
+                )),
+            }
+        }
+
+        if self.options.uptime_timestamps {
+            self.options.started = Some(Instant::now());
+        }
+
+        #[cfg(feature = "timestamps")]
+        if let Some(digits) = self.options.subsecond_digits
+            && self.options.timestamps_format.is_none()
+        {
+            self.options.subsecond_format = Some(timestamp_format_with_digits(
+                &self.options.timestamps,
+                digits,
+            ));
+        }
+
+        if self.options.hostname {
+            self.options.host = Some(format::hostname().unwrap_or_else(|| "unknown".to_string()));
+        }
+
+        let (sender, receiver) = if self.options.unbounded_channel {
+            crossbeam_channel::unbounded()
+        } else {
+            crossbeam_channel::bounded(self.options.channel_size)
+        };
+
+        let (pool_sender, pool) = if self.options.buffer_pool_size > 0 {
+            let (pool_sender, pool) = crossbeam_channel::bounded(self.options.buffer_pool_size);
+            (Some(pool_sender), Some(pool))
+        } else {
+            (None, None)
+        };
+
+        if self.capture.is_none() && self.writes_to_stdio() {
+            self.options.sync_strip_ansi = self
+                .options
+                .strip_ansi
+                .unwrap_or(!self.options.output.sink().is_terminal());
+        } else {
+            self.options.sync_level = None;
+        }
+
+        let mut sinks = self.sinks;
+        sinks.insert(0, (None, output));
+
+        if let Some(ring_buffer) = &self.ring_buffer {
+            sinks.push((None, Box::new(ring_buffer.clone())));
+        }
+
+        self.options.sync_copies =
+            sinks.len() > 1 || error_file.is_some() || self.options.on_log.is_some();
+
+        // Longest first, so the logger can take the first matching prefix
+        let mut routes = self.routes;
+        routes.sort_by_key(|(prefix, _sink)| std::cmp::Reverse(prefix.len()));
+        let (route_prefixes, routes) = routes.into_iter().unzip();
+        self.options.route_prefixes = route_prefixes;
+
+        let alive = Arc::new(AtomicBool::new(false));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let received = Arc::new(AtomicU64::new(0));
+
+        if self.options.synchronous {
+            // Every record takes the synchronous path, nothing is ever sent to the channel
+            running.store(true, Ordering::SeqCst);
+            alive.store(true, Ordering::SeqCst);
+
+            let logger = NonBlockingLogger {
+                options: self.options,
+                sender,
+                pool: None,
+                running,
+                alive,
+                dropped,
+                received,
+                ring_buffer: None,
+            };
+
+            return Ok((logger, None));
+        }
+
+        let worker = worker::LogWorker::new(
+            receiver,
+            pool_sender,
+            self.options.clone(),
+            sinks,
+            running.clone(),
+            alive.clone(),
+            dropped.clone(),
+        )
+        .with_routes(routes)
+        .with_error_file(error_file)
+        .with_received(received.clone());
+        // Without the worker nothing would ever be written, so the logger is never handed out
+        let worker = worker
+            .spawn()
+            .map_err(NonBlockingLoggerError::SpawnWorker)?;
+
+        let logger = NonBlockingLogger {
+            options: self.options,
+            sender,
+            pool,
+            running,
+            alive,
+            dropped,
+            received,
+            ring_buffer: self.ring_buffer,
+        };
+
+        Ok((logger, Some(worker)))
+    }
+
+    /// Fails if the logger is synchronous and writes anywhere but STDOUT/STDERR
+    fn check_synchronous(&self) -> Result<(), NonBlockingLoggerError> {
+        let only_stdio = self.capture.is_none()
+            && self.writes_to_stdio()
+            && self.sinks.is_empty()
+            && self.routes.is_empty()
+            && self.ring_buffer.is_none()
+            && self.error_file.is_none();
+
+        if self.options.synchronous && !only_stdio {
+            return Err(NonBlockingLoggerError::Error {
+                reason: "Failed to build logger: A synchronous logger only writes to STDOUT/STDERR"
+                    .to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Resolves colors from `with_colors`, `NO_COLOR` and `CLICOLOR_FORCE`, in this order,
+    /// see [`with_colors`](#method.with_colors)
+    #[cfg(feature = "colors")]
+    fn apply_color_env(&mut self) {
+        if self.colors_explicit {
+            if self.options.colors {
+                colored::control::set_override(true);
+            }
+        } else if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+            self.options.colors = false;
+        } else if env_flag("CLICOLOR_FORCE") {
+            self.options.colors = true;
+            colored::control::set_override(true);
+            self.options.strip_ansi.get_or_insert(false);
+        }
+    }
+
+    /// Whether the primary output is STDOUT/STDERR, rather than a file, TCP, syslog or journald
+    fn writes_to_stdio(&self) -> bool {
+        #[cfg(all(unix, feature = "syslog"))]
+        if self.options.syslog.is_some() {
+            return false;
+        }
+
+        #[cfg(all(target_os = "linux", feature = "journald"))]
+        if self.options.journald {
+            return false;
+        }
+
+        #[cfg(unix)]
+        if self.options.unix_socket.is_some() {
+            return false;
+        }
+
+        self.options.file.is_none() && self.options.tcp.is_none()
+    }
+
+    /// Opens the output selected by the builder: a file, TCP, a Unix socket, syslog, journald or
+    /// STDOUT/STDERR. If it can't be opened, reports why and falls back to STDOUT/STDERR, or
+    /// returns the error with [`with_validate_on_init`](#method.with_validate_on_init).
+    fn primary_output(
+        &mut self,
+        running: &Arc<AtomicBool>,
+    ) -> Result<Box<dyn Sink>, NonBlockingLoggerError> {
+        let output: Box<dyn Sink> = match &self.options.file {
+            Some(file_options) => match FileSink::with_options(file::FileOptions {
+                #[cfg(feature = "compression")]
+                compress: self.options.compress_rotated,
+                ..file_options.clone()
+            }) {
+                Ok(file) => Box::new(file),
+                Err(err) => {
+                    let reason = format!(
+                        "Failed to open log file {}: {}",
+                        file_options.path.display(),
+                        err
+                    );
+                    self.check_output(reason, self.options.output)?;
+                    self.options.output.sink()
+                }
+            },
+            None => match self.options.tcp {
+                Some(addr) => {
+                    let mut tcp = tcp::TcpOutput::new(addr, running.clone(), self.options.framing);
+
+                    if self.options.validate_on_init
+                        && let Err(err) = tcp.connect_now()
+                    {
+                        return Err(NonBlockingLoggerError::Error {
+                            reason: format!("Failed to connect to log collector {}: {}", addr, err),
+                        });
+                    }
+
+                    Box::new(tcp)
+                }
+                None => self.options.output.sink(),
+            },
+        };
+
+        #[cfg(unix)]
+        let output: Box<dyn Sink> = match &self.options.unix_socket {
+            Some(socket) if self.options.file.is_none() && self.options.tcp.is_none() => socket
+                .open(running, self.options.framing, self.options.validate_on_init)
+                .map_err(|err| NonBlockingLoggerError::Error {
+                    reason: format!(
+                        "Failed to connect to Unix socket {}: {}",
+                        socket.path(),
+                        err
+                    ),
+                })?,
+            _ => output,
+        };
+
+        #[cfg(all(unix, feature = "syslog"))]
+        let output: Box<dyn Sink> = match &self.options.syslog {
+            Some(_) => match syslog::SyslogSocket::connect() {
+                Ok(socket) => Box::new(socket),
+                Err(err) => {
+                    self.check_output(
+                        format!("Failed to connect to syslog: {}", err),
+                        Output::Stderr,
+                    )?;
+                    Box::new(StderrSink)
+                }
+            },
+            None => output,
+        };
+
+        #[cfg(all(target_os = "linux", feature = "journald"))]
+        let output: Box<dyn Sink> = match self.options.journald {
+            true => match journald::JournalSocket::connect() {
+                Ok(socket) => Box::new(socket),
+                Err(err) => {
+                    self.check_output(
+                        format!("Failed to connect to journald: {}", err),
+                        self.options.output,
+                    )?;
+                    // Plain lines, journal fields are unreadable on a terminal
+                    self.options.journald = false;
+                    self.options.output.sink()
+                }
+            },
+            false => output,
+        };
+
+        Ok(output)
+    }
+
+    /// Opens the file of [`with_error_file`](#method.with_error_file), `None` if it can't be
+    /// opened and outputs aren't validated
+    fn open_error_file(
+        &self,
+        path: &Path,
+    ) -> Result<Option<Box<dyn Sink>>, NonBlockingLoggerError> {
+        match FileSink::open(path) {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(err) => {
+                let reason = format!("Failed to open error log file {}: {}", path.display(), err);
+
+                if self.options.validate_on_init {
+                    return Err(NonBlockingLoggerError::Error { reason });
+                }
+
+                io::write_stderr_with_retry_internal(&format!(
+                    "{}, writing errors to the other outputs only",
+                    reason
+                ));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Fails with `reason` if outputs are validated, otherwise reports it and lets the caller
+    /// fall back to `fallback`
+    fn check_output(&self, reason: String, fallback: Output) -> Result<(), NonBlockingLoggerError> {
+        if self.options.validate_on_init {
+            return Err(NonBlockingLoggerError::Error { reason });
+        }
+
+        io::write_stderr_with_retry_internal(&format!(
+            "{}, falling back to {:?}",
+            reason, fallback
+        ));
+        Ok(())
+    }
+}
+
+/// Whether `target` is the module `module` or one of its sub-modules
+fn matches_module(target: &str, module: &str) -> bool {
+    match target.strip_prefix(module) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+/// Whether the environment variable is set to a value which enables it: non-empty and not `0`
+#[cfg(feature = "colors")]
+fn env_flag(name: &str) -> bool {
+    std::env::var_os(name).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+#[derive(Debug)]
+pub enum NonBlockingLoggerError {
+    Error {
+        reason: String,
+    },
+    /// The global logger has already been set
+    SetLogger(SetLoggerError),
+    /// The worker thread couldn't be spawned
+    SpawnWorker(std::io::Error),
+}
+
+impl std::fmt::Display for NonBlockingLoggerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NonBlockingLoggerError::Error { reason } => {
+                write!(f, "NonBlockingLoggerError: {}", reason)
+            }
+            NonBlockingLoggerError::SetLogger(err) => {
+                write!(f, "NonBlockingLoggerError: {}", err)
+            }
+            NonBlockingLoggerError::SpawnWorker(err) => {
+                write!(
+                    f,
+                    "NonBlockingLoggerError: Failed to spawn logger worker: {}",
+                    err
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for NonBlockingLoggerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NonBlockingLoggerError::Error { .. } => None,
+            NonBlockingLoggerError::SetLogger(err) => Some(err),
+            NonBlockingLoggerError::SpawnWorker(err) => Some(err),
+        }
+    }
+}
+
+impl From<SetLoggerError> for NonBlockingLoggerError {
+    fn from(err: SetLoggerError) -> Self {
+        NonBlockingLoggerError::SetLogger(err)
+    }
+}
+
+/// Returned by [`NonBlockingLogger::shutdown_with_stats`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownStats {
+    /// Records waiting in the channel or in [thread batches](NonBlockingLoggerBuilder::with_thread_local_batching)
+    /// when the shutdown started, written before the worker stopped
+    pub drained: usize,
+    /// Messages dropped over the lifetime of the logger because the channel was full,
+    /// see [`FullPolicy::Drop`]
+    pub dropped: u64,
+}
+
+/// Shuts down the global logger when dropped, returned by
+/// [`NonBlockingLoggerBuilder::init_guarded`].
+///
+/// Keep the guard alive for as long as the program logs, typically by binding it to a variable
+/// in `main`: `let _guard = ...`. Binding it to `_` drops it at once, which stops the logger
+/// right away. Dropping the guard writes the messages still waiting in the channel, flushes the
+/// outputs and stops the worker thread, like [`NonBlockingLogger::stop`].
+///
+/// Destructors don't run on [`std::process::exit`], so drop the guard before calling it.
+///
+/// The guard is `Send`, so it can also be moved to the thread which outlives the others.
+#[must_use = "The logger is shut down as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct LoggerGuard {
+    logger: NonBlockingLogger,
+}
+
+impl Drop for LoggerGuard {
+    fn drop(&mut self) {
+        // Fails only if the logger was already stopped, which leaves nothing to do
+        let _ = self.logger.stop();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct NonBlockingLogger {
+    options: NonBlockingOptions,
+    sender: crossbeam_channel::Sender<worker::WorkerMessage>,
+    /// Spare message buffers returned by the worker, see [`NonBlockingLoggerBuilder::with_buffer_pool_size`]
+    pool: Option<crossbeam_channel::Receiver<String>>,
+    running: Arc<AtomicBool>,
+    /// Whether the worker thread is still there, see [`is_healthy`](NonBlockingLogger::is_healthy)
+    alive: Arc<AtomicBool>,
+    /// Messages dropped because the channel was full, reported and reset by the worker
+    dropped: Arc<AtomicU64>,
+    /// Records the worker took so far, see [`ShutdownStats::drained`]
+    received: Arc<AtomicU64>,
+    /// See [`NonBlockingLoggerBuilder::with_ring_buffer`]
+    ring_buffer: Option<sink::RingBuffer>,
+}
+
+impl NonBlockingLogger {
+    /// Level passed to `log::set_max_level` by [`init`](NonBlockingLoggerBuilder::init): the
+    /// override set with [`with_max_level_override`](NonBlockingLoggerBuilder::with_max_level_override),
+    /// or the most verbose of the default, module and [scoped](#method.scoped_module_level)
+    /// levels, capped to [`STATIC_MAX_LEVEL`].
+    pub fn max_level(&self) -> LevelFilter {
+        self.configured_max_level().min(STATIC_MAX_LEVEL)
+    }
+
+    /// Whether this is a clone of the logger set up by [`init`](NonBlockingLoggerBuilder::init)
+    fn is_global(&self) -> bool {
+        GLOBAL_LOGGER
+            .get()
+            .is_some_and(|global| Arc::ptr_eq(&global.running, &self.running))
+    }
+
+    fn configured_max_level(&self) -> LevelFilter {
+        if let Some(level) = self.options.max_level_override {
+            return level;
+        }
+
+        let max_level = self
+            .options
+            .module_levels
+            .iter()
+            .map(|(_name, level)| level)
+            .copied()
+            .max();
+
+        #[cfg(feature = "regex")]
+        let max_level = max_level.max(
+            self.options
+                .module_level_regexes
+                .iter()
+                .map(|(_regex, level)| level)
+                .copied()
+                .max(),
+        );
+        let max_level = max_level.max(self.options.scoped_levels.max());
+
+        max_level
+            .map(|lvl| lvl.max(self.options.default_level))
+            .unwrap_or(self.options.default_level)
+    }
+
+    /// Level of the records written for `target`, as resolved by [`enabled`](Log::enabled).
+    ///
+    /// The first rule which applies wins: a [scoped level](Self::scoped_module_level), the
+    /// most specific [module level](NonBlockingLoggerBuilder::with_module_level) covering the
+    /// target, a [pattern](NonBlockingLoggerBuilder::with_module_level_regex), and finally the
+    /// default level. The result is capped to [`STATIC_MAX_LEVEL`], and for the global logger,
+    /// `enabled` caps it to `log::max_level()` too, see
+    /// [`with_manage_max_level`](NonBlockingLoggerBuilder::with_manage_max_level). The
+    /// [filter](NonBlockingLoggerBuilder::with_filter) isn't taken into account, as it can
+    /// depend on more than the target.
+    ///
+    /// Useful to show what a configuration actually does, e.g. for a `--log-config-dump` flag.
+    ///
+    /// ```
+    /// use log::LevelFilter;
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(LevelFilter::Info)
+    ///     .with_module_level("app", LevelFilter::Warn)
+    ///     .with_module_level("app::db::pool", LevelFilter::Trace)
+    ///     .with_module_level("app::db", LevelFilter::Debug)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(logger.effective_level("other"), LevelFilter::Info);
+    /// assert_eq!(logger.effective_level("app"), LevelFilter::Warn);
+    /// assert_eq!(logger.effective_level("app::http"), LevelFilter::Warn);
+    /// assert_eq!(logger.effective_level("app::db"), LevelFilter::Debug);
+    /// assert_eq!(logger.effective_level("app::db::pool::conn"), LevelFilter::Trace);
+    /// // Only whole module names match
+    /// assert_eq!(logger.effective_level("app::dbx"), LevelFilter::Warn);
+    /// assert_eq!(logger.effective_level("apps"), LevelFilter::Info);
+    ///
+    /// let _scope = logger.scoped_module_level("app::db", LevelFilter::Error);
+    /// assert_eq!(logger.effective_level("app::db::pool"), LevelFilter::Error);
+    /// ```
+    pub fn effective_level(&self, target: &str) -> LevelFilter {
+        let scoped = self.options.scoped_levels.find(target);
+
+        let level = scoped.as_ref().or_else(|| {
+            self.options
+                .module_levels
+                .iter()
+                /* At this point the Vec is sorted from the most specific rule, so that we can
+                 * simply take the first match
+                 */
+                .find(|(name, _level)| matches_module(target, name))
+                .map(|(_name, level)| level)
+        });
+
+        #[cfg(feature = "regex")]
+        let level = level.or_else(|| {
+            self.options
+                .module_level_regexes
+                .iter()
+                .find(|(regex, _level)| regex.is_match(target))
+                .map(|(_regex, level)| level)
+        });
+
+        (*level.unwrap_or(&self.options.default_level)).min(STATIC_MAX_LEVEL)
+    }
+
+    /// Returns the number of messages waiting in the channel for the worker.
+    ///
+    /// This is an instantaneous snapshot which can change immediately after it is read. Compare
+    /// it with [`queue_capacity`](#method.queue_capacity) to detect when the logger is close to
+    /// dropping messages.
+    pub fn queue_len(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// Returns the capacity of the channel, the configured `channel_size`,
+    /// or `usize::MAX` with [`with_unbounded_channel`](NonBlockingLoggerBuilder::with_unbounded_channel)
+    pub fn queue_capacity(&self) -> usize {
+        self.sender.capacity().unwrap_or(usize::MAX)
+    }
+
+    /// Returns how many debug and trace records were sampled out while the channel was close
+    /// to full, see [`with_adaptive_sampling`](NonBlockingLoggerBuilder::with_adaptive_sampling)
+    pub fn sampled_out(&self) -> u64 {
+        self.options
+            .sampler
+            .as_ref()
+            .map_or(0, |sampler| sampler.sampled_out())
+    }
+
+    /// Flushes only the sinks added with [`add_named_sink`](NonBlockingLoggerBuilder::add_named_sink)
+    /// under `name`, e.g. to read a log file in a test without waiting for a slow network sink
+    /// to flush.
+    ///
+    /// Blocks until the worker has written every message sent before the call and flushed the
+    /// named sinks. Messages are still written to every sink in order, so a slow sink can delay
+    /// the flush, but its buffered data isn't forced out. If no sink has this name, nothing is
+    /// flushed and a diagnostic is written to STDERR.
+    ///
+    /// Called on the worker thread, e.g. from a sink, it returns at once and every sink is
+    /// flushed after the current write, like [`flush`](log::Log::flush).
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{FileSink, NonBlockingLoggerBuilder};
+    ///
+    /// let path = std::env::temp_dir().join(format!("log_nonblock_flush_sink_{}.log", std::process::id()));
+    /// let _ = std::fs::remove_file(&path);
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .add_named_sink("file", FileSink::open(&path).unwrap())
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(&Record::builder().args(format_args!("Saved")).level(Level::Info).target("app").build());
+    /// logger.flush_sink("file");
+    ///
+    /// let written = std::fs::read_to_string(&path).unwrap();
+    /// assert!(written.contains("[app] Saved"));
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn flush_sink(&self, name: &str) {
+        self.write_pending_repeated();
+
+        if self.options.synchronous || worker::request_flush_on_worker_thread() {
+            return;
+        }
+
+        self.send_thread_batches();
+        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+
+        self.send_flush(
+            worker::WorkerMessage::FlushSink(
+                name.to_string(),
+                worker::FlushDone::Blocking(done_tx),
+            ),
+            done_rx,
+        );
+    }
+
+    /// Reopens the outputs, e.g. after logrotate renamed the log file.
+    ///
+    /// Blocks until the worker has written every message sent before the call, then flushes and
+    /// reopens each sink with [`Sink::reopen`]: files ([`with_file`](NonBlockingLoggerBuilder::with_file),
+    /// [`FileSink`]) are closed and opened again at their path, creating a fresh file if the old
+    /// one was moved away. The worker does it between two messages, so no line is split between
+    /// the old and the new file.
+    ///
+    /// logrotate (without `copytruncate`) renames the file and sends SIGHUP, expecting the
+    /// process to reopen it. A signal handler can't wait for the worker, so wait for the signal
+    /// on a thread which calls `reopen`, e.g. with `signal_hook::iterator::Signals`. See the
+    /// `logrotate` example for the whole pattern.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error of the sinks which couldn't be reopened, the others are reopened
+    /// anyway. A sink which fails keeps writing to its old destination if it can. Also fails
+    /// when the logger is stopped, when called on the worker thread, and in
+    /// [synchronous](NonBlockingLoggerBuilder::with_synchronous) mode, which only writes to
+    /// STDOUT/STDERR.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::NonBlockingLoggerBuilder;
+    ///
+    /// let path = std::env::temp_dir().join(format!("log_nonblock_reopen_{}.log", std::process::id()));
+    /// let rotated = path.with_extension("log.1");
+    /// let _ = std::fs::remove_file(&path);
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_file(&path)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let log = |message: &str| {
+    ///     logger.log(&Record::builder().args(format_args!("{}", message)).level(Level::Info).target("app").build());
+    /// };
+    ///
+    /// log("Before");
+    /// logger.flush();
+    ///
+    /// // What logrotate does before sending SIGHUP
+    /// std::fs::rename(&path, &rotated).unwrap();
+    /// logger.reopen().unwrap();
+    ///
+    /// log("After");
+    /// logger.flush();
+    ///
+    /// let old = std::fs::read_to_string(&rotated).unwrap();
+    /// let new = std::fs::read_to_string(&path).unwrap();
+    /// assert!(old.contains("[app] Before") && !old.contains("After"));
+    /// assert!(new.contains("[app] After") && !new.contains("Before"));
+    ///
+    /// std::fs::remove_file(&path).unwrap();
+    /// std::fs::remove_file(&rotated).unwrap();
+    /// ```
+    pub fn reopen(&self) -> std::io::Result<()> {
+        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+        self.send_to_worker(worker::WorkerMessage::Reopen(done_tx))?;
+
+        done_rx
+            .recv()
+            .map_err(|_| std::io::Error::other("The logger worker stopped"))?
+    }
+
+    /// Replaces the primary output with `sink`, e.g. to move logging to a new file at runtime.
+    ///
+    /// The primary output is the one set with [`with_output`](NonBlockingLoggerBuilder::with_output),
+    /// [`with_file`](NonBlockingLoggerBuilder::with_file) or the other output methods of the
+    /// builder. Sinks added with [`add_sink`](NonBlockingLoggerBuilder::add_sink) and routes are
+    /// kept. Blocks until the worker has written every message sent before the call to the old
+    /// output, flushed and dropped it. Every message sent after the call goes to `sink`.
+    ///
+    /// # Errors
+    ///
+    /// Fails when the logger is stopped, when called on the worker thread, and in
+    /// [synchronous](NonBlockingLoggerBuilder::with_synchronous) mode, which only writes to
+    /// STDOUT/STDERR.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let (old, new) = (CaptureSink::new(), CaptureSink::new());
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_capture(&old)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let log = |message: &str| {
+    ///     logger.log(&Record::builder().args(format_args!("{}", message)).level(Level::Info).target("app").build());
+    /// };
+    ///
+    /// log("Before");
+    /// logger.set_sink(new.clone()).unwrap();
+    /// log("After");
+    /// logger.flush();
+    ///
+    /// assert_eq!(old.lines(), ["INFO  [app] Before"]);
+    /// assert_eq!(new.lines(), ["INFO  [app] After"]);
+    /// ```
+    pub fn set_sink(&self, sink: impl Sink + 'static) -> std::io::Result<()> {
+        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+        self.send_to_worker(worker::WorkerMessage::SetSink(
+            Box::new(sink),
+            worker::FlushDone::Blocking(done_tx),
+        ))?;
+
+        done_rx
+            .recv()
+            .map_err(|_| std::io::Error::other("The logger worker stopped"))
+    }
+
+    /// Sends a message which the caller waits a reply for, blocking while the channel is full
+    fn send_to_worker(&self, message: worker::WorkerMessage) -> std::io::Result<()> {
+        if self.options.synchronous {
+            return Err(std::io::Error::other(
+                "There is no worker thread in synchronous mode",
+            ));
+        }
+
+        if worker::on_worker_thread() {
+            return Err(std::io::Error::other(
+                "The worker thread can't wait for itself",
+            ));
+        }
+
+        self.send_thread_batches();
+
+        self.sender
+            .send(message)
+            .map_err(|_| std::io::Error::other("The logger is stopped"))
+    }
+
+    /// Flushes the logger without blocking the calling thread, for use in async code.
+    ///
+    /// Works like [`flush`](log::Log::flush): the returned future completes once the worker has
+    /// written every message sent before the call. Instead of blocking on the reply, it awaits a
+    /// Tokio oneshot channel, and yields to the runtime while the channel to the worker is full.
+    ///
+    /// Only the waiting is async, the worker is still a regular thread and writes the same way.
+    /// The future can be awaited on any Tokio runtime, including a current-thread one.
+    /// Like `flush`, it returns at once when called on the worker thread.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.log(
+    ///     &Record::builder()
+    ///         .args(format_args!("Hello"))
+    ///         .level(Level::Info)
+    ///         .target("async")
+    ///         .build(),
+    /// );
+    ///
+    /// tokio::runtime::Builder::new_current_thread()
+    ///     .build()
+    ///     .unwrap()
+    ///     .block_on(logger.flush_async());
+    ///
+    /// assert!(capture.lines()[0].ends_with("[async] Hello"));
+    /// ```
+    ///
+    /// This method is only available if the `tokio` feature is enabled.
+    #[cfg(feature = "tokio")]
+    pub async fn flush_async(&self) {
+        self.write_pending_repeated();
+
+        if self.options.synchronous || worker::request_flush_on_worker_thread() {
+            return;
+        }
+
+        self.send_thread_batches();
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+        let mut message = worker::WorkerMessage::Flush(worker::FlushDone::Async(done_tx));
+
+        loop {
+            match self.sender.try_send(message) {
+                Ok(_) => break,
+                Err(crossbeam_channel::TrySendError::Full(returned)) => {
+                    message = returned;
+                    tokio::task::yield_now().await;
+                }
+                Err(err) => {
+                    io::write_stderr_with_retry_internal(&format!(
+                        "Failed to send flush request to logger worker: {}",
+                        err
+                    ));
+                    return;
+                }
+            }
+        }
+
+        // Fails only if the worker went away, which leaves nothing to wait for
+        let _ = done_rx.await;
+    }
+
+    /// Log panics through this logger, as error messages with the `panic` target.
+    ///
+    /// Sets a [panic hook](std::panic::set_hook) which logs the thread name, location and payload
+    /// of the panic, followed by the backtrace when `RUST_BACKTRACE` is set, and then calls the
+    /// previously installed hook. Panic messages are sent regardless of the configured levels,
+    /// filters and rate limits.
+    ///
+    /// The message is written by the worker thread like any other, so call
+    /// [`flush`](log::Log::flush) before the process exits, otherwise the panic line can be lost.
+    /// The hook itself doesn't flush, as it also runs when the worker thread panics.
+    ///
+    /// ```
+    /// use log::Log;
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// logger.install_panic_hook();
+    ///
+    /// let result = std::thread::Builder::new()
+    ///     .name("job".to_string())
+    ///     .spawn(|| panic!("Boom"))
+    ///     .unwrap()
+    ///     .join();
+    ///
+    /// assert!(result.is_err());
+    /// logger.flush();
+    ///
+    /// let lines = capture.lines();
+    /// assert!(lines[0].contains("[panic] thread 'job' panicked at "));
+    /// assert!(lines[0].contains(": Boom"));
+    /// ```
+    pub fn install_panic_hook(&self) {
+        let logger = self.clone();
+        let previous = std::panic::take_hook();
+
+        std::panic::set_hook(Box::new(move |info| {
+            let payload = info
+                .payload()
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("Box<dyn Any>");
+
+            let thread = std::thread::current();
+            let mut message = format!("thread '{}' panicked", thread.name().unwrap_or("<unnamed>"));
+
+            if let Some(location) = info.location() {
+                let _ = write!(message, " at {}", location);
+            }
+
+            let _ = write!(message, ": {}", payload);
+
+            if std::env::var_os("RUST_BACKTRACE").is_some_and(|value| value != "0") {
+                let _ = write!(
+                    message,
+                    "\nstack backtrace:\n{}",
+                    std::backtrace::Backtrace::force_capture()
+                );
+            }
+
+            logger.write_record(
+                &Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(Level::Error)
+                    .target("panic")
+                    .file(info.location().map(|location| location.file()))
+                    .line(info.location().map(|location| location.line()))
+                    .build(),
+            );
+
+            previous(info);
+        }));
+    }
+
+    /// Logs the record through this logger, without it being registered as the global logger.
+    ///
+    /// Same as [`log`](log::Log::log), without importing the `Log` trait. A logger created
+    /// with [`build`](NonBlockingLoggerBuilder::build) is independent of the `log` macros, so
+    /// any number of them can be created, used and shut down in one process, e.g. one per test.
+    ///
+    /// ```
+    /// use log::{Level, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// for run in 0..3 {
+    ///     let capture = CaptureSink::new();
+    ///     let logger = NonBlockingLoggerBuilder::new()
+    ///         .with_capture(&capture)
+    ///         .build()
+    ///         .unwrap();
+    ///
+    ///     logger.log_record(&Record::builder().args(format_args!("Run {}", run)).level(Level::Info).target("test").build());
+    ///     logger.shutdown().unwrap();
+    ///
+    ///     assert_eq!(capture.lines().len(), 1);
+    ///     assert!(capture.lines()[0].ends_with(&format!("[test] Run {}", run)));
+    /// }
+    /// ```
+    pub fn log_record(&self, record: &Record) {
+        self.try_log_enabled(record);
+    }
+
+    /// Writes a payload rendered by the caller to the outputs, as is.
+    ///
+    /// For custom formats which aren't text, e.g. binary framing or compressed chunks. The
+    /// payload goes through the channel and is written by the worker like a log line, in order
+    /// with the other lines, but without any formatting: nothing is added, not even a line
+    /// ending, and it doesn't have to be UTF-8. Color codes are still removed from sinks which
+    /// aren't terminals, see [`with_strip_ansi`](NonBlockingLoggerBuilder::with_strip_ansi).
+    ///
+    /// `level` isn't checked against the configured levels, it is passed on to
+    /// [`with_on_log`](NonBlockingLoggerBuilder::with_on_log), which gets the payload as lossy
+    /// UTF-8. Returns `false` if the payload couldn't be sent, like
+    /// [`try_log_enabled`](#method.try_log_enabled).
+    ///
+    /// ```
+    /// use log::{Level, Log};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// // Rendered by the caller, no timestamp or level is added
+    /// assert!(logger.log_bytes(Level::Info, "{\"event\":\"ready\"}"));
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines(), [r#"{"event":"ready"}"#]);
+    /// ```
+    pub fn log_bytes(&self, level: Level, payload: impl Into<Vec<u8>>) -> bool {
+        let payload = payload.into();
+
+        // Nothing is ever sent to the worker, see `with_synchronous`
+        if self.options.synchronous {
+            io::write_stdio_with_retry(&payload, self.options.output, self.options.sync_strip_ansi);
+            return true;
+        }
+
+        self.send_message(worker::WorkerMessage::Log(
+            level,
+            line::Line::Bytes(payload),
+        ))
+    }
+
+    /// Logs the record like [`log`](log::Log::log), and reports whether it was lost.
+    ///
+    /// Returns `false` if the record should have been logged but couldn't be sent to the worker,
+    /// e.g. because the channel is full with [`FullPolicy::Drop`]. Returns `true` if it was
+    /// enqueued, or if it intentionally isn't logged (disabled level, filter, rate limit,
+    /// deduplication, sampling). Callers can use this to fall back to writing critical messages
+    /// synchronously when the logger is saturated.
+    pub fn try_log_enabled(&self, record: &Record) -> bool {
+        if !self.enabled(record.metadata()) {
+            return true;
+        }
 
-        #[cfg(not(feature = "stderr"))]
+        if let Some(sampler) = &self.options.sampler
+            && !sampler.keep(record.level(), self.sender.len(), self.sender.capacity())
         {
-            #[cfg(all(unix, feature = "nonblock-io"))]
-            if let Err(err) = io::set_nonblocking(std::io::stdout().as_raw_fd()) {
-                io::write_stdout_with_retry_internal(&format!(
-                    "Failed to set STDOUT to non-blocking mode: {}",
-                    err
-                ));
+            return true;
+        }
+
+        match &self.options.dedup {
+            Some(dedup) => {
+                // Keep the lock while writing, so the summary is sent before the new message
+                let mut dedup = dedup
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                let (suppress, repeated) = dedup.check(record);
+
+                if let Some(repeated) = repeated {
+                    self.write_repeated(&repeated);
+                }
+
+                suppress || !self.acquire_rate_limit(record) || self.write_record(record)
             }
+            None => !self.acquire_rate_limit(record) || self.write_record(record),
         }
+    }
 
-        #[cfg(feature = "stderr")]
-        {
-            #[cfg(all(unix, feature = "nonblock-io"))]
-            if let Err(err) = io::set_nonblocking(std::io::stderr().as_raw_fd()) {
+    /// Takes a token from the rate limit of the record target, if it has one.
+    ///
+    /// Returns `false` if the record must be dropped. Writes the summary of previously
+    /// suppressed messages first, when a message gets through after some were dropped.
+    fn acquire_rate_limit(&self, record: &Record) -> bool {
+        let Some(limit) = self
+            .options
+            .rate_limits
+            .iter()
+            .find(|limit| matches_module(record.target(), &limit.target))
+        else {
+            return true;
+        };
+
+        match limit.acquire() {
+            None => false,
+            Some(0) => true,
+            Some(suppressed) => {
+                self.write_record(
+                    &Record::builder()
+                        .args(format_args!(
+                            "suppressed {} messages from {}",
+                            suppressed, limit.target
+                        ))
+                        .level(Level::Warn)
+                        .target(record.target())
+                        .build(),
+                );
+
+                true
+            }
+        }
+    }
+
+    /// Blocks until the worker has written and flushed every message sent before the call
+    fn wait_for_worker(&self) {
+        // Every record has been written already
+        if self.options.synchronous {
+            return;
+        }
+
+        // The worker can't answer while it is busy calling us, e.g. from a sink
+        if worker::request_flush_on_worker_thread() {
+            return;
+        }
+
+        self.send_thread_batches();
+        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+
+        self.send_flush(
+            worker::WorkerMessage::Flush(worker::FlushDone::Blocking(done_tx)),
+            done_rx,
+        );
+    }
+
+    /// Sends a flush request to the worker and blocks until it is done
+    fn send_flush(&self, message: worker::WorkerMessage, done_rx: crossbeam_channel::Receiver<()>) {
+        match self.sender.send(message) {
+            Ok(_) => {
+                // Block until flush completes
+                let _ = done_rx.recv();
+            }
+            Err(err) => {
                 io::write_stderr_with_retry_internal(&format!(
-                    "Failed to set STDERR to non-blocking mode: {}",
+                    "Failed to send flush request to logger worker: {}",
                     err
                 ));
             }
         }
+    }
 
-        let (sender, receiver) = crossbeam_channel::bounded(self.options.channel_size);
+    /// Reports the first call to `log()` slower than [`BLOCKING_THRESHOLD`]
+    fn report_blocking(&self, record: &Record, elapsed: Duration, reported: &AtomicBool) {
+        if elapsed < BLOCKING_THRESHOLD || reported.swap(true, Ordering::Relaxed) {
+            return;
+        }
 
-        let (worker, running) = worker::LogWorker::new(receiver);
-        if let Err(err) = worker.spawn() {
-            println!("Failed to spawn logger worker: {}", err);
-        };
+        io::write_stderr_with_retry_internal(&format!(
+            "Logging a {} record of {} blocked for {:?} ({} of {} messages waiting in the channel, \
+             full policy {:?}, sync level {:?}). Further slow calls are not reported",
+            record.level(),
+            record.target(),
+            elapsed,
+            self.sender.len(),
+            self.sender.capacity().unwrap_or(0),
+            self.options.full_policy,
+            self.options.sync_level,
+        ));
+    }
 
-        let logger = NonBlockingLogger {
-            options: self.options,
-            sender,
-            running,
-        };
+    /// Writes the "repeated" summary of a message still held back by deduplication, if any
+    fn write_pending_repeated(&self) {
+        if let Some(dedup) = &self.options.dedup {
+            let mut dedup = dedup
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
 
-        Ok(logger)
+            if let Some(repeated) = dedup.take_repeated() {
+                self.write_repeated(&repeated);
+            }
+        }
     }
-}
 
-#[derive(Debug)]
-pub enum NonBlockingLoggerError {
-    Error { reason: String },
-}
+    fn write_repeated(&self, repeated: &dedup::Repeated) {
+        self.write_record(
+            &Record::builder()
+                .args(format_args!(
+                    "last message repeated {} times",
+                    repeated.count
+                ))
+                .level(repeated.level)
+                .target(&repeated.target)
+                .build(),
+        );
+    }
 
-impl std::fmt::Display for NonBlockingLoggerError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            NonBlockingLoggerError::Error { reason } => {
-                write!(f, "NonBlockingLoggerError: {}", reason)
-            }
+    /// Spare buffer from the pool, or a new one if the pool is empty or disabled
+    fn take_buffer(&self) -> String {
+        self.pool
+            .as_ref()
+            .and_then(|pool| pool.try_recv().ok())
+            .unwrap_or_default()
+    }
+
+    /// Formats the record and sends it to the worker, without checking levels or rate limits.
+    ///
+    /// Returns whether the message was sent.
+    fn write_record(&self, record: &Record) -> bool {
+        if let Some(sender) = &self.options.record_sink {
+            self.send_owned_record(sender, record);
         }
+
+        #[cfg(feature = "bench-hooks")]
+        if let Some(hook) = &self.options.stage_hook {
+            let started = Instant::now();
+            let sent = self.send_record(record);
+            (hook.0)(Stage::Enqueue, started.elapsed());
+
+            return sent;
+        }
+
+        self.send_record(record)
     }
-}
 
-impl std::error::Error for NonBlockingLoggerError {}
+    /// Sends a copy of the record, see [`NonBlockingLoggerBuilder::with_record_sink`]
+    fn send_owned_record(&self, sender: &std::sync::mpsc::Sender<OwnedRecord>, record: &Record) {
+        #[cfg(feature = "timestamps")]
+        let timestamp = self.options.now().into();
 
-#[derive(Clone, Debug)]
-pub struct NonBlockingLogger {
-    options: NonBlockingOptions,
-    sender: crossbeam_channel::Sender<worker::WorkerMessage>,
-    running: Arc<AtomicBool>,
-}
+        #[cfg(not(feature = "timestamps"))]
+        let timestamp = std::time::SystemTime::now();
 
-impl NonBlockingLogger {
-    pub fn max_level(&self) -> LevelFilter {
-        let max_level = self
-            .options
-            .module_levels
-            .iter()
-            .map(|(_name, level)| level)
-            .copied()
-            .max();
-        max_level
-            .map(|lvl| lvl.max(self.options.default_level))
-            .unwrap_or(self.options.default_level)
+        // Fails once the receiver is gone, the record is only written then
+        let _ = sender.send(OwnedRecord::new(record, timestamp));
     }
 
-    pub fn shutdown(self) -> Result<(), NonBlockingLoggerError> {
-        let compare = self.running.compare_exchange(
-            true,
-            false,
-            std::sync::atomic::Ordering::SeqCst,
-            std::sync::atomic::Ordering::SeqCst,
-        );
+    /// See [`write_record`](Self::write_record)
+    fn send_record(&self, record: &Record) -> bool {
+        let target = if !record.target().is_empty() {
+            record.target()
+        } else {
+            record.module_path().unwrap_or_default()
+        };
 
-        if compare.is_err() {
-            Err(NonBlockingLoggerError::Error {
-                reason: "Failed to shutdown logger: It was already shutted down".to_string(),
-            })
+        let thread = self.options.thread_label();
+        let uptime = self.options.uptime();
+
+        #[cfg(feature = "timestamps")]
+        let now = self.options.now();
+
+        let module = record.module_path().filter(|_| self.options.module_path);
+
+        let route = self
+            .options
+            .route_prefixes
+            .iter()
+            .position(|prefix| matches_module(target, prefix));
+
+        let (file, line) = if self.options.source_location {
+            (record.file(), record.line())
         } else {
-            Ok(())
-        }
-    }
-}
+            (None, None)
+        };
 
-impl Log for NonBlockingLogger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        &metadata.level().to_level_filter()
-            <= self
+        let args = format::Message::new(record);
+
+        // Synchronous records are always assembled here, they don't reach the worker
+        let sync = route.is_none()
+            && self
                 .options
-                .module_levels
-                .iter()
-                /* At this point the Vec is already sorted so that we can simply take
-                 * the first match
-                 */
-                .find(|(name, _level)| metadata.target().starts_with(name))
-                .map(|(_name, level)| level)
-                .unwrap_or(&self.options.default_level)
-    }
+                .sync_level
+                .is_some_and(|level| record.level() <= level);
+        let deferred = self.options.deferred_formatting && !sync;
 
-    fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            let level_string = {
-                #[cfg(feature = "colors")]
-                {
-                    if self.options.colors {
-                        match record.level() {
-                            log::Level::Error => format!("{:<5}", record.level().to_string())
-                                .red()
-                                .to_string(),
-                            log::Level::Warn => format!("{:<5}", record.level().to_string())
-                                .yellow()
-                                .to_string(),
-                            log::Level::Info => format!("{:<5}", record.level().to_string())
-                                .cyan()
-                                .to_string(),
-                            log::Level::Debug => format!("{:<5}", record.level().to_string())
-                                .purple()
-                                .to_string(),
-                            log::Level::Trace => format!("{:<5}", record.level().to_string())
-                                .normal()
-                                .to_string(),
-                        }
-                    } else {
-                        format!("{:<5}", record.level().to_string())
-                    }
-                }
-                #[cfg(not(feature = "colors"))]
-                {
-                    format!("{:<5}", record.level().to_string())
+        // Rendered up front only when it has to be cut
+        let mut truncated = String::new();
+
+        let args: &dyn std::fmt::Display = match self.options.max_message_len {
+            Some(max_len) if !deferred => {
+                format::write_truncated(&mut truncated, &args, max_len);
+                &truncated
+            }
+            _ => &args,
+        };
+
+        let message = if deferred {
+            let mut buffer = self.take_buffer();
+
+            match self.options.max_message_len {
+                Some(max_len) => format::write_truncated(&mut buffer, args, max_len),
+                None => {
+                    // Writing into a String can't fail
+                    let _ = write!(buffer, "{}", args);
                 }
-            };
+            }
 
-            let target = if !record.target().is_empty() {
-                record.target()
-            } else {
-                record.module_path().unwrap_or_default()
-            };
+            worker::WorkerMessage::Record(format::DeferredRecord {
+                level: record.level(),
+                target: target.to_string(),
+                thread,
+                module: module.map(str::to_string),
+                file: file.map(str::to_string),
+                line,
+                uptime,
+                #[cfg(feature = "timestamps")]
+                now,
+                args: buffer,
+                route,
+            })
+        } else {
+            // Short lines stay on the stack, a pooled buffer is only taken once they grow too long
+            let mut writer = line::LineWriter::new(|| self.take_buffer());
 
-            let thread = {
-                #[cfg(feature = "threads")]
-                if self.options.threads {
-                    let thread = std::thread::current();
-
-                    format!("@{}", {
-                        #[cfg(feature = "nightly")]
-                        {
-                            thread.name().unwrap_or(&thread.id().as_u64().to_string())
-                        }
-
-                        #[cfg(not(feature = "nightly"))]
-                        {
-                            thread.name().unwrap_or("?")
-                        }
-                    })
-                } else {
-                    "".to_string()
+            self.options.write_line(
+                &format::LineParts {
+                    level: record.level(),
+                    target,
+                    thread: &thread,
+                    module,
+                    file,
+                    line,
+                    uptime,
+                    #[cfg(feature = "timestamps")]
+                    now,
+                    args: &args,
+                    #[cfg(feature = "kv")]
+                    key_values: Some(record.key_values()),
+                },
+                &mut writer,
+            );
+
+            let line = writer.into_line();
+
+            if sync {
+                if self.options.ordered_sync {
+                    self.wait_for_worker();
                 }
 
-                #[cfg(not(feature = "threads"))]
-                ""
-            };
+                io::write_stdio_with_retry(
+                    line.as_bytes(),
+                    self.options.output,
+                    self.options.sync_strip_ansi,
+                );
 
-            let timestamp = {
-                #[cfg(feature = "timestamps")]
-                match self.options.timestamps {
-                    Timestamps::None => "".to_string(),
-                    Timestamps::Utc => format!(
-                        "{} ",
-                        OffsetDateTime::now_utc()
-                            .format(
-                                &self
-                                    .options
-                                    .timestamps_format
-                                    .unwrap_or(TIMESTAMP_FORMAT_UTC)
-                            )
-                            .unwrap()
-                    ),
-                    Timestamps::UtcOffset(offset) => format!(
-                        "{} ",
-                        OffsetDateTime::now_utc()
-                            .to_offset(offset)
-                            .format(
-                                &self
-                                    .options
-                                    .timestamps_format
-                                    .unwrap_or(TIMESTAMP_FORMAT_OFFSET)
-                            )
-                            .unwrap()
-                    ),
+                // The other sinks, the error file and the ring buffer get it from the worker. The
+                // line counts as written even if the copy is dropped
+                if self.options.sync_copies {
+                    self.send_message(worker::WorkerMessage::Synced(record.level(), line));
                 }
 
-                #[cfg(not(feature = "timestamps"))]
-                ""
-            };
+                return true;
+            }
 
-            let message = format!(
-                "{}{} [{}{}] {}\r\n",
-                timestamp,
-                level_string,
-                target,
-                thread,
-                record.args()
-            );
+            match route {
+                Some(route) => worker::WorkerMessage::Routed(route, record.level(), line),
+                None => match &self.options.thread_batches {
+                    Some(batches) => {
+                        batches.push(record.level(), line, |lines| {
+                            self.send_message(worker::WorkerMessage::Batch(lines));
+                        });
+                        return true;
+                    }
+                    None => worker::WorkerMessage::Log(record.level(), line),
+                },
+            }
+        };
+
+        self.send_message(message)
+    }
+
+    /// Sends a message to the worker according to the [`FullPolicy`], returns whether it was sent
+    fn send_message(&self, message: worker::WorkerMessage) -> bool {
+        // Before sending, so the application hears of a full channel before `Block` waits on it
+        if let Some(backpressure) = &self.options.backpressure {
+            backpressure.check(self.sender.len(), self.sender.capacity());
+        }
+
+        let sent = match self.options.full_policy {
+            FullPolicy::Drop => self.sender.try_send(message),
+            FullPolicy::Block => self
+                .sender
+                .send(message)
+                .map_err(|err| TrySendError::Disconnected(err.0)),
+        };
+
+        let err = match sent {
+            Ok(()) => return true,
+            Err(err) => err,
+        };
 
-            if let Err(err) = self.sender.send(worker::WorkerMessage::Log(message)) {
+        let mut written = 0;
+
+        if self.options.error_fallback {
+            let (TrySendError::Full(message) | TrySendError::Disconnected(message)) = &err;
+
+            written = self.write_error_fallback(message);
+            if written == message.records() {
+                return true;
+            }
+        }
+
+        match err {
+            TrySendError::Full(message) => {
+                // Counted instead of reported one by one, the worker writes a summary
+                self.dropped
+                    .fetch_add(message.records() - written, Ordering::Relaxed);
+                false
+            }
+            err => {
                 io::write_stderr_with_retry_internal(&format!("Failed to schedule log: {}", err));
+                false
             }
         }
     }
 
-    fn flush(&self) {
-        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+    /// Writes an error record which couldn't be sent to STDERR, see
+    /// [`NonBlockingLoggerBuilder::with_error_fallback`]. Returns the number of records written,
+    /// only the error lines of a batch are.
+    fn write_error_fallback(&self, message: &worker::WorkerMessage) -> u64 {
+        let rendered;
 
-        match self.sender.send(worker::WorkerMessage::Flush(done_tx)) {
-            Ok(_) => {
-                // Block until flush completes
-                let _ = done_rx.recv();
+        let line = match message {
+            worker::WorkerMessage::Log(Level::Error, line)
+            | worker::WorkerMessage::Routed(_, Level::Error, line) => line.as_bytes(),
+            worker::WorkerMessage::Record(record) if record.level == Level::Error => {
+                let mut writer = line::LineWriter::new(String::new);
+                self.options.write_line(&record.parts(), &mut writer);
+                rendered = writer.into_line();
+                rendered.as_bytes()
             }
-            Err(err) => {
-                io::write_stderr_with_retry_internal(&format!(
-                    "Failed to send flush request to logger worker: {}",
-                    err
-                ));
+            worker::WorkerMessage::Batch(lines) => {
+                let mut written = 0;
+
+                for (_level, line) in lines.iter().filter(|(level, _line)| *level == Level::Error) {
+                    self.write_fallback_line(line.as_bytes());
+                    written += 1;
+                }
+
+                // The other lines of the batch are lost
+                return written;
             }
+            _ => return 0,
+        };
+
+        self.write_fallback_line(line);
+        1
+    }
+
+    fn write_fallback_line(&self, line: &[u8]) {
+        let strip_ansi = self
+            .options
+            .strip_ansi
+            .unwrap_or(!Output::Stderr.sink().is_terminal());
+        io::write_stdio_with_retry(line, Output::Stderr, strip_ansi);
+    }
+
+    /// Sends the lines held in thread batches to the worker, see
+    /// [`NonBlockingLoggerBuilder::with_thread_local_batching`]
+    fn send_thread_batches(&self) {
+        if let Some(batches) = &self.options.thread_batches {
+            batches.drain(
+                || true,
+                |lines| {
+                    self.send_message(worker::WorkerMessage::Batch(lines));
+                },
+            );
+        }
+    }
+
+    /// Sends text from `aprintln!`/`aeprintln!` to the worker, giving it back if it can't be sent
+    #[cfg(feature = "macros")]
+    pub(crate) fn print(&self, stream: worker::Stream, text: String) -> Result<(), String> {
+        let message = worker::WorkerMessage::Print(stream, text);
+
+        let sent = match self.options.full_policy {
+            FullPolicy::Drop => self.sender.try_send(message),
+            FullPolicy::Block => self
+                .sender
+                .send(message)
+                .map_err(|err| TrySendError::Disconnected(err.0)),
+        };
+
+        match sent.map_err(TrySendError::into_inner) {
+            Ok(()) => Ok(()),
+            Err(worker::WorkerMessage::Print(_, text)) => Err(text),
+            Err(_) => unreachable!("the message is a print"),
+        }
+    }
+
+    /// Copy of the last log lines kept by
+    /// [`with_ring_buffer`](NonBlockingLoggerBuilder::with_ring_buffer), oldest first.
+    ///
+    /// Safe to call from any thread while the worker writes. Lines still waiting in the channel
+    /// aren't there yet, call [`flush`](Log::flush) first to include them. Empty without a
+    /// ring buffer.
+    pub fn dump_ring_buffer(&self) -> Vec<u8> {
+        self.ring_buffer
+            .as_ref()
+            .map(sink::RingBuffer::dump)
+            .unwrap_or_default()
+    }
+
+    /// Whether messages logged now are still written.
+    ///
+    /// Turns `false` once the logger is stopped, by [`shutdown`](#method.shutdown),
+    /// [`stop`](#method.stop) or dropping the [`LoggerGuard`], and when the worker thread is
+    /// gone. Panics in sinks are caught and don't stop the worker, see [`Sink`], but messages
+    /// are silently lost once it is gone, so a long running service can check this to alert or
+    /// build a new logger.
+    ///
+    /// A worker which is stuck on a stalled output is still alive and counts as healthy, see
+    /// [`with_write_timeout`](NonBlockingLoggerBuilder::with_write_timeout).
+    ///
+    /// ```
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_capture(&CaptureSink::new())
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(logger.is_healthy());
+    ///
+    /// logger.stop().unwrap();
+    /// assert!(!logger.is_healthy());
+    /// ```
+    pub fn is_healthy(&self) -> bool {
+        self.running.load(Ordering::SeqCst) && self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Stops the worker thread, after it has written the messages still waiting in the channel.
+    ///
+    /// Waits until the pending messages are written and the outputs are flushed. Messages
+    /// logged after this call are not written. Use
+    /// [`with_write_timeout`](NonBlockingLoggerBuilder::with_write_timeout) so a stalled output
+    /// can't keep the shutdown waiting forever.
+    pub fn shutdown(self) -> Result<(), NonBlockingLoggerError> {
+        self.stop()
+    }
+
+    /// Like [`shutdown`](#method.shutdown), without consuming the logger.
+    ///
+    /// Useful when the logger is shared behind an `Arc` or stored in a struct, e.g. to stop it
+    /// from the `Drop` implementation of that struct. The logger and its clones stay usable
+    /// values, but messages logged after this call are not written.
+    ///
+    /// Only the first call stops the worker: calling it again, or calling
+    /// [`shutdown`](#method.shutdown) afterwards, does nothing and returns an error.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLogger, NonBlockingLoggerBuilder};
+    ///
+    /// struct App {
+    ///     logger: NonBlockingLogger,
+    /// }
+    ///
+    /// impl Drop for App {
+    ///     fn drop(&mut self) {
+    ///         let _ = self.logger.stop();
+    ///     }
+    /// }
+    ///
+    /// let capture = CaptureSink::new();
+    /// let app = App {
+    ///     logger: NonBlockingLoggerBuilder::new()
+    ///         .with_capture(&capture)
+    ///         .build()
+    ///         .unwrap(),
+    /// };
+    ///
+    /// app.logger.log(&Record::builder().args(format_args!("Bye")).level(Level::Info).build());
+    /// assert!(app.logger.stop().is_ok());
+    /// assert!(app.logger.stop().is_err());
+    ///
+    /// drop(app);
+    /// assert_eq!(capture.lines().len(), 1);
+    /// ```
+    pub fn stop(&self) -> Result<(), NonBlockingLoggerError> {
+        self.stop_with_stats().map(|_stats| ())
+    }
+
+    /// Like [`shutdown`](#method.shutdown), and reports how many messages were still pending
+    /// and how many were lost.
+    ///
+    /// ```
+    /// use log::{Level, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// for i in 0..10 {
+    ///     logger.log(&Record::builder().args(format_args!("Message {}", i)).level(Level::Info).build());
+    /// }
+    ///
+    /// // Some of the messages may still be waiting for the worker
+    /// let stats = logger.shutdown_with_stats().unwrap();
+    ///
+    /// assert!(stats.drained <= 10);
+    /// assert_eq!(stats.dropped, 0);
+    /// assert_eq!(capture.lines().len(), 10);
+    /// ```
+    pub fn shutdown_with_stats(self) -> Result<ShutdownStats, NonBlockingLoggerError> {
+        self.stop_with_stats()
+    }
+
+    fn stop_with_stats(&self) -> Result<ShutdownStats, NonBlockingLoggerError> {
+        let compare =
+            self.running
+                .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst);
+
+        if compare.is_err() {
+            return Err(NonBlockingLoggerError::Error {
+                reason: "Failed to shutdown logger: It was already shutted down".to_string(),
+            });
+        }
+
+        self.send_thread_batches();
+        // Whatever the worker takes from now on was waiting when the shutdown started
+        let received = self.received.load(Ordering::SeqCst);
+
+        // Queued behind the pending messages, the worker stops once it gets there
+        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+        let dropped = match self.sender.send(worker::WorkerMessage::Shutdown(done_tx)) {
+            Ok(()) => done_rx.recv().unwrap_or_default(),
+            Err(_) => 0,
+        };
+
+        Ok(ShutdownStats {
+            drained: (self.received.load(Ordering::SeqCst) - received) as usize,
+            // Dropped after the worker wrote its last summary
+            dropped: dropped + self.dropped.load(Ordering::Relaxed),
+        })
+    }
+}
+
+impl Log for NonBlockingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.level() > STATIC_MAX_LEVEL {
+            return false;
+        }
+
+        // A max level lowered with `log::set_max_level` by someone else caps the global logger
+        if metadata.level() > log::max_level() && self.is_global() {
+            return false;
+        }
+
+        let enabled = metadata.level() <= self.effective_level(metadata.target());
+
+        match &self.options.filter {
+            Some(filter) => enabled && (filter.0)(metadata),
+            None => enabled,
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if cfg!(debug_assertions)
+            && let Some(reported) = &self.options.detect_blocking
+        {
+            let started = Instant::now();
+            self.try_log_enabled(record);
+            self.report_blocking(record, started.elapsed(), reported);
+        } else {
+            self.try_log_enabled(record);
         }
     }
+
+    fn flush(&self) {
+        self.write_pending_repeated();
+        self.wait_for_worker();
+    }
 }
 
 /// The colored crate will disable colors when STDOUT is not a terminal. This method overrides this
 /// behavior to check the status of STDERR instead.
-#[cfg(all(feature = "colored", feature = "stderr"))]
+#[cfg(feature = "colored")]
 fn use_stderr_for_colors() {
     use std::io::{IsTerminal, stderr};
 
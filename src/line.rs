@@ -0,0 +1,107 @@
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+/// Lines up to this many bytes are sent to the worker without a heap allocation.
+///
+/// Kept small so a [`Line`] is no larger than the other messages sent to the worker.
+pub(crate) const INLINE_LINE_CAPACITY: usize = 128;
+
+/// Assembled log line, stored in place when it is short enough
+pub(crate) enum Line {
+    Inline {
+        len: u8,
+        buf: [u8; INLINE_LINE_CAPACITY],
+    },
+    Heap(String),
+    /// Payload rendered by the caller, which doesn't have to be UTF-8, see `log_bytes`
+    Bytes(Vec<u8>),
+}
+
+impl Line {
+    /// The line as text, only copied if it is a payload which isn't valid UTF-8
+    pub fn as_text(&self) -> Cow<'_, str> {
+        match self {
+            Line::Inline { len, buf } => Cow::Borrowed(inline_str(buf, *len as usize)),
+            Line::Heap(line) => Cow::Borrowed(line),
+            Line::Bytes(payload) => String::from_utf8_lossy(payload),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Line::Inline { len, buf } => &buf[..*len as usize],
+            Line::Heap(line) => line.as_bytes(),
+            Line::Bytes(payload) => payload,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+}
+
+fn inline_str(buf: &[u8; INLINE_LINE_CAPACITY], len: usize) -> &str {
+    // SAFETY: `LineWriter` only copies whole `str`s into the buffer, so the first `len` bytes
+    // are always valid UTF-8
+    unsafe { std::str::from_utf8_unchecked(&buf[..len]) }
+}
+
+/// Assembles a [`Line`] in place, moving it to a buffer from `spill` once it no longer fits
+pub(crate) struct LineWriter<F> {
+    line: Line,
+    spill: F,
+}
+
+impl<F: FnMut() -> String> LineWriter<F> {
+    pub fn new(spill: F) -> Self {
+        LineWriter {
+            line: Line::Inline {
+                len: 0,
+                buf: [0; INLINE_LINE_CAPACITY],
+            },
+            spill,
+        }
+    }
+
+    pub fn into_line(self) -> Line {
+        self.line
+    }
+
+    /// Turns the line into a binary payload, keeping what was written so far, and returns it
+    #[cfg(all(target_os = "linux", feature = "journald"))]
+    pub fn bytes_mut(&mut self) -> &mut Vec<u8> {
+        if !matches!(self.line, Line::Bytes(_)) {
+            self.line = Line::Bytes(self.line.as_bytes().to_vec());
+        }
+
+        match &mut self.line {
+            Line::Bytes(payload) => payload,
+            _ => unreachable!("the line was just turned into bytes"),
+        }
+    }
+}
+
+impl<F: FnMut() -> String> Write for LineWriter<F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match &mut self.line {
+            Line::Heap(line) => line.push_str(s),
+            Line::Bytes(payload) => payload.extend_from_slice(s.as_bytes()),
+            Line::Inline { len, buf } => {
+                let start = *len as usize;
+                let end = start + s.len();
+
+                if end <= INLINE_LINE_CAPACITY {
+                    buf[start..end].copy_from_slice(s.as_bytes());
+                    *len = end as u8;
+                } else {
+                    let mut line = (self.spill)();
+                    line.push_str(inline_str(buf, start));
+                    line.push_str(s);
+                    self.line = Line::Heap(line);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -73,3 +73,140 @@ macro_rules! eprintln {
         $crate::io::write_stderr_with_retry(&message_with_newline)
     }};
 }
+
+/// Non-blocking `print!` macro that uses write_stdout_with_retry.
+///
+/// Like [`println!`](crate::println), but doesn't append a newline, for partial lines such as
+/// progress bars or streamed output. Stdout is flushed afterwards, so the text appears right
+/// away instead of waiting for the end of the line. Both the write and the flush retry on
+/// `WouldBlock`, where `std::print!` would panic.
+///
+/// Called without arguments it only flushes stdout.
+///
+/// # Examples
+///
+/// ```
+/// use log_nonblock::print;
+///
+/// for percent in [0, 50, 100] {
+///     print!("\rProgress: {}%", percent);
+/// }
+/// print!("\n");
+/// print!(); // Just a flush
+/// ```
+///
+/// # Note
+///
+/// This macro writes directly to stdout synchronously (with retry logic).
+/// It is independent of the `NonBlockingLogger` and doesn't use the
+/// background worker thread.
+#[macro_export]
+macro_rules! print {
+    () => {
+        $crate::io::flush_stdout_with_retry()
+    };
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        $crate::io::write_stdout_with_retry(&message);
+        $crate::io::flush_stdout_with_retry()
+    }};
+}
+
+/// Non-blocking `eprint!` macro that uses write_stderr_with_retry.
+///
+/// Like [`eprintln!`](crate::eprintln), but doesn't append a newline. Stderr is flushed
+/// afterwards, with the same retry logic as the write.
+///
+/// Called without arguments it only flushes stderr.
+///
+/// # Examples
+///
+/// ```
+/// use log_nonblock::eprint;
+///
+/// eprint!("Retrying");
+/// eprint!("...");
+/// eprint!("\n");
+/// eprint!(); // Just a flush
+/// ```
+///
+/// # Note
+///
+/// This macro writes directly to stderr synchronously (with retry logic).
+/// It is independent of the `NonBlockingLogger` and doesn't use the
+/// background worker thread.
+#[macro_export]
+macro_rules! eprint {
+    () => {
+        $crate::io::flush_stderr_with_retry()
+    };
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        $crate::io::write_stderr_with_retry(&message);
+        $crate::io::flush_stderr_with_retry()
+    }};
+}
+
+/// `println!` which is written by the worker thread of the global logger.
+///
+/// The message is formatted on the calling thread and sent to the worker of the logger
+/// registered with [`init`](crate::NonBlockingLoggerBuilder::init), which writes it to stdout
+/// in order with the log lines. The caller doesn't wait for the write, like with `log` macros.
+///
+/// Falls back to writing synchronously, like [`println!`](crate::println), when no logger was
+/// registered, or the message can't be sent to it (e.g. the channel is full with
+/// [`FullPolicy::Drop`](crate::FullPolicy::Drop)).
+///
+/// # Examples
+///
+/// ```
+/// use log_nonblock::aprintln;
+///
+/// let logger = log_nonblock::NonBlockingLoggerBuilder::new().init().unwrap();
+///
+/// aprintln!("Hello, world!");
+/// aprintln!("The answer is {}", 42);
+/// aprintln!(); // Just a newline
+///
+/// log::logger().flush();
+/// ```
+#[macro_export]
+macro_rules! aprintln {
+    () => {
+        $crate::io::write_stdout_async(::std::string::String::from("\n"))
+    };
+    ($($arg:tt)*) => {{
+        let mut message = format!($($arg)*);
+        message.push('\n');
+        $crate::io::write_stdout_async(message)
+    }};
+}
+
+/// `eprintln!` which is written by the worker thread of the global logger.
+///
+/// Works like [`aprintln!`](crate::aprintln), writing to stderr instead of stdout.
+///
+/// # Examples
+///
+/// ```
+/// use log_nonblock::aeprintln;
+///
+/// let logger = log_nonblock::NonBlockingLoggerBuilder::new().init().unwrap();
+///
+/// aeprintln!("Error occurred!");
+/// aeprintln!("Error code: {}", 500);
+/// aeprintln!(); // Just a newline
+///
+/// log::logger().flush();
+/// ```
+#[macro_export]
+macro_rules! aeprintln {
+    () => {
+        $crate::io::write_stderr_async(::std::string::String::from("\n"))
+    };
+    ($($arg:tt)*) => {{
+        let mut message = format!($($arg)*);
+        message.push('\n');
+        $crate::io::write_stderr_async(message)
+    }};
+}
@@ -0,0 +1,70 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Token bucket limiting how many messages a target can log,
+/// see [`NonBlockingLoggerBuilder::with_rate_limit`](crate::NonBlockingLoggerBuilder::with_rate_limit)
+#[derive(Debug)]
+pub(crate) struct RateLimit {
+    pub target: String,
+    max_per_interval: u32,
+    interval: Duration,
+    /// Shared by all clones of the logger, `log()` is called from many threads
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    /// Messages dropped since the last one that got through
+    suppressed: u64,
+}
+
+impl RateLimit {
+    pub fn new(target: &str, max_per_interval: u32, interval: Duration) -> Self {
+        Self {
+            target: target.to_string(),
+            max_per_interval,
+            interval,
+            bucket: Mutex::new(Bucket {
+                tokens: max_per_interval as f64,
+                refilled: Instant::now(),
+                suppressed: 0,
+            }),
+        }
+    }
+
+    /// Takes a token for one message.
+    ///
+    /// Returns `None` if the message must be dropped, otherwise the number of messages
+    /// dropped since the previous one was allowed, so the caller can report them.
+    pub fn acquire(&self) -> Option<u64> {
+        let mut bucket = self
+            .bucket
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled);
+
+        // Tokens are refilled continuously, `max_per_interval` of them per `interval`
+        let refill = if self.interval.is_zero() {
+            self.max_per_interval as f64
+        } else {
+            elapsed.as_secs_f64() / self.interval.as_secs_f64() * self.max_per_interval as f64
+        };
+
+        bucket.tokens = (bucket.tokens + refill).min(self.max_per_interval as f64);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            Some(std::mem::take(&mut bucket.suppressed))
+        } else {
+            bucket.suppressed += 1;
+
+            None
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use log::{Level, Record};
+use std::time::SystemTime;
+
+/// Owned copy of a record, sent to the channel set with
+/// [`with_record_sink`](crate::NonBlockingLoggerBuilder::with_record_sink).
+///
+/// The fields are taken from the record as it was logged: the message is rendered in full,
+/// without the limit of [`with_max_message_len`](crate::NonBlockingLoggerBuilder::with_max_message_len),
+/// and the target, file and line are kept whatever the display options are.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OwnedRecord {
+    pub level: Level,
+    pub target: String,
+    /// Rendered `record.args()`
+    pub args: String,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Time of logging, from the [clock](crate::NonBlockingLoggerBuilder::with_clock) of the
+    /// logger when one is set
+    pub timestamp: SystemTime,
+}
+
+impl OwnedRecord {
+    pub(crate) fn new(record: &Record, timestamp: SystemTime) -> Self {
+        Self {
+            level: record.level(),
+            target: record.target().to_string(),
+            args: record.args().to_string(),
+            module_path: record.module_path().map(str::to_string),
+            file: record.file().map(str::to_string),
+            line: record.line(),
+            timestamp,
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use log::Level;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Sampling starts once the channel is filled above this percentage of its capacity
+pub(crate) const HIGH_WATER_PERCENT: usize = 75;
+
+/// The range above the high-water mark is split into this many bands, each one keeping half as
+/// many records as the previous: 1 in 2, 1 in 4, 1 in 8 and 1 in 16 near a full channel
+const SAMPLING_BANDS: usize = 4;
+
+/// Keeps 1 in N debug and trace records while the channel is close to full,
+/// see [`NonBlockingLoggerBuilder::with_adaptive_sampling`](crate::NonBlockingLoggerBuilder::with_adaptive_sampling)
+#[derive(Debug, Default)]
+pub(crate) struct Sampler {
+    /// Records considered for sampling, picks which 1 in N is kept
+    seen: AtomicU64,
+    sampled_out: AtomicU64,
+}
+
+impl Sampler {
+    /// Whether a record at `level` is kept with `len` messages waiting in a channel of `capacity`
+    pub fn keep(&self, level: Level, len: usize, capacity: Option<usize>) -> bool {
+        // Unbounded channels never fill up
+        let Some(capacity) = capacity else {
+            return true;
+        };
+
+        let high_water = capacity * HIGH_WATER_PERCENT / 100;
+
+        if level <= Level::Info || len <= high_water {
+            return true;
+        }
+
+        let band = ((len - high_water) * SAMPLING_BANDS / (capacity - high_water).max(1))
+            .min(SAMPLING_BANDS - 1);
+        let keep_one_in = 2u64 << band;
+
+        if self
+            .seen
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(keep_one_in)
+        {
+            return true;
+        }
+
+        self.sampled_out.fetch_add(1, Ordering::Relaxed);
+        false
+    }
+
+    pub fn sampled_out(&self) -> u64 {
+        self.sampled_out.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,152 @@
+use log::LevelFilter;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::{NonBlockingLogger, matches_module};
+
+/// Levels set with [`NonBlockingLogger::scoped_module_level`], shared by all clones of the logger
+#[derive(Debug, Default)]
+pub(crate) struct ScopedLevels {
+    /// Number of entries in `levels`, so the lock is only taken while a scope is active
+    len: AtomicUsize,
+    next_id: AtomicU64,
+    /// Oldest first, every entry is removed by the guard which added it
+    levels: RwLock<Vec<(u64, String, LevelFilter)>>,
+}
+
+impl ScopedLevels {
+    fn push(&self, target: &str, level: LevelFilter) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+        let mut levels = self
+            .levels
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        levels.push((id, target.to_string(), level));
+        self.len.store(levels.len(), Ordering::Relaxed);
+
+        id
+    }
+
+    fn remove(&self, id: u64) {
+        let mut levels = self
+            .levels
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        levels.retain(|(level_id, _target, _level)| *level_id != id);
+        self.len.store(levels.len(), Ordering::Relaxed);
+    }
+
+    /// Level of the most recent scope covering `target`
+    pub fn find(&self, target: &str) -> Option<LevelFilter> {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+
+        self.levels
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .rev()
+            .find(|(_id, name, _level)| matches_module(target, name))
+            .map(|(_id, _name, level)| *level)
+    }
+
+    /// Most verbose level of the active scopes
+    pub fn max(&self) -> Option<LevelFilter> {
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+
+        self.levels
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .iter()
+            .map(|(_id, _name, level)| *level)
+            .max()
+    }
+}
+
+/// Restores the level of a module when dropped, returned by
+/// [`NonBlockingLogger::scoped_module_level`].
+///
+/// Scopes are kept on a stack: while several are active, the most recent one covering a target
+/// decides its level. Dropping a guard only removes its own scope, so the remaining ones stay in
+/// effect no matter in which order, or on which threads, the guards are dropped. Once every
+/// scope covering a target is gone, the levels set with the builder apply again.
+#[must_use = "The level is restored as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct ScopedLevel {
+    logger: NonBlockingLogger,
+    id: u64,
+}
+
+impl NonBlockingLogger {
+    /// Sets the level of `target` and its sub-modules until the returned guard is dropped, e.g.
+    /// to trace a single test or a code path while debugging.
+    ///
+    /// The scoped level takes precedence over the levels set with the builder, including those
+    /// of sub-modules, but not over [`STATIC_MAX_LEVEL`](crate::STATIC_MAX_LEVEL) or the
+    /// [filter](crate::NonBlockingLoggerBuilder::with_filter). It is shared by all clones of the
+    /// logger. For the global logger, the max level of the `log` crate is raised too, and
+    /// lowered again when the guard is dropped, unless
+    /// [`with_manage_max_level`](crate::NonBlockingLoggerBuilder::with_manage_max_level) is off.
+    /// See [`ScopedLevel`] for overlapping scopes.
+    ///
+    /// ```
+    /// use log::{Level, LevelFilter, Log, Record};
+    /// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+    ///
+    /// let capture = CaptureSink::new();
+    /// let logger = NonBlockingLoggerBuilder::new()
+    ///     .with_level(LevelFilter::Info)
+    ///     .without_timestamps()
+    ///     .with_colors(false)
+    ///     .with_capture(&capture)
+    ///     .build()
+    ///     .unwrap();
+    ///
+    /// let trace = |message: &str| {
+    ///     logger.log(&Record::builder().args(format_args!("{}", message)).level(Level::Trace).target("app::db").build());
+    /// };
+    ///
+    /// trace("Hidden");
+    /// {
+    ///     let _app = logger.scoped_module_level("app", LevelFilter::Trace);
+    ///     trace("Traced");
+    ///
+    ///     let db = logger.scoped_module_level("app::db", LevelFilter::Info);
+    ///     trace("Hidden again");
+    ///     drop(db);
+    ///
+    ///     trace("Traced again");
+    /// }
+    /// trace("Hidden once more");
+    /// logger.flush();
+    ///
+    /// assert_eq!(capture.lines(), ["TRACE [app::db] Traced", "TRACE [app::db] Traced again"]);
+    /// ```
+    pub fn scoped_module_level(&self, target: &str, level: LevelFilter) -> ScopedLevel {
+        let id = self.options.scoped_levels.push(target, level);
+        self.update_global_max_level();
+
+        ScopedLevel {
+            logger: self.clone(),
+            id,
+        }
+    }
+
+    /// Updates the max level of the `log` crate if this is the global logger, and it manages it
+    fn update_global_max_level(&self) {
+        if self.options.manage_max_level && self.is_global() {
+            log::set_max_level(self.max_level());
+        }
+    }
+}
+
+impl Drop for ScopedLevel {
+    fn drop(&mut self) {
+        self.logger.options.scoped_levels.remove(self.id);
+        self.logger.update_global_max_level();
+    }
+}
@@ -0,0 +1,72 @@
+use log::Level;
+
+/// Numeric severity of each log level, for outputs which don't use level names
+///
+/// The syslog and journald outputs take the severity of each message from this map, see
+/// [`with_severity_map`](crate::NonBlockingLoggerBuilder::with_severity_map).
+///
+/// ```
+/// use log::Level;
+/// use log_nonblock::SeverityMap;
+///
+/// assert_eq!(SeverityMap::SYSLOG.get(Level::Error), 3);
+/// assert_eq!(SeverityMap::SYSLOG.get(Level::Trace), 7);
+///
+/// // Report warnings as notices
+/// let map = SeverityMap::new(3, 5, 6, 7, 7);
+/// assert_eq!(map.get(Level::Warn), 5);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SeverityMap {
+    error: u8,
+    warn: u8,
+    info: u8,
+    debug: u8,
+    trace: u8,
+}
+
+impl SeverityMap {
+    /// Severities defined by [RFC 5424](https://datatracker.ietf.org/doc/html/rfc5424#section-6.2.1):
+    /// error=3, warn=4, info=6, debug/trace=7
+    ///
+    /// Journald priorities use the same values.
+    pub const SYSLOG: SeverityMap = SeverityMap::new(3, 4, 6, 7, 7);
+
+    /// Levels of the [GELF](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html)
+    /// `level` field: error=3, warn=4, info=6, debug/trace=7.
+    ///
+    /// GELF takes its levels from syslog, so this is the same map as [`SeverityMap::SYSLOG`],
+    /// named for collectors which are configured in GELF terms.
+    pub const GELF: SeverityMap = SeverityMap::new(3, 4, 6, 7, 7);
+
+    /// Creates a map from the severity of each level.
+    ///
+    /// Syslog and journald only know severities 0 to 7, higher ones are written as 7.
+    pub const fn new(error: u8, warn: u8, info: u8, debug: u8, trace: u8) -> Self {
+        SeverityMap {
+            error,
+            warn,
+            info,
+            debug,
+            trace,
+        }
+    }
+
+    /// Returns the severity of `level`
+    pub const fn get(&self, level: Level) -> u8 {
+        match level {
+            Level::Error => self.error,
+            Level::Warn => self.warn,
+            Level::Info => self.info,
+            Level::Debug => self.debug,
+            Level::Trace => self.trace,
+        }
+    }
+}
+
+impl Default for SeverityMap {
+    /// Returns [`SeverityMap::SYSLOG`]
+    fn default() -> Self {
+        SeverityMap::SYSLOG
+    }
+}
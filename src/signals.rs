@@ -0,0 +1,48 @@
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use signal_hook::low_level;
+use std::io;
+use std::thread;
+
+use crate::NonBlockingLogger;
+
+impl NonBlockingLogger {
+    /// Write the pending messages before the process is terminated by SIGTERM or SIGINT.
+    ///
+    /// Services stopped with SIGTERM, e.g. during a deploy, otherwise exit before the worker
+    /// wrote the last lines. Once one of these signals arrives, the logger is stopped like with
+    /// [`stop`](#method.stop), which writes everything still waiting and flushes the outputs,
+    /// then the default action of the signal terminates the process.
+    ///
+    /// A signal handler may only do async-signal-safe work: it can't allocate, take locks or
+    /// wait for the worker. The handler installed here only writes to a pipe, the logger is
+    /// stopped by a dedicated thread (`log_nonblock-signals`) which waits on that pipe.
+    ///
+    /// The process always terminates after the logger is stopped, so don't install this when
+    /// the application handles SIGTERM or SIGINT itself. Call [`stop`](#method.stop) from
+    /// that handling instead.
+    ///
+    /// This method is only available on Unix, if the `unix-signals` feature is enabled.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the signal handler can't be registered or the thread can't be spawned.
+    pub fn install_term_handler(&self) -> io::Result<()> {
+        let mut signals = Signals::new([SIGTERM, SIGINT])?;
+        let logger = self.clone();
+
+        thread::Builder::new()
+            .name("log_nonblock-signals".to_string())
+            .spawn(move || {
+                if let Some(signal) = signals.forever().next() {
+                    // Fails when the logger was already stopped, there is nothing left to write
+                    let _ = logger.stop();
+
+                    // Terminates the process, as if the handler was never installed
+                    let _ = low_level::emulate_default_handler(signal);
+                }
+            })?;
+
+        Ok(())
+    }
+}
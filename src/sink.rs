@@ -0,0 +1,398 @@
+use std::io::{self, IoSlice, IsTerminal, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use crate::file::{FileOptions, RotatingFile};
+use crate::worker::LogWorker;
+
+/// Destination for formatted log lines, written by the worker thread.
+///
+/// Every sink added with [`NonBlockingLoggerBuilder::add_sink`](crate::NonBlockingLoggerBuilder::add_sink)
+/// receives the same bytes. Sinks are written one after another, in the order they were added.
+/// Each sink handles `WouldBlock` on its own by waiting until its output becomes writable, so a
+/// slow sink delays the following ones, but an error in one sink is reported to STDERR and
+/// doesn't prevent writing to the others.
+///
+/// A sink may call [`flush`](log::Log::flush) on the logger, directly or from a `Drop`
+/// implementation. On the worker thread, flush doesn't wait for the worker, which would
+/// deadlock: it returns at once and the outputs are flushed after the current write.
+///
+/// A panic in a sink is caught by the worker and reported to STDERR. The lines being written
+/// are lost for the sinks which didn't get them yet, and the worker carries on with the next
+/// messages, still writing to the same sink:
+///
+/// ```
+/// use log::{Level, Log, Record};
+/// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder, Sink};
+/// use std::sync::{Arc, Mutex};
+///
+/// #[derive(Clone, Default)]
+/// struct Flaky(Arc<Mutex<Vec<String>>>);
+///
+/// impl Sink for Flaky {
+///     fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+///         let line = String::from_utf8_lossy(buf).to_string();
+///         if line.contains("first") {
+///             panic!("flaky sink");
+///         }
+///
+///         self.0.lock().unwrap().push(line);
+///         Ok(())
+///     }
+///
+///     fn flush(&mut self) -> std::io::Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// let flaky = Flaky::default();
+/// let capture = CaptureSink::new();
+/// let logger = NonBlockingLoggerBuilder::new()
+///     .without_timestamps()
+///     .with_colors(false)
+///     .add_sink(flaky.clone())
+///     .with_capture(&capture)
+///     .build()
+///     .unwrap();
+///
+/// for message in ["first", "second"] {
+///     logger.log(&Record::builder().args(format_args!("{}", message)).level(Level::Info).build());
+///     logger.flush();
+/// }
+///
+/// assert_eq!(capture.lines(), ["INFO  first", "INFO  second"]);
+/// assert_eq!(*flaky.0.lock().unwrap(), ["INFO  second\r\n"]);
+/// assert!(logger.is_healthy());
+/// ```
+pub trait Sink: Send {
+    /// Writes the whole buffer, which holds one or more complete log lines.
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    /// Flushes any data buffered by the sink to its destination.
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Whether every log line must be passed to [`write_all`](Sink::write_all) on its own.
+    ///
+    /// By default the worker may pipe several lines into one buffer, to save write calls.
+    /// Sinks where each write is a separate message (e.g. datagram sockets) return `true`.
+    fn is_message_oriented(&self) -> bool {
+        false
+    }
+
+    /// Whether the sink implements [`write_all_vectored`](Sink::write_all_vectored) efficiently.
+    ///
+    /// When the worker writes several lines at once, it uses vectored writes for sinks which
+    /// return `true`, and copies the lines into one buffer for [`write_all`](Sink::write_all)
+    /// otherwise.
+    fn is_write_vectored(&self) -> bool {
+        false
+    }
+
+    /// Writes all buffers in order, as if they were concatenated.
+    ///
+    /// The default implementation writes them one by one with [`write_all`](Sink::write_all).
+    fn write_all_vectored(&mut self, bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+        for buf in bufs.iter() {
+            self.write_all(buf)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reopens the destination, e.g. a log file renamed by logrotate, see
+    /// [`NonBlockingLogger::reopen`](crate::NonBlockingLogger::reopen).
+    ///
+    /// Called on the worker thread between two writes, after [`flush`](Sink::flush). The
+    /// default implementation does nothing, for sinks which have nothing to reopen.
+    fn reopen(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether the sink displays ANSI color codes.
+    ///
+    /// Color codes are removed from lines written to sinks which return `false`, unless
+    /// overridden with [`NonBlockingLoggerBuilder::with_strip_ansi`](crate::NonBlockingLoggerBuilder::with_strip_ansi).
+    fn is_terminal(&self) -> bool {
+        false
+    }
+}
+
+/// Writes log lines to STDOUT, with the non-blocking retry logic
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        LogWorker::write_buffer(&mut io::stdout(), buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().lock().flush()
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn write_all_vectored(&mut self, bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+        LogWorker::write_buffer_vectored(&mut io::stdout(), bufs)
+    }
+
+    fn is_terminal(&self) -> bool {
+        io::stdout().is_terminal()
+    }
+}
+
+/// Writes log lines to STDERR, with the non-blocking retry logic
+#[derive(Debug, Default)]
+pub struct StderrSink;
+
+impl Sink for StderrSink {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        LogWorker::write_buffer(&mut io::stderr(), buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().lock().flush()
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        true
+    }
+
+    fn write_all_vectored(&mut self, bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+        LogWorker::write_buffer_vectored(&mut io::stderr(), bufs)
+    }
+
+    fn is_terminal(&self) -> bool {
+        io::stderr().is_terminal()
+    }
+}
+
+/// Appends log lines to a file
+pub struct FileSink {
+    file: RotatingFile,
+}
+
+impl FileSink {
+    /// Opens `path` for appending, creating the file if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::with_options(FileOptions {
+            path: path.as_ref().to_path_buf(),
+            max_bytes: None,
+            max_files: 0,
+            #[cfg(feature = "compression")]
+            compress: false,
+        })
+    }
+
+    /// Opens `path` for appending and rotates it once it reaches `max_bytes`,
+    /// see [`NonBlockingLoggerBuilder::with_rotating_file`](crate::NonBlockingLoggerBuilder::with_rotating_file)
+    pub fn rotating<P: AsRef<Path>>(path: P, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        Self::with_options(FileOptions {
+            path: path.as_ref().to_path_buf(),
+            max_bytes: Some(max_bytes),
+            max_files,
+            #[cfg(feature = "compression")]
+            compress: false,
+        })
+    }
+
+    pub(crate) fn with_options(options: FileOptions) -> io::Result<Self> {
+        Ok(Self {
+            file: RotatingFile::open(options)?,
+        })
+    }
+}
+
+impl Sink for FileSink {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.file.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    /// Closes the file and opens `path` again, creating it if it was moved or deleted
+    fn reopen(&mut self) -> io::Result<()> {
+        self.file.reopen()
+    }
+}
+
+/// Stores formatted log lines in memory instead of writing them to a descriptor, for tests.
+///
+/// Lines go through the same formatting as any other output, without the trailing line ending.
+/// The sink is cheap to clone, all clones share the same buffer.
+///
+/// ```
+/// use log::{Level, Log, Record};
+/// use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+///
+/// let capture = CaptureSink::new();
+/// let logger = NonBlockingLoggerBuilder::new()
+///     .with_capture(&capture)
+///     .build()
+///     .unwrap();
+///
+/// logger.log(
+///     &Record::builder()
+///         .args(format_args!("Hello"))
+///         .level(Level::Info)
+///         .target("capture")
+///         .build(),
+/// );
+/// logger.flush();
+///
+/// let lines = capture.lines();
+/// assert_eq!(lines.len(), 1);
+/// assert!(lines[0].ends_with("[capture] Hello"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CaptureSink {
+    lines: Arc<Mutex<Vec<String>>>,
+}
+
+impl CaptureSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shared buffer with the captured lines
+    pub fn buffer(&self) -> Arc<Mutex<Vec<String>>> {
+        self.lines.clone()
+    }
+
+    /// Returns a copy of the lines captured so far.
+    ///
+    /// Call [`flush`](log::Log::flush) on the logger first to make sure all logged lines are there.
+    pub fn lines(&self) -> Vec<String> {
+        self.lock().clone()
+    }
+
+    /// Removes all captured lines
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<String>> {
+        // A test which panicked while holding the lock must not break the following ones
+        self.lines
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Sink for CaptureSink {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let line = String::from_utf8_lossy(buf);
+        self.lock().push(line.trim_end_matches("\r\n").to_string());
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Every write is exactly one line
+    fn is_message_oriented(&self) -> bool {
+        true
+    }
+
+    /// Color codes are kept, so captured lines match what a terminal would show
+    fn is_terminal(&self) -> bool {
+        true
+    }
+}
+
+/// Keeps the last bytes written in a fixed-size buffer, overwriting the oldest ones,
+/// see [`NonBlockingLoggerBuilder::with_ring_buffer`](crate::NonBlockingLoggerBuilder::with_ring_buffer)
+#[derive(Clone)]
+pub(crate) struct RingBuffer {
+    state: Arc<Mutex<RingState>>,
+}
+
+struct RingState {
+    buf: Box<[u8]>,
+    /// Index of the oldest byte
+    start: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    /// Allocates the whole buffer up front, `capacity` must not be zero
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(RingState {
+                buf: vec![0; capacity].into_boxed_slice(),
+                start: 0,
+                len: 0,
+            })),
+        }
+    }
+
+    /// Copy of the current contents, oldest byte first
+    pub fn dump(&self) -> Vec<u8> {
+        let state = self.lock();
+        let end = state.start + state.len;
+
+        if end <= state.buf.len() {
+            return state.buf[state.start..end].to_vec();
+        }
+
+        let mut dump = Vec::with_capacity(state.len);
+        dump.extend_from_slice(&state.buf[state.start..]);
+        dump.extend_from_slice(&state.buf[..end - state.buf.len()]);
+        dump
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RingState> {
+        // The state is consistent between writes, a panic elsewhere doesn't break it
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl RingState {
+    fn push(&mut self, data: &[u8]) {
+        let capacity = self.buf.len();
+
+        if data.len() >= capacity {
+            self.buf.copy_from_slice(&data[data.len() - capacity..]);
+            self.start = 0;
+            self.len = capacity;
+            return;
+        }
+
+        // Fills up to the end of the buffer, then wraps around to the front
+        let end = (self.start + self.len) % capacity;
+        let first = data.len().min(capacity - end);
+        self.buf[end..end + first].copy_from_slice(&data[..first]);
+        self.buf[..data.len() - first].copy_from_slice(&data[first..]);
+
+        let overwritten = (self.len + data.len()).saturating_sub(capacity);
+        self.start = (self.start + overwritten) % capacity;
+        self.len = (self.len + data.len()).min(capacity);
+    }
+}
+
+impl std::fmt::Debug for RingBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RingBuffer")
+            .field("capacity", &self.lock().buf.len())
+            .finish()
+    }
+}
+
+impl Sink for RingBuffer {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.lock().push(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
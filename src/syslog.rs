@@ -0,0 +1,148 @@
+use std::fmt::Write;
+use std::io;
+use std::net::UdpSocket;
+use std::os::unix::net::UnixDatagram;
+
+use crate::format::LineParts;
+use crate::{SeverityMap, Sink};
+
+/// Path of the local syslog daemon socket
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+
+/// Address used when the local socket is unavailable
+const SYSLOG_UDP_ADDR: &str = "127.0.0.1:514";
+
+/// RFC 5424 allows at most 6 digits of fractional seconds
+#[cfg(feature = "timestamps")]
+const SYSLOG_TIMESTAMP_FORMAT: &[time::format_description::FormatItem] = time::macros::format_description!(
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:6]Z"
+);
+
+/// Syslog facility, as defined by [RFC 5424](https://datatracker.ietf.org/doc/html/rfc5424#section-6.2.1)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Facility {
+    Kern = 0,
+    User = 1,
+    Mail = 2,
+    Daemon = 3,
+    Auth = 4,
+    Syslog = 5,
+    Lpr = 6,
+    News = 7,
+    Uucp = 8,
+    Cron = 9,
+    Authpriv = 10,
+    Ftp = 11,
+    Local0 = 16,
+    Local1 = 17,
+    Local2 = 18,
+    Local3 = 19,
+    Local4 = 20,
+    Local5 = 21,
+    Local6 = 22,
+    Local7 = 23,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct SyslogOptions {
+    pub facility: Facility,
+    pub app_name: String,
+}
+
+impl SyslogOptions {
+    /// Appends an RFC 5424 message, without a trailing newline, to `out`
+    ///
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+    pub fn write_line(
+        &self,
+        parts: &LineParts,
+        hostname: Option<&str>,
+        severity: &SeverityMap,
+        out: &mut dyn Write,
+    ) {
+        // A severity above 7 would spill into the facility bits
+        let priority =
+            u16::from(self.facility as u8) * 8 + u16::from(severity.get(parts.level).min(7));
+
+        // Writing a line can't fail
+        let _ = write!(out, "<{}>1 ", priority);
+
+        #[cfg(feature = "timestamps")]
+        {
+            let _ = match parts.now.format(SYSLOG_TIMESTAMP_FORMAT) {
+                Ok(timestamp) => write!(out, "{} ", timestamp),
+                Err(_) => write!(out, "- "),
+            };
+        }
+
+        #[cfg(not(feature = "timestamps"))]
+        {
+            let _ = write!(out, "- ");
+        }
+
+        let _ = write!(
+            out,
+            "{} {} {} - - [{}{}",
+            hostname.unwrap_or("-"),
+            self.app_name,
+            std::process::id(),
+            parts.target,
+            parts.thread,
+        );
+
+        parts.write_location(" ", out);
+
+        let _ = write!(out, "] {}", parts.args);
+    }
+}
+
+/// Connection to the syslog daemon, owned by the worker thread
+pub(crate) enum SyslogSocket {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+}
+
+impl SyslogSocket {
+    /// Connects to `/dev/log`, falling back to UDP on `127.0.0.1:514`
+    pub fn connect() -> io::Result<Self> {
+        let unix = UnixDatagram::unbound()
+            .and_then(|socket| socket.connect(SYSLOG_SOCKET_PATH).map(|_| socket));
+
+        match unix {
+            Ok(socket) => Ok(Self::Unix(socket)),
+            Err(_) => {
+                let socket = UdpSocket::bind("127.0.0.1:0")?;
+                socket.connect(SYSLOG_UDP_ADDR)?;
+
+                Ok(Self::Udp(socket))
+            }
+        }
+    }
+
+    fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Unix(socket) => socket.send(buf),
+            Self::Udp(socket) => socket.send(buf),
+        }
+    }
+}
+
+impl Sink for SyslogSocket {
+    /// Sends a single message, reconnecting once if the daemon went away (e.g. it was restarted)
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if self.send(buf).is_err() {
+            *self = Self::connect()?;
+            self.send(buf)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn is_message_oriented(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::Sink;
+use crate::worker::{LogWorker, Pipe};
+
+/// Delay before the first reconnection attempt, doubled after every failed attempt
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How log lines are delimited on a TCP or Unix stream connection, see
+/// [`NonBlockingLoggerBuilder::with_framing`](crate::NonBlockingLoggerBuilder::with_framing)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Framing {
+    /// Every line ends with `\r\n`, lines containing newlines can't be told apart
+    #[default]
+    Newline,
+    /// Every line is sent without its line ending, after its length in bytes as a 4-byte
+    /// big-endian integer
+    LengthPrefixed,
+}
+
+/// Address of a log collector accepting stream connections
+pub(crate) trait Endpoint: Display + Send {
+    type Stream: Pipe + Send;
+
+    /// Connects and switches the stream to non-blocking mode where supported
+    fn connect(&self) -> io::Result<Self::Stream>;
+}
+
+impl Endpoint for SocketAddr {
+    type Stream = TcpStream;
+
+    fn connect(&self) -> io::Result<TcpStream> {
+        let stream = TcpStream::connect_timeout(self, CONNECT_TIMEOUT)?;
+
+        #[cfg(unix)]
+        crate::io::set_nonblocking(stream.as_raw_fd())?;
+
+        Ok(stream)
+    }
+}
+
+/// Connection to a log collector over TCP
+pub(crate) type TcpOutput = StreamOutput<SocketAddr>;
+
+/// Connection to a log collector, owned by the worker thread
+pub(crate) struct StreamOutput<E: Endpoint> {
+    addr: E,
+    stream: Option<E::Stream>,
+    /// Worker running flag, reconnection stops once the logger is shut down
+    running: Arc<AtomicBool>,
+    framing: Framing,
+    /// Length prefix and line of the current frame, reused between lines
+    frame: Vec<u8>,
+}
+
+impl<E: Endpoint> StreamOutput<E> {
+    pub fn new(addr: E, running: Arc<AtomicBool>, framing: Framing) -> Self {
+        Self {
+            addr,
+            stream: None,
+            running,
+            framing,
+            frame: Vec::new(),
+        }
+    }
+
+    /// Connects once, so the first line doesn't wait for the connection, see
+    /// [`with_validate_on_init`](crate::NonBlockingLoggerBuilder::with_validate_on_init)
+    pub fn connect_now(&mut self) -> io::Result<()> {
+        self.stream = Some(self.addr.connect()?);
+        Ok(())
+    }
+
+    /// Connects with an exponential backoff, retrying until it succeeds.
+    ///
+    /// While the worker waits here it doesn't drain the channel, so messages are buffered in the
+    /// channel during an outage. Gives up only when the logger is shut down.
+    fn reconnect(&self) -> io::Result<E::Stream> {
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            match self.addr.connect() {
+                Ok(stream) => return Ok(stream),
+                Err(err) => {
+                    if !self.running.load(Ordering::SeqCst) {
+                        return Err(err);
+                    }
+
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Writes a whole frame, reconnecting and writing it again if the connection drops, so the
+    /// collector never gets a partial frame
+    fn write_frame(&mut self, buf: &[u8]) -> io::Result<()> {
+        loop {
+            let stream = match self.stream {
+                Some(ref mut stream) => stream,
+                None => self.stream.insert(self.reconnect()?),
+            };
+
+            match LogWorker::write_buffer(stream, buf) {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    self.stream = None;
+
+                    if !self.running.load(Ordering::SeqCst) {
+                        return Err(err);
+                    }
+
+                    crate::io::write_stderr_with_retry_internal(&format!(
+                        "Lost connection to log collector {}, reconnecting: {}",
+                        self.addr, err
+                    ));
+                }
+            }
+        }
+    }
+}
+
+impl<E: Endpoint> Sink for StreamOutput<E> {
+    /// Writes the whole buffer, reconnecting and writing it again if the connection drops.
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        match self.framing {
+            Framing::Newline => self.write_frame(buf),
+            Framing::LengthPrefixed => {
+                let mut frame = std::mem::take(&mut self.frame);
+                let result = frame_line(&mut frame, buf).and_then(|()| self.write_frame(&frame));
+                self.frame = frame;
+
+                result
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stream {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Length-prefixed frames hold exactly one line
+    fn is_message_oriented(&self) -> bool {
+        self.framing == Framing::LengthPrefixed
+    }
+}
+
+/// Replaces the contents of `frame` with the length prefix and `line`, without its line ending
+fn frame_line(frame: &mut Vec<u8>, line: &[u8]) -> io::Result<()> {
+    let line = line.strip_suffix(b"\r\n").unwrap_or(line);
+    let len = u32::try_from(line.len()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "log line too long for a frame")
+    })?;
+
+    frame.clear();
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(line);
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use log::Level;
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::line::Line;
+
+/// Lines a thread collects before sending them to the worker in one message
+pub(crate) const THREAD_BATCH_LEN: usize = 32;
+
+/// How long the worker waits for a message before writing the lines left in thread batches
+pub(crate) const THREAD_BATCH_MAX_DELAY: Duration = Duration::from_millis(100);
+
+type Lines = Vec<(Level, Line)>;
+
+/// Lines of a thread, shared with [`ThreadBatches`]
+type Batch = Arc<Mutex<Lines>>;
+
+thread_local! {
+    /// Batch of this thread, and the logger it belongs to
+    static THREAD_BATCH: RefCell<Option<(Arc<ThreadBatches>, Batch)>> =
+        const { RefCell::new(None) };
+}
+
+/// Batches of every thread which logged, see
+/// [`NonBlockingLoggerBuilder::with_thread_local_batching`](crate::NonBlockingLoggerBuilder::with_thread_local_batching).
+///
+/// Each batch is shared by its thread and this list, so its lines survive the thread. They are
+/// sent by a flush, or written by the worker once it has been idle for
+/// [`THREAD_BATCH_MAX_DELAY`].
+#[derive(Default)]
+pub(crate) struct ThreadBatches {
+    batches: Mutex<Vec<Batch>>,
+}
+
+impl fmt::Debug for ThreadBatches {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadBatches")
+            .field("threads", &lock(&self.batches).len())
+            .finish()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl ThreadBatches {
+    /// Adds a line to the batch of the current thread, and calls `send` with the batch once it
+    /// is full. `send` is called with the batch locked, so another thread can't send newer lines
+    /// of this thread first.
+    pub fn push(self: &Arc<Self>, level: Level, line: Line, send: impl FnOnce(Lines)) {
+        THREAD_BATCH.with_borrow_mut(|current| {
+            let batch = match current {
+                Some((batches, batch)) if Arc::ptr_eq(batches, self) => batch,
+                // First line of this thread, or for another logger than the last one. The batch
+                // of the previous logger stays in its list until it is sent.
+                _ => {
+                    let batch = Arc::new(Mutex::new(Vec::with_capacity(THREAD_BATCH_LEN)));
+                    lock(&self.batches).push(batch.clone());
+                    &current.insert((self.clone(), batch)).1
+                }
+            };
+
+            let mut lines = lock(batch);
+            lines.push((level, line));
+
+            if lines.len() >= THREAD_BATCH_LEN {
+                send(std::mem::replace(
+                    &mut lines,
+                    Vec::with_capacity(THREAD_BATCH_LEN),
+                ));
+            }
+        });
+    }
+
+    /// Whether no thread holds lines which weren't sent yet
+    pub fn is_empty(&self) -> bool {
+        lock(&self.batches)
+            .iter()
+            .all(|batch| lock(batch).is_empty())
+    }
+
+    /// Takes the lines of every batch for which `ready` returns `true`, checked while the batch
+    /// is locked. Batches of threads which exited are forgotten once they are empty.
+    pub fn drain(&self, mut ready: impl FnMut() -> bool, mut send: impl FnMut(Lines)) {
+        let mut batches = lock(&self.batches);
+
+        batches.retain(|batch| {
+            let mut lines = lock(batch);
+
+            if !lines.is_empty() && ready() {
+                send(std::mem::take(&mut *lines));
+            }
+
+            // The list holds the only reference once the thread is gone
+            !lines.is_empty() || Arc::strong_count(batch) > 1
+        });
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::{UnixDatagram, UnixStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+
+use crate::Sink;
+use crate::tcp::{Endpoint, Framing, StreamOutput};
+
+/// Unix socket of a local log shipper, see
+/// [`NonBlockingLoggerBuilder::with_unix_socket`](crate::NonBlockingLoggerBuilder::with_unix_socket)
+#[derive(Clone, Debug)]
+pub(crate) enum UnixSocket {
+    Stream(UnixPath),
+    Datagram(UnixPath),
+}
+
+impl UnixSocket {
+    pub fn path(&self) -> &UnixPath {
+        match self {
+            UnixSocket::Stream(path) | UnixSocket::Datagram(path) => path,
+        }
+    }
+
+    /// Creates the output, connected right away if `connect_now` is set
+    pub fn open(
+        &self,
+        running: &Arc<AtomicBool>,
+        framing: Framing,
+        connect_now: bool,
+    ) -> io::Result<Box<dyn Sink>> {
+        match self {
+            UnixSocket::Stream(path) => {
+                let mut stream = StreamOutput::new(path.clone(), running.clone(), framing);
+
+                if connect_now {
+                    stream.connect_now()?;
+                }
+
+                Ok(Box::new(stream))
+            }
+            UnixSocket::Datagram(path) => {
+                let mut datagram = UnixDatagramOutput::new(path.clone());
+
+                if connect_now {
+                    datagram.connect_now()?;
+                }
+
+                Ok(Box::new(datagram))
+            }
+        }
+    }
+}
+
+/// Path of a Unix socket, displayed in diagnostics
+#[derive(Clone, Debug)]
+pub(crate) struct UnixPath(pub PathBuf);
+
+impl fmt::Display for UnixPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.display())
+    }
+}
+
+impl Endpoint for UnixPath {
+    type Stream = UnixStream;
+
+    fn connect(&self) -> io::Result<UnixStream> {
+        let stream = UnixStream::connect(&self.0)?;
+        crate::io::set_nonblocking(stream.as_raw_fd())?;
+
+        Ok(stream)
+    }
+}
+
+/// Datagram socket of a log shipper, owned by the worker thread
+pub(crate) struct UnixDatagramOutput {
+    path: UnixPath,
+    socket: Option<UnixDatagram>,
+}
+
+impl UnixDatagramOutput {
+    pub fn new(path: UnixPath) -> Self {
+        Self { path, socket: None }
+    }
+
+    /// Connects now instead of on the first line, see
+    /// [`with_validate_on_init`](crate::NonBlockingLoggerBuilder::with_validate_on_init)
+    pub fn connect_now(&mut self) -> io::Result<()> {
+        self.socket = Some(self.connect()?);
+        Ok(())
+    }
+
+    fn connect(&self) -> io::Result<UnixDatagram> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&self.path.0)?;
+
+        Ok(socket)
+    }
+}
+
+impl Sink for UnixDatagramOutput {
+    /// Sends a line without its line ending as one datagram, reconnecting once if the receiver
+    /// went away (e.g. it was restarted)
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        let line = buf.strip_suffix(b"\r\n").unwrap_or(buf);
+
+        let sent = match &self.socket {
+            Some(socket) => socket.send(line).map(|_| ()),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        };
+
+        if sent.is_err() {
+            let socket = self.socket.insert(self.connect()?);
+            socket.send(line)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn is_message_oriented(&self) -> bool {
+        true
+    }
+}
@@ -1,93 +1,576 @@
-use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TryRecvError};
+use log::Level;
+use std::any::Any;
+use std::cell::Cell;
 use std::io;
-use std::io::Write;
+use std::io::{IoSlice, Write};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 #[cfg(unix)]
 use std::os::fd::AsRawFd;
 
+use crate::format::{DeferredRecord, LineParts};
+use crate::line::{Line, LineWriter};
+use crate::{NonBlockingOptions, Sink};
+
+thread_local! {
+    /// Set on worker threads, where a flush can't wait for the worker without deadlocking
+    static ON_WORKER_THREAD: Cell<bool> = const { Cell::new(false) };
+
+    /// Flush requested from the worker thread itself, e.g. by a sink, done after the current write
+    static FLUSH_REQUESTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether this is a worker thread, which can't wait for a reply from the worker
+pub(crate) fn on_worker_thread() -> bool {
+    ON_WORKER_THREAD.get()
+}
+
+/// Asks the worker to flush once it finishes the current write, if called from a worker thread.
+///
+/// Returns `false` on any other thread, where the caller has to send a flush message instead.
+pub(crate) fn request_flush_on_worker_thread() -> bool {
+    if !ON_WORKER_THREAD.get() {
+        return false;
+    }
+
+    FLUSH_REQUESTED.set(true);
+    true
+}
+
 pub enum WorkerMessage {
     /// Log message to be written
-    Log(String),
+    Log(Level, Line),
+    /// Lines collected by a logging thread, see `with_thread_local_batching`
+    Batch(Vec<(Level, Line)>),
+    /// Log message to be written only to the sink of a route, see `route_target`
+    Routed(usize, Level, Line),
+    /// Line already written to the output by the logging thread, for the other sinks, see
+    /// `with_sync_level`
+    Synced(Level, Line),
+    /// Record to be formatted by the worker before writing, see `with_deferred_formatting`
+    Record(DeferredRecord),
     /// Request to flush the output, with a sender to signal completion
-    Flush(Sender<()>),
+    Flush(FlushDone),
+    /// Request to flush only the sinks with this name, see `flush_sink`
+    FlushSink(String, FlushDone),
+    /// Request to reopen every sink, with a sender for the first error, see `NonBlockingLogger::reopen`
+    Reopen(Sender<io::Result<()>>),
+    /// Replaces the primary output, see `NonBlockingLogger::set_sink`
+    SetSink(Box<dyn Sink>, FlushDone),
+    /// Last message before the worker stops, with a sender for the total number of messages
+    /// dropped because the channel was full
+    Shutdown(Sender<u64>),
+    /// Text from `aprintln!`/`aeprintln!`, written as is to STDOUT or STDERR instead of the outputs
+    #[cfg(feature = "macros")]
+    Print(Stream, String),
+}
+
+impl WorkerMessage {
+    /// Number of records carried by the message, none for control messages
+    pub fn records(&self) -> u64 {
+        match self {
+            WorkerMessage::Log(..)
+            | WorkerMessage::Routed(..)
+            | WorkerMessage::Synced(..)
+            | WorkerMessage::Record(..) => 1,
+            WorkerMessage::Batch(lines) => lines.len() as u64,
+            _ => 0,
+        }
+    }
+}
+
+/// Signals the completion of a flush to the waiting logger
+pub enum FlushDone {
+    Blocking(Sender<()>),
+    /// See `NonBlockingLogger::flush_async`
+    #[cfg(feature = "tokio")]
+    Async(tokio::sync::oneshot::Sender<()>),
+}
+
+impl FlushDone {
+    /// Wakes up the waiting logger, ignoring if it stopped waiting
+    fn signal(self) {
+        match self {
+            FlushDone::Blocking(sender) => {
+                let _ = sender.send(());
+            }
+            #[cfg(feature = "tokio")]
+            FlushDone::Async(sender) => {
+                let _ = sender.send(());
+            }
+        }
+    }
+}
+
+#[cfg(feature = "macros")]
+#[derive(Clone, Copy, Debug)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Anything `write_buffer` can write to with the non-blocking retry logic
+#[cfg(unix)]
+pub(crate) trait Pipe: Write + AsRawFd {}
+
+#[cfg(unix)]
+impl<T: Write + AsRawFd> Pipe for T {}
+
+#[cfg(not(unix))]
+pub(crate) trait Pipe: Write {}
+
+#[cfg(not(unix))]
+impl<T: Write> Pipe for T {}
+
+/// Batches of messages at least this large are written with a vectored write instead of being
+/// copied into one buffer. Below it the copy is cheaper than the vectored write, see
+/// `BENCH_RESULTS.md`.
+const VECTORED_WRITE_THRESHOLD: usize = 16 * 1024;
+
+/// A sink with its write settings, resolved when the worker is created
+struct SinkEntry {
+    sink: Box<dyn Sink>,
+    /// Set with `add_named_sink`
+    name: Option<String>,
+    /// Remove ANSI color codes before writing, see `with_strip_ansi`
+    strip_ansi: bool,
+}
+
+impl SinkEntry {
+    fn new(name: Option<String>, sink: Box<dyn Sink>, options: &NonBlockingOptions) -> Self {
+        // Journal entries are binary, their values are stripped when the entry is written
+        #[cfg(all(target_os = "linux", feature = "journald"))]
+        if options.journald {
+            return Self {
+                strip_ansi: false,
+                sink,
+                name,
+            };
+        }
+
+        Self {
+            strip_ansi: options.strip_ansi.unwrap_or(!sink.is_terminal()),
+            sink,
+            name,
+        }
+    }
+
+    /// Flushes the sink, an error is reported and doesn't stop flushing the others
+    fn flush(&mut self) {
+        if let Err(err) = self.sink.flush() {
+            crate::io::write_stderr_with_retry_internal(&format!(
+                "Error flushing log output: {}",
+                err
+            ));
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8], strip_buffer: &mut Vec<u8>) -> io::Result<()> {
+        if self.strip_ansi && buf.contains(&0x1b) {
+            strip_buffer.clear();
+            crate::format::strip_ansi(buf, strip_buffer);
+
+            return self.sink.write_all(strip_buffer);
+        }
+
+        self.sink.write_all(buf)
+    }
+
+    /// Writes a batch of messages, with a single vectored write if the sink supports it and the
+    /// batch is large, otherwise by copying them into `pipe_buffer` first. The copy is shared by
+    /// all sinks of the batch.
+    fn write_batch(
+        &mut self,
+        batch: &[Line],
+        pipe_buffer: &mut Vec<u8>,
+        strip_buffer: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        if let [line] = batch {
+            return self.write_all(line.as_bytes(), strip_buffer);
+        }
+
+        if self.sink.is_message_oriented() {
+            for line in batch {
+                self.write_all(line.as_bytes(), strip_buffer)?;
+            }
+
+            return Ok(());
+        }
+
+        let strip = self.strip_ansi && batch.iter().any(|line| line.as_bytes().contains(&b'\x1b'));
+        let len: usize = batch.iter().map(Line::len).sum();
+
+        if self.sink.is_write_vectored() && !strip && len >= VECTORED_WRITE_THRESHOLD {
+            let mut slices: Vec<IoSlice> = batch
+                .iter()
+                .map(|line| IoSlice::new(line.as_bytes()))
+                .collect();
+
+            return self.sink.write_all_vectored(&mut slices);
+        }
+
+        if pipe_buffer.is_empty() {
+            for line in batch {
+                pipe_buffer.extend_from_slice(line.as_bytes());
+            }
+        }
+
+        self.write_all(pipe_buffer, strip_buffer)
+    }
+}
+
+/// File which also gets the severe lines, see `with_error_file`
+struct ErrorFile {
+    min_level: Level,
+    entry: SinkEntry,
+    /// Set after a failed write, so a broken file is reported once instead of for every line
+    failing: bool,
+}
+
+/// Message of a panic payload, for the usual `panic!` payloads
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    }
+}
+
+impl Drop for LogWorker {
+    fn drop(&mut self) {
+        self.alive.store(false, Ordering::SeqCst);
+    }
 }
 
 /// Worker thread that handles non-blocking writes to stdout/stderr
 pub(crate) struct LogWorker {
     receiver: Receiver<WorkerMessage>,
+    /// Returns written message buffers back to the logger for reuse
+    pool: Option<Sender<String>>,
     running: Arc<AtomicBool>,
+    /// Cleared when the worker is dropped, also while unwinding from a panic
+    alive: Arc<AtomicBool>,
+    options: NonBlockingOptions,
+    sinks: Vec<SinkEntry>,
+    /// Sinks of `route_target`, indexed by the route of a message
+    routes: Vec<SinkEntry>,
+    error_file: Option<ErrorFile>,
+    /// Scratch buffer for lines with stripped color codes
+    strip_buffer: Vec<u8>,
+    /// Messages the logger dropped because the channel was full
+    dropped: Arc<AtomicU64>,
+    /// Dropped messages reported so far, returned on shutdown
+    total_dropped: u64,
+    /// Records taken from the channel and from thread batches, see `ShutdownStats::drained`
+    received: Arc<AtomicU64>,
 }
 
 impl LogWorker {
-    pub fn new(receiver: Receiver<WorkerMessage>) -> (Self, Arc<AtomicBool>) {
-        let running = Arc::new(AtomicBool::new(false));
+    pub fn new(
+        receiver: Receiver<WorkerMessage>,
+        pool: Option<Sender<String>>,
+        options: NonBlockingOptions,
+        sinks: Vec<(Option<String>, Box<dyn Sink>)>,
+        running: Arc<AtomicBool>,
+        alive: Arc<AtomicBool>,
+        dropped: Arc<AtomicU64>,
+    ) -> Self {
+        let sinks = sinks
+            .into_iter()
+            .map(|(name, sink)| SinkEntry::new(name, sink, &options))
+            .collect();
 
-        (
-            Self {
-                receiver,
-                running: running.clone(),
-            },
+        Self {
+            receiver,
+            pool,
             running,
-        )
+            alive,
+            options,
+            sinks,
+            routes: Vec::new(),
+            error_file: None,
+            strip_buffer: Vec::new(),
+            dropped,
+            total_dropped: 0,
+            received: Default::default(),
+        }
+    }
+
+    /// Sets the sinks of `route_target`, in the order of the routes the logger resolves
+    pub fn with_routes(mut self, routes: Vec<Box<dyn Sink>>) -> Self {
+        self.routes = routes
+            .into_iter()
+            .map(|sink| SinkEntry::new(None, sink, &self.options))
+            .collect();
+        self
+    }
+
+    /// Sets the file of `with_error_file` and its least severe level
+    pub fn with_error_file(mut self, error_file: Option<(Level, Box<dyn Sink>)>) -> Self {
+        self.error_file = error_file.map(|(min_level, sink)| ErrorFile {
+            min_level,
+            entry: SinkEntry::new(None, sink, &self.options),
+            failing: false,
+        });
+        self
+    }
+
+    /// Sets the counter of records taken by the worker, read by the logger on shutdown
+    pub fn with_received(mut self, received: Arc<AtomicU64>) -> Self {
+        self.received = received;
+        self
     }
 
     pub fn spawn(mut self) -> io::Result<JoinHandle<()>> {
         self.running.store(true, Ordering::SeqCst);
+        self.alive.store(true, Ordering::SeqCst);
 
-        Ok(thread::spawn(move || {
-            self.run();
-        }))
+        thread::Builder::new()
+            .name(self.options.worker_thread_name.clone())
+            .spawn(move || {
+                crate::io::set_write_timeout(self.options.write_timeout);
+                ON_WORKER_THREAD.set(true);
+
+                // A panicking sink loses the batch being written, not the following messages
+                while let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| self.run())) {
+                    crate::io::write_stderr_with_retry_internal(&format!(
+                        "Log worker panicked, restarting it: {}",
+                        panic_message(panic.as_ref())
+                    ));
+
+                    // The shutdown message may have been lost with the batch
+                    if !self.running.load(Ordering::SeqCst) {
+                        break;
+                    }
+                }
+
+                // Already done by `stop` after a shutdown, this covers the dropped loggers
+                self.restore_output_flags();
+            })
     }
 
-    fn write_buffer(buf: &[u8]) -> Result<(), io::Error> {
-        let mut cursor = 0;
+    /// Hands a written message buffer back to the pool, dropping it if the pool is full
+    /// or the buffer grew too large to be worth keeping.
+    fn recycle(&self, mut msg: String) {
+        if let Some(pool) = &self.pool
+            && msg.capacity() <= crate::MAX_POOLED_BUFFER_CAPACITY
+        {
+            msg.clear();
+            let _ = pool.try_send(msg);
+        }
+    }
 
-        let mut pipe = {
-            #[cfg(not(feature = "stderr"))]
-            {
-                io::stdout()
+    /// Writes the buffer to every sink, an error in one sink is reported and doesn't stop the others
+    fn write_output(&mut self, buf: &[u8]) {
+        for entry in self.sinks.iter_mut() {
+            if let Err(err) = entry.write_all(buf, &mut self.strip_buffer) {
+                crate::io::write_stderr_with_retry_internal(&format!(
+                    "Error writing log output: {}",
+                    err
+                ))
             }
+        }
+    }
+
+    fn flush_output(&mut self) {
+        let error_file = self
+            .error_file
+            .as_mut()
+            .map(|error_file| &mut error_file.entry);
+
+        for entry in self
+            .sinks
+            .iter_mut()
+            .chain(self.routes.iter_mut())
+            .chain(error_file)
+        {
+            entry.flush();
+        }
+    }
+
+    /// Flushes and reopens every sink, returns the first error after trying all of them
+    fn reopen(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+
+        let error_file = self
+            .error_file
+            .as_mut()
+            .map(|error_file| &mut error_file.entry);
+
+        for entry in self
+            .sinks
+            .iter_mut()
+            .chain(self.routes.iter_mut())
+            .chain(error_file)
+        {
+            entry.flush();
 
-            #[cfg(feature = "stderr")]
+            if let Err(err) = entry.sink.reopen()
+                && result.is_ok()
             {
-                io::stderr()
+                result = Err(err);
             }
+        }
+
+        result
+    }
+
+    /// Writes the lines of the batch at the level of the error file or more severe to it
+    fn write_error_file(&mut self, levels: &[Level], batch: &[Line]) {
+        let Some(error_file) = &mut self.error_file else {
+            return;
         };
 
+        for (level, line) in levels.iter().zip(batch) {
+            if *level > error_file.min_level {
+                continue;
+            }
+
+            match error_file
+                .entry
+                .write_all(line.as_bytes(), &mut self.strip_buffer)
+            {
+                Ok(()) => error_file.failing = false,
+                Err(err) if !error_file.failing => {
+                    error_file.failing = true;
+                    crate::io::write_stderr_with_retry_internal(&format!(
+                        "Error writing the error log file, not reported again until it recovers: {}",
+                        err
+                    ));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// Writes a line to the sink of its route only
+    fn write_routed(&mut self, route: usize, level: Level, line: Line) {
+        if let Err(err) = self.routes[route].write_all(line.as_bytes(), &mut self.strip_buffer) {
+            crate::io::write_stderr_with_retry_internal(&format!(
+                "Error writing log output: {}",
+                err
+            ))
+        }
+
+        if self.flushes_after(level) {
+            self.routes[route].flush();
+        }
+
+        if let Line::Heap(line) = line {
+            self.recycle(line);
+        }
+    }
+
+    /// Writes a line the logging thread wrote to the primary output to the other sinks and the
+    /// error file
+    fn write_synced(&mut self, level: Level, line: Line) {
+        self.on_log(level, &line.as_text());
+        self.write_error_file(&[level], std::slice::from_ref(&line));
+
+        let flush = self.flushes_after(level);
+
+        for entry in self.sinks.iter_mut().skip(1) {
+            if let Err(err) = entry.write_all(line.as_bytes(), &mut self.strip_buffer) {
+                crate::io::write_stderr_with_retry_internal(&format!(
+                    "Error writing log output: {}",
+                    err
+                ))
+            }
+
+            if flush {
+                entry.flush();
+            }
+        }
+
+        if let Line::Heap(line) = line {
+            self.recycle(line);
+        }
+    }
+
+    /// Whether the outputs are flushed right after writing a line at `level`, see `with_flush_on_level`
+    fn flushes_after(&self, level: Level) -> bool {
+        self.options
+            .flush_level
+            .is_some_and(|flush_level| level <= flush_level)
+    }
+
+    /// Flushes the sinks named `name`, reporting if there is none
+    fn flush_named(&mut self, name: &str) {
+        let mut found = false;
+
+        for entry in self.sinks.iter_mut() {
+            if entry.name.as_deref() == Some(name) {
+                entry.flush();
+                found = true;
+            }
+        }
+
+        if !found {
+            crate::io::write_stderr_with_retry_internal(&format!(
+                "No log sink named {:?} to flush",
+                name
+            ));
+        }
+    }
+
+    pub(crate) fn write_buffer(pipe: &mut impl Pipe, buf: &[u8]) -> Result<(), io::Error> {
+        let mut cursor = 0;
+        let timeout = crate::io::write_timeout();
+        // Start of the current wait for the pipe to accept more data
+        let mut stalled_since: Option<Instant> = None;
+
         // Write all buffered data
         while cursor < buf.len() {
             let slice = &buf[cursor..];
             match pipe.write(slice) {
-                Ok(0) => {
-                    #[cfg(unix)]
-                    {
-                        // Nothing accepted, wait for stdout to become writable using poll
-                        crate::io::wait_writable(pipe.as_raw_fd())?
-                    }
-
-                    #[cfg(windows)]
-                    {
-                        // On Windows, just retry
-                    }
-                }
-                Ok(n) => {
+                Ok(n) if n > 0 => {
                     // Advance cursor by number of bytes written
                     cursor += n;
+                    stalled_since = None;
                 }
+                // Nothing accepted (Ok(0)) or WouldBlock, wait for the pipe to become writable
+                Ok(_) => Self::wait_writable(pipe, timeout, &mut stalled_since)?,
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    #[cfg(unix)]
-                    {
-                        // Wait for stdout to become writable usig poll
-                        crate::io::wait_writable(pipe.as_raw_fd())?
-                    }
+                    Self::wait_writable(pipe, timeout, &mut stalled_since)?
+                }
+                Err(err) => {
+                    // Hard error, give up
+                    return Err(err);
+                }
+            }
+        }
 
-                    #[cfg(windows)]
-                    {
-                        // On Windows, just retry
-                    }
+        Ok(())
+    }
+
+    /// Writes all buffers in order, like [`write_buffer`](Self::write_buffer) over their
+    /// concatenation, but with vectored writes instead of copying them into one buffer.
+    /// Partial writes advance across the slices.
+    pub(crate) fn write_buffer_vectored(
+        pipe: &mut impl Pipe,
+        mut bufs: &mut [IoSlice<'_>],
+    ) -> Result<(), io::Error> {
+        let timeout = crate::io::write_timeout();
+        let mut stalled_since: Option<Instant> = None;
+
+        // Skip leading empty slices, so an empty write below means the pipe is full
+        IoSlice::advance_slices(&mut bufs, 0);
+
+        while !bufs.is_empty() {
+            match pipe.write_vectored(bufs) {
+                Ok(n) if n > 0 => {
+                    IoSlice::advance_slices(&mut bufs, n);
+                    stalled_since = None;
+                }
+                Ok(_) => Self::wait_writable(pipe, timeout, &mut stalled_since)?,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    Self::wait_writable(pipe, timeout, &mut stalled_since)?
                 }
                 Err(err) => {
                     // Hard error, give up
@@ -99,100 +582,491 @@ impl LogWorker {
         Ok(())
     }
 
+    /// Waits for the pipe to become writable, giving up with `TimedOut` once it hasn't accepted
+    /// any data for the write timeout, so a wedged consumer doesn't hang the worker forever.
+    fn wait_writable(
+        pipe: &impl Pipe,
+        timeout: Option<Duration>,
+        stalled_since: &mut Option<Instant>,
+    ) -> Result<(), io::Error> {
+        let stalled = stalled_since.get_or_insert_with(Instant::now).elapsed();
+
+        let remaining = match timeout {
+            Some(timeout) if stalled >= timeout => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "output wasn't writable for {:?}, dropping the message",
+                        timeout
+                    ),
+                ));
+            }
+            Some(timeout) => Some(timeout - stalled),
+            None => None,
+        };
+
+        #[cfg(unix)]
+        {
+            // Wait using poll
+            crate::io::wait_writable(pipe.as_raw_fd(), remaining)
+        }
+
+        #[cfg(windows)]
+        {
+            // Sleeps briefly, the timeout is checked again on the next call
+            let _ = pipe;
+            crate::io::wait_writable(remaining);
+            Ok(())
+        }
+
+        #[cfg(not(any(unix, windows)))]
+        {
+            let _ = (pipe, remaining);
+            Ok(())
+        }
+    }
+
+    /// Writes a summary of the messages dropped since the last one, if there were any.
+    ///
+    /// Called once the worker took a message from the channel, so there is room again.
+    fn write_dropped_summary(&mut self) {
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped == 0 {
+            return;
+        }
+
+        self.total_dropped += dropped;
+
+        let mut line = LineWriter::new(String::new);
+        self.options.write_line(
+            &LineParts {
+                level: Level::Warn,
+                target: "log_nonblock",
+                thread: "",
+                module: None,
+                file: None,
+                line: None,
+                uptime: self.options.uptime(),
+                #[cfg(feature = "timestamps")]
+                now: self.options.now(),
+                args: &format_args!("dropped {} messages due to backpressure", dropped),
+                #[cfg(feature = "kv")]
+                key_values: None,
+            },
+            &mut line,
+        );
+        let line = line.into_line();
+
+        self.on_log(Level::Warn, &line.as_text());
+        self.write_output(line.as_bytes());
+    }
+
+    /// Passes the line to the closure set with `with_on_log`, catching its panics
+    fn on_log(&self, level: Level, line: &str) {
+        let Some(on_log) = &self.options.on_log else {
+            return;
+        };
+
+        let text = line.trim_end_matches("\r\n");
+
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| (on_log.0)(level, text))) {
+            crate::io::write_stderr_with_retry_internal(&format!(
+                "Log callback panicked: {}",
+                panic_message(panic.as_ref())
+            ));
+        }
+    }
+
+    /// Formats a deferred record into a ready to write line
+    fn render(&self, record: DeferredRecord) -> Line {
+        let capacity = record.args.len() + 64;
+        let mut line = LineWriter::new(|| String::with_capacity(capacity));
+        self.options.write_line(&record.parts(), &mut line);
+        self.recycle(record.args);
+
+        line.into_line()
+    }
+
+    /// Writes the batch to every sink and hands the buffers back to the pool, leaving `batch` empty
+    fn write_batch(&mut self, pipe_buffer: &mut Vec<u8>, batch: &mut Vec<Line>) {
+        #[cfg(feature = "bench-hooks")]
+        let started = Instant::now();
+
+        for entry in self.sinks.iter_mut() {
+            if let Err(err) = entry.write_batch(batch, pipe_buffer, &mut self.strip_buffer) {
+                crate::io::write_stderr_with_retry_internal(&format!(
+                    "Error writing log output: {}",
+                    err
+                ))
+            }
+        }
+
+        pipe_buffer.clear();
+
+        #[cfg(feature = "bench-hooks")]
+        if let Some(hook) = &self.options.stage_hook {
+            (hook.0)(
+                crate::Stage::Write { lines: batch.len() },
+                started.elapsed(),
+            );
+        }
+
+        for line in batch.drain(..) {
+            if let Line::Heap(line) = line {
+                self.recycle(line);
+            }
+        }
+    }
+
+    /// Writes text from `aprintln!`/`aeprintln!` to STDOUT or STDERR
+    #[cfg(feature = "macros")]
+    fn print(stream: Stream, text: &str) {
+        let res = match stream {
+            Stream::Stdout => Self::write_buffer(&mut io::stdout(), text.as_bytes()),
+            Stream::Stderr => Self::write_buffer(&mut io::stderr(), text.as_bytes()),
+        };
+
+        if let Err(err) = res {
+            crate::io::write_stderr_with_retry_internal(&format!("Error writing output: {}", err));
+        }
+    }
+
+    /// Reports the last dropped messages and flushes the outputs before the worker exits
+    fn stop(&mut self, done: Sender<u64>) {
+        // Lines added to thread batches after the logger sent them, up to the shutdown
+        self.write_thread_batches(true, &mut Vec::new(), &mut Vec::new(), &mut Vec::new());
+
+        self.write_dropped_summary();
+        self.flush_output();
+        self.restore_output_flags();
+
+        // Ignore if the logger stopped waiting
+        let _ = done.send(self.total_dropped);
+    }
+
+    /// Puts STDOUT/STDERR back in blocking mode if building the logger changed it, so other
+    /// code sharing the descriptor doesn't get `WouldBlock` errors after the logger stopped
+    fn restore_output_flags(&mut self) {
+        #[cfg(all(unix, feature = "nonblock-io"))]
+        if let Some((fd, flags)) = self.options.original_output_flags.take()
+            && let Err(err) = crate::io::clear_nonblocking(fd, flags)
+        {
+            crate::io::write_stderr_with_retry_internal(&format!(
+                "Failed to restore blocking mode of the output: {}",
+                err
+            ));
+        }
+    }
+
+    /// Handles a message which isn't added to a batch, returns `false` once the worker has stopped
+    fn handle_control(&mut self, msg: WorkerMessage) -> bool {
+        match msg {
+            WorkerMessage::Synced(level, line) => self.write_synced(level, line),
+            WorkerMessage::Routed(route, level, line) => {
+                self.on_log(level, &line.as_text());
+                self.write_routed(route, level, line);
+            }
+            WorkerMessage::Record(record) => {
+                let (level, route) = (record.level, record.route);
+                let line = self.render(record);
+                self.on_log(level, &line.as_text());
+
+                match route {
+                    Some(route) => self.write_routed(route, level, line),
+                    None => unreachable!("records without a route are written in batches"),
+                }
+            }
+            WorkerMessage::Flush(done) => {
+                self.flush_output();
+                done.signal();
+            }
+            WorkerMessage::FlushSink(name, done) => {
+                self.flush_named(&name);
+                done.signal();
+            }
+            WorkerMessage::Reopen(done) => {
+                let _ = done.send(self.reopen());
+            }
+            WorkerMessage::SetSink(sink, done) => {
+                self.sinks[0].flush();
+                // The old output is dropped here, e.g. closing its file
+                self.sinks[0] = SinkEntry::new(None, sink, &self.options);
+                done.signal();
+            }
+            #[cfg(feature = "macros")]
+            WorkerMessage::Print(stream, text) => Self::print(stream, &text),
+            WorkerMessage::Shutdown(done) => {
+                self.stop(done);
+                return false;
+            }
+            WorkerMessage::Log(..) | WorkerMessage::Batch(..) => {
+                unreachable!("log lines are written in batches")
+            }
+        }
+
+        true
+    }
+
+    /// Waits for the next message. With thread batches or deduplication, gives up after a while
+    /// so the lines left in the batches and the pending repeats are written even if nothing
+    /// else is logged.
+    fn receive(&self) -> Result<WorkerMessage, RecvTimeoutError> {
+        let batches = self
+            .options
+            .thread_batches
+            .as_ref()
+            .map(|_| crate::thread_batch::THREAD_BATCH_MAX_DELAY);
+        let repeats = self
+            .options
+            .dedup
+            .as_ref()
+            .map(|_| crate::dedup::REPEAT_SUMMARY_INTERVAL);
+
+        let msg = match batches.into_iter().chain(repeats).min() {
+            Some(timeout) => self.receiver.recv_timeout(timeout),
+            None => self
+                .receiver
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        msg.inspect(|msg| self.count_received(msg))
+    }
+
+    /// Counts the records of a message taken from the channel
+    fn count_received(&self, msg: &WorkerMessage) {
+        self.received.fetch_add(msg.records(), Ordering::Relaxed);
+    }
+
+    /// Writes lines sent together, like a batch of lines waiting in the channel
+    fn write_lines(
+        &mut self,
+        lines: Vec<(Level, Line)>,
+        pipe_buffer: &mut Vec<u8>,
+        batch: &mut Vec<Line>,
+        levels: &mut Vec<Level>,
+    ) {
+        let mut flush = false;
+
+        for (level, line) in lines {
+            self.on_log(level, &line.as_text());
+            flush |= self.flushes_after(level);
+
+            batch.push(line);
+            levels.push(level);
+        }
+
+        self.write_error_file(levels, batch);
+        levels.clear();
+        self.write_batch(pipe_buffer, batch);
+
+        if flush {
+            self.flush_output();
+        }
+    }
+
+    /// Writes the lines still held by logging threads, only those of threads which stopped
+    /// logging for a while unless `all` is set
+    fn write_thread_batches(
+        &mut self,
+        all: bool,
+        pipe_buffer: &mut Vec<u8>,
+        batch: &mut Vec<Line>,
+        levels: &mut Vec<Level>,
+    ) {
+        let Some(batches) = self.options.thread_batches.clone() else {
+            return;
+        };
+
+        let mut taken = Vec::new();
+        // With the channel empty, nothing the thread sent before these lines is left unwritten
+        batches.drain(
+            || all || self.receiver.is_empty(),
+            |lines| taken.push(lines),
+        );
+
+        for lines in taken {
+            self.received
+                .fetch_add(lines.len() as u64, Ordering::Relaxed);
+            self.write_lines(lines, pipe_buffer, batch, levels);
+        }
+    }
+
+    /// Writes the summary of repeats held back by deduplication for a while, unless lines logged
+    /// before them are still waiting, which must be written first
+    fn write_due_repeated(
+        &mut self,
+        pipe_buffer: &mut Vec<u8>,
+        batch: &mut Vec<Line>,
+        levels: &mut Vec<Level>,
+    ) {
+        let Some(dedup) = self.options.dedup.clone() else {
+            return;
+        };
+
+        let repeated = {
+            // Logging threads send the repeated message with the lock held
+            let mut dedup = dedup
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+            let waiting = !self.receiver.is_empty()
+                || self
+                    .options
+                    .thread_batches
+                    .as_ref()
+                    .is_some_and(|batches| !batches.is_empty());
+            if waiting {
+                return;
+            }
+
+            dedup.take_due_repeated()
+        };
+
+        let Some(repeated) = repeated else {
+            return;
+        };
+
+        let mut line = LineWriter::new(String::new);
+        self.options.write_line(
+            &LineParts {
+                level: repeated.level,
+                target: &repeated.target,
+                thread: "",
+                module: None,
+                file: None,
+                line: None,
+                uptime: self.options.uptime(),
+                #[cfg(feature = "timestamps")]
+                now: self.options.now(),
+                args: &format_args!("last message repeated {} times", repeated.count),
+                #[cfg(feature = "kv")]
+                key_values: None,
+            },
+            &mut line,
+        );
+
+        self.write_lines(
+            vec![(repeated.level, line.into_line())],
+            pipe_buffer,
+            batch,
+            levels,
+        );
+    }
+
+    /// Whether one more waiting message is added to a batch of `len` messages and `bytes` bytes
+    fn batch_has_room(&self, len: usize, bytes: usize) -> bool {
+        match self.options.max_batch_bytes {
+            Some(max_bytes) => bytes < max_bytes,
+            // Pipe one more message into the buffer behind a small one, saving a write call
+            None => len < 2 && bytes < self.options.large_message_threshold,
+        }
+    }
+
     fn run(&mut self) {
-        let stdout = io::stdout();
+        // Room for a line just under the threshold and one more of similar size, a larger
+        // batch grows the buffer once and the allocation is kept
+        let mut pipe_buffer = Vec::with_capacity(
+            self.options
+                .large_message_threshold
+                .min(crate::DEFAULT_LARGE_MESSAGE_THRESHOLD * 8)
+                * 2,
+        );
+        let mut batch = Vec::new();
+        // Level of each line in `batch`, only kept for the error file
+        let mut levels = Vec::new();
+
+        // Runs until the shutdown message, so everything sent before it is written
+        loop {
+            if FLUSH_REQUESTED.take() {
+                self.flush_output();
+            }
 
-        let mut pipe_buffer = Vec::with_capacity(2 * 1024);
+            self.write_dropped_summary();
 
-        while self.running.load(Ordering::SeqCst) {
             // block until at least one message
-            let first_message_to_pipe = match self.receiver.recv() {
-                Ok(msg) => match msg {
-                    WorkerMessage::Log(msg) => {
-                        if msg.len() < 1280 {
-                            msg
-                        } else {
-                            if let Err(err) = Self::write_buffer(msg.as_bytes()) {
-                                crate::io::write_stderr_with_retry_internal(&format!(
-                                    "Error waiting for stdout: {}",
-                                    err
-                                ))
-                            }
-
-                            continue;
-                        }
-                    }
-                    WorkerMessage::Flush(done) => {
-                        if let Err(err) = stdout.lock().flush() {
-                            crate::io::write_stderr_with_retry_internal(&format!(
-                                "Error flushing stdout: {}",
-                                err
-                            ));
-                        }
-
-                        // Signal completion (ignore if receiver was dropped)
-                        let _ = done.send(());
-
-                        continue;
+            let (level, first) = match self.receive() {
+                Ok(WorkerMessage::Log(level, line)) => (level, line),
+                Ok(WorkerMessage::Batch(lines)) => {
+                    self.write_lines(lines, &mut pipe_buffer, &mut batch, &mut levels);
+                    continue;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.write_thread_batches(false, &mut pipe_buffer, &mut batch, &mut levels);
+                    self.write_due_repeated(&mut pipe_buffer, &mut batch, &mut levels);
+                    continue;
+                }
+                Ok(WorkerMessage::Record(record)) if record.route.is_none() => {
+                    (record.level, self.render(record))
+                }
+                Ok(msg) => {
+                    if !self.handle_control(msg) {
+                        return;
                     }
-                },
-                Err(_) => break, // channel closed
-            };
 
-            // pipe one more message into the buffer (optimization)
-            match self.receiver.try_recv() {
-                Ok(msg) => match msg {
-                    WorkerMessage::Log(second_message_to_pipe) => {
-                        pipe_buffer.extend_from_slice(first_message_to_pipe.as_bytes());
-                        drop(first_message_to_pipe);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break, // channel closed
+            };
 
-                        pipe_buffer.extend_from_slice(second_message_to_pipe.as_bytes());
-                        drop(second_message_to_pipe);
+            self.on_log(level, &first.as_text());
+            let mut flush = self.flushes_after(level);
 
-                        let res = Self::write_buffer(pipe_buffer.as_slice());
+            let mut bytes = first.len();
+            batch.push(first);
+            levels.push(level);
 
-                        pipe_buffer.clear();
+            // Only messages which are already waiting are added, the worker never waits for more
+            let mut control = None;
+            let mut disconnected = false;
 
-                        if let Err(err) = res {
-                            crate::io::write_stderr_with_retry_internal(&format!(
-                                "Error waiting for stdout: {}",
-                                err
-                            ))
-                        }
+            while self.batch_has_room(batch.len(), bytes) {
+                let msg = self.receiver.try_recv();
+                let (level, line) = match msg.inspect(|msg| self.count_received(msg)) {
+                    Ok(WorkerMessage::Log(level, line)) => (level, line),
+                    Ok(WorkerMessage::Record(record)) if record.route.is_none() => {
+                        (record.level, self.render(record))
                     }
-                    WorkerMessage::Flush(done) => {
-                        let res = Self::write_buffer(first_message_to_pipe.as_bytes());
-                        let flush_res = stdout.lock().flush();
-
-                        // Signal completion (ignore if receiver was dropped)
-                        let _ = done.send(());
-
-                        if let Err(err) = res {
-                            crate::io::write_stderr_with_retry_internal(&format!(
-                                "Error waiting for stdout: {}",
-                                err
-                            ))
-                        }
-
-                        if let Err(err) = flush_res {
-                            crate::io::write_stderr_with_retry_internal(&format!(
-                                "Error flushing stdout: {}",
-                                err
-                            ));
-                        }
-
-                        continue;
+                    // Routed messages end the batch too, so lines keep their order
+                    Ok(msg) => {
+                        control = Some(msg);
+                        break;
                     }
-                },
-                Err(TryRecvError::Empty) => {
-                    if let Err(err) = Self::write_buffer(first_message_to_pipe.as_bytes()) {
-                        crate::io::write_stderr_with_retry_internal(&format!(
-                            "Error waiting for stdout: {}",
-                            err
-                        ))
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        disconnected = true;
+                        break;
                     }
-                }
-                Err(TryRecvError::Disconnected) => break, // channel closed
+                };
+
+                self.on_log(level, &line.as_text());
+                flush |= self.flushes_after(level);
+
+                bytes += line.len();
+                batch.push(line);
+                levels.push(level);
+            }
+
+            self.write_error_file(&levels, &batch);
+            levels.clear();
+            self.write_batch(&mut pipe_buffer, &mut batch);
+
+            if flush {
+                self.flush_output();
+            }
+
+            // Only handled once the batch is written, so a flush waits for every line sent
+            // before it, see `examples/flush_ordering.rs`
+            if let Some(WorkerMessage::Batch(lines)) = control {
+                self.write_lines(lines, &mut pipe_buffer, &mut batch, &mut levels);
+            } else if let Some(msg) = control
+                && !self.handle_control(msg)
+            {
+                return;
+            }
+
+            if disconnected {
+                break; // channel closed
             }
         }
     }
@@ -0,0 +1,61 @@
+//! Sinks and helpers shared by the integration tests
+
+// Each test file uses only some of them
+#![allow(dead_code)]
+
+use log::{Level, Log, Record};
+use log_nonblock::{NonBlockingLogger, Sink};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Blocks the worker on every write while the test holds the lock
+pub struct GatedSink(pub Arc<Mutex<()>>);
+
+impl Sink for GatedSink {
+    fn write_all(&mut self, _buf: &[u8]) -> io::Result<()> {
+        drop(self.0.lock());
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Keeps every byte written, without splitting them into lines like `CaptureSink`
+#[derive(Clone, Default)]
+pub struct BytesSink(Arc<Mutex<Vec<u8>>>);
+
+impl BytesSink {
+    /// Returns a copy of the bytes written so far
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl Sink for BytesSink {
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn log(logger: &dyn Log, level: Level, message: &str) {
+    logger.log(
+        &Record::builder()
+            .args(format_args!("{}", message))
+            .level(level)
+            .build(),
+    );
+}
+
+/// Waits until the worker took every waiting message, e.g. before a closed gate
+pub fn wait_until_taken(logger: &NonBlockingLogger) {
+    while logger.queue_len() > 0 {
+        std::thread::yield_now();
+    }
+}
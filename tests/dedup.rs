@@ -0,0 +1,64 @@
+//! Summaries of repeated messages written by the worker, without waiting for another message
+
+mod common;
+
+use common::log;
+use log::Level;
+use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+use std::time::{Duration, Instant};
+
+fn wait_for_lines(capture: &CaptureSink, count: usize) -> Vec<String> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+
+    while capture.lines().len() < count && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    capture.lines()
+}
+
+#[test]
+fn repeats_are_summarized_once_nothing_else_is_logged() {
+    let capture = CaptureSink::new();
+    let logger = NonBlockingLoggerBuilder::new()
+        .without_timestamps()
+        .with_colors(false)
+        .with_dedup(true)
+        .with_capture(&capture)
+        .build()
+        .unwrap();
+
+    for _ in 0..3 {
+        log(&logger, Level::Info, "Retrying");
+    }
+
+    // Neither another message nor a flush
+    let lines = wait_for_lines(&capture, 2);
+
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with("Retrying"));
+    assert!(lines[1].ends_with("last message repeated 2 times"));
+}
+
+#[test]
+fn repeats_are_summarized_after_the_thread_batch() {
+    let capture = CaptureSink::new();
+    let logger = NonBlockingLoggerBuilder::new()
+        .without_timestamps()
+        .with_colors(false)
+        .with_dedup(true)
+        .with_thread_local_batching(true)
+        .with_capture(&capture)
+        .build()
+        .unwrap();
+
+    for _ in 0..3 {
+        log(&logger, Level::Info, "Retrying");
+    }
+
+    let lines = wait_for_lines(&capture, 2);
+
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].ends_with("Retrying"));
+    assert!(lines[1].ends_with("last message repeated 2 times"));
+}
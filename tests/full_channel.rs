@@ -0,0 +1,176 @@
+//! What happens to records while the worker is stuck and the channel fills up
+
+mod common;
+
+use common::{GatedSink, log, wait_until_taken};
+use log::{Level, Log, Record};
+use log_nonblock::{CaptureSink, FullPolicy, NonBlockingLoggerBuilder};
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn adaptive_sampling_keeps_severe_records() {
+    let gate = Arc::new(Mutex::new(()));
+    let closed = gate.lock().unwrap();
+
+    let capture = CaptureSink::new();
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_channel_size(100)
+        .with_adaptive_sampling(true)
+        .with_capture(&capture)
+        .add_sink(GatedSink(gate.clone()))
+        .build()
+        .unwrap();
+
+    // The worker takes the first message and waits at the gate
+    log(&logger, Level::Info, "Start");
+    wait_until_taken(&logger);
+
+    for i in 0..100 {
+        log(&logger, Level::Debug, &format!("Step {}", i));
+    }
+    log(&logger, Level::Warn, "Still flowing");
+
+    drop(closed);
+    logger.flush();
+
+    let lines = capture.lines();
+    assert!(logger.sampled_out() > 0);
+    assert_eq!(lines.len() as u64, 102 - logger.sampled_out());
+    assert!(lines.last().unwrap().ends_with("Still flowing"));
+}
+
+#[test]
+fn error_fallback_writes_errors_which_dont_fit() {
+    let gate = Arc::new(Mutex::new(()));
+    let closed = gate.lock().unwrap();
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_channel_size(1)
+        .with_capture(&CaptureSink::new())
+        .add_sink(GatedSink(gate.clone()))
+        .with_error_fallback(true)
+        .build()
+        .unwrap();
+
+    let try_log = |level| {
+        logger.try_log_enabled(
+            &Record::builder()
+                .args(format_args!("Failed"))
+                .level(level)
+                .build(),
+        )
+    };
+
+    // The worker takes the first record and waits at the gate, the second fills the channel
+    assert!(try_log(Level::Info));
+    wait_until_taken(&logger);
+    assert!(try_log(Level::Info));
+
+    // Errors which don't fit in the channel go to STDERR
+    assert!((0..20).all(|_| try_log(Level::Error)));
+    // Other records are dropped
+    assert!(!try_log(Level::Warn));
+
+    drop(closed);
+    assert_eq!(logger.shutdown_with_stats().unwrap().dropped, 1);
+}
+
+#[test]
+fn error_fallback_only_drops_the_other_lines_of_a_batch() {
+    let gate = Arc::new(Mutex::new(()));
+    let closed = gate.lock().unwrap();
+
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_channel_size(1)
+        .with_thread_local_batching(true)
+        .with_capture(&CaptureSink::new())
+        .add_sink(GatedSink(gate.clone()))
+        .with_error_fallback(true)
+        .build()
+        .unwrap();
+
+    // Batches of 32 lines: the worker takes the first one and waits at the gate, the second
+    // fills the channel
+    (0..32).for_each(|_| log(&logger, Level::Info, "Failed"));
+    wait_until_taken(&logger);
+    (0..32).for_each(|_| log(&logger, Level::Info, "Failed"));
+
+    // The third batch doesn't fit, its 2 errors go to STDERR
+    (0..30).for_each(|_| log(&logger, Level::Warn, "Failed"));
+    (0..2).for_each(|_| log(&logger, Level::Error, "Failed"));
+
+    drop(closed);
+    assert_eq!(logger.shutdown_with_stats().unwrap().dropped, 30);
+}
+
+#[test]
+fn backpressure_notify_pings_again_once_rearmed() {
+    let gate = Arc::new(Mutex::new(()));
+
+    // Small channels re-arm as well
+    for channel_size in [1, 8] {
+        let (notify, pings) = std::sync::mpsc::channel();
+        let logger = NonBlockingLoggerBuilder::new()
+            .with_channel_size(channel_size)
+            .with_full_policy(FullPolicy::Drop)
+            .with_capture(&CaptureSink::new())
+            .add_sink(GatedSink(gate.clone()))
+            .with_backpressure_notify(notify)
+            .build()
+            .unwrap();
+
+        let closed = gate.lock().unwrap();
+        (0..20).for_each(|_| log(&logger, Level::Info, "Hello"));
+        // A single ping while the channel stayed full
+        assert_eq!(pings.try_iter().count(), 1, "channel of {}", channel_size);
+
+        // Logging into the drained channel re-arms the notifier, without a ping
+        drop(closed);
+        logger.flush();
+        log(&logger, Level::Info, "Hello");
+        logger.flush();
+        assert_eq!(pings.try_iter().count(), 0, "channel of {}", channel_size);
+
+        // So the channel filling up again pings again
+        let closed = gate.lock().unwrap();
+        (0..20).for_each(|_| log(&logger, Level::Info, "Hello"));
+        assert_eq!(pings.try_iter().count(), 1, "channel of {}", channel_size);
+        drop(closed);
+    }
+}
+
+#[test]
+fn shutdown_stats_count_the_drained_records() {
+    let gate = Arc::new(Mutex::new(()));
+    let closed = gate.lock().unwrap();
+
+    let capture = CaptureSink::new();
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_capture(&capture)
+        .add_sink(GatedSink(gate.clone()))
+        .build()
+        .unwrap();
+
+    // The worker takes the first message and waits at the gate
+    log(&logger, Level::Info, "Start");
+    wait_until_taken(&logger);
+
+    for i in 0..10 {
+        log(&logger, Level::Info, &format!("Message {}", i));
+    }
+
+    // Opens the gate once the shutdown message is queued behind the records left, the worker
+    // may have taken one of them with "Start"
+    let watcher = logger.clone();
+    let shutdown = std::thread::spawn(move || logger.shutdown_with_stats().unwrap());
+    while watcher.queue_len() + capture.lines().len() < 12 {
+        std::thread::yield_now();
+    }
+    drop(closed);
+
+    let stats = shutdown.join().unwrap();
+
+    assert!((9..=10).contains(&stats.drained));
+    assert_eq!(stats.dropped, 0);
+    assert_eq!(capture.lines().len(), 11);
+}
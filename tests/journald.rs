@@ -0,0 +1,56 @@
+#![cfg(all(target_os = "linux", feature = "journald"))]
+
+use log::{Level, Log, Record};
+use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+
+#[test]
+fn multi_line_values_are_framed_after_stripping_colors() {
+    let capture = CaptureSink::new();
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_journald()
+        .with_strip_ansi(true)
+        .with_capture(&capture)
+        .build()
+        .unwrap();
+
+    logger.log(
+        &Record::builder()
+            .args(format_args!("first\n\x1b[31msecond\x1b[0m"))
+            .level(Level::Warn)
+            .target("app")
+            .build(),
+    );
+    logger.flush();
+
+    // The length is counted without the color codes, as a little-endian 64-bit integer
+    let mut expected = b"PRIORITY=4\nTARGET=app\nMESSAGE\n".to_vec();
+    expected.extend_from_slice(&12u64.to_le_bytes());
+    expected.extend_from_slice(b"first\nsecond\n");
+
+    assert_eq!(capture.lines(), [String::from_utf8(expected).unwrap()]);
+}
+
+#[test]
+fn colors_are_kept_in_values_when_asked() {
+    let capture = CaptureSink::new();
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_journald()
+        .with_strip_ansi(false)
+        .with_capture(&capture)
+        .build()
+        .unwrap();
+
+    logger.log(
+        &Record::builder()
+            .args(format_args!("\x1b[31mred\x1b[0m"))
+            .level(Level::Error)
+            .target("app")
+            .build(),
+    );
+    logger.flush();
+
+    assert_eq!(
+        capture.lines(),
+        ["PRIORITY=3\nTARGET=app\nMESSAGE=\x1b[31mred\x1b[0m\n"]
+    );
+}
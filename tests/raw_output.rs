@@ -0,0 +1,70 @@
+//! Bytes written to the sinks, checked as a whole rather than line by line
+
+mod common;
+
+use common::{BytesSink, log};
+use log::{Level, Log};
+use log_nonblock::{CaptureSink, NonBlockingLoggerBuilder};
+
+#[test]
+fn lines_without_newline_run_into_each_other() {
+    let bytes = BytesSink::default();
+    let logger = NonBlockingLoggerBuilder::new()
+        .without_timestamps()
+        .with_colors(false)
+        .with_append_newline(false)
+        .add_sink(bytes.clone())
+        .build()
+        .unwrap();
+
+    // Each message ends with its own record separator
+    for message in ["first", "second"] {
+        log(&logger, Level::Info, &format!("{}\x1e", message));
+    }
+    logger.flush();
+
+    assert_eq!(bytes.bytes(), b"INFO  first\x1eINFO  second\x1e");
+}
+
+#[test]
+fn large_message_threshold_doesnt_change_the_output() {
+    let output = |threshold| {
+        let bytes = BytesSink::default();
+        let logger = NonBlockingLoggerBuilder::new()
+            .without_timestamps()
+            .with_large_message_threshold(threshold)
+            .add_sink(bytes.clone())
+            .with_capture(&CaptureSink::new())
+            .build()
+            .unwrap();
+
+        // Lines of 63, 64 and 65 bytes, around a threshold of 64
+        for len in [55, 56, 57, 55, 57, 56] {
+            log(&logger, Level::Info, &"x".repeat(len));
+        }
+        logger.flush();
+
+        bytes.bytes()
+    };
+
+    let expected = output(usize::MAX);
+    assert_eq!(expected.split(|&byte| byte == b'\n').count(), 7);
+    assert_eq!(output(64), expected);
+    assert_eq!(output(0), expected);
+}
+
+#[test]
+fn log_bytes_writes_the_payload_as_is() {
+    let bytes = BytesSink::default();
+    let logger = NonBlockingLoggerBuilder::new()
+        .with_capture(&CaptureSink::new())
+        .add_sink(bytes.clone())
+        .build()
+        .unwrap();
+
+    // Length-prefixed frame with a payload which isn't UTF-8
+    assert!(logger.log_bytes(Level::Info, [0, 0, 0, 2, 0xff, 0xfe]));
+    logger.flush();
+
+    assert_eq!(bytes.bytes(), [0, 0, 0, 2, 0xff, 0xfe]);
+}